@@ -0,0 +1,132 @@
+// Copyright © SixtyFPS GmbH <info@slint.dev>
+// SPDX-License-Identifier: MIT
+
+use std::path::{Path, PathBuf};
+
+use crate::{player, save_png, video_frame_to_pixel_buffer, PixelBufferPool};
+
+/// How to invoke [`BatchThumbnails`].
+pub const USAGE: &str = "Usage: ffmpeg <input> (--thumbnails <seconds>,… | --interval <seconds>) \
+                         [--out <dir>] [--height <pixels>]";
+
+/// Writes PNG thumbnails of an input without opening a window, for scripts that make contact
+/// sheets and the like, at listed positions or at an interval over the whole input. See
+/// [`USAGE`] for the arguments.
+#[derive(Debug)]
+pub struct BatchThumbnails {
+    input: PathBuf,
+    positions: Positions,
+    out_dir: PathBuf,
+    /// Scales the thumbnails to this many pixels high, or leaves them at the video's size.
+    height: Option<u32>,
+}
+
+#[derive(Debug)]
+enum Positions {
+    /// In seconds.
+    List(Vec<f64>),
+    /// Every this many seconds, from the start to the end of the input.
+    Interval(f64),
+}
+
+impl BatchThumbnails {
+    /// Parses the command line arguments after the program name, or returns `Ok(None)` if they
+    /// don't ask for thumbnails.
+    pub fn from_args(args: &[String]) -> Result<Option<Self>, anyhow::Error> {
+        if !args.iter().any(|arg| arg == "--thumbnails" || arg == "--interval") {
+            return Ok(None);
+        }
+
+        let mut input = None;
+        let mut positions = None;
+        let mut out_dir = PathBuf::from(".");
+        let mut height = None;
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| anyhow::anyhow!("{} needs a value", arg));
+            match arg.as_str() {
+                "--thumbnails" => {
+                    let list = value()?
+                        .split(',')
+                        .map(|position| parse_seconds(position.trim()))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    positions = Some(Positions::List(list));
+                }
+                "--interval" => {
+                    let interval = parse_seconds(value()?)?;
+                    if interval <= 0. {
+                        anyhow::bail!("The interval has to be longer than 0 seconds");
+                    }
+                    positions = Some(Positions::Interval(interval));
+                }
+                "--out" => out_dir = PathBuf::from(value()?),
+                "--height" => {
+                    height = Some(
+                        value()?
+                            .parse()
+                            .map_err(|_| anyhow::anyhow!("--height takes a number of pixels"))?,
+                    )
+                }
+                option if option.starts_with("--") => anyhow::bail!("Unknown option {}", option),
+                path if input.is_none() => input = Some(PathBuf::from(path)),
+                path => anyhow::bail!("Only one input can be given, not also {}", path),
+            }
+        }
+
+        Ok(Some(Self {
+            input: input.ok_or_else(|| anyhow::anyhow!("No input given"))?,
+            positions: positions.unwrap(),
+            out_dir,
+            height,
+        }))
+    }
+
+    /// Writes the thumbnails, named after the input and their position, like
+    /// `movie-30.000s.png`. Goes on with the others when one can't be decoded, and returns
+    /// whether all of them were written.
+    pub fn run(&self) -> Result<bool, anyhow::Error> {
+        let mut extractor = player::FrameExtractor::open(&self.input, None)?;
+        let positions = match &self.positions {
+            Positions::List(positions) => positions.clone(),
+            Positions::Interval(interval) => {
+                let duration = extractor.duration().ok_or_else(|| {
+                    anyhow::anyhow!("The duration of the input is unknown, list the positions")
+                })?;
+                (0..).map(|number| number as f64 * interval).take_while(|&p| p < duration).collect()
+            }
+        };
+        std::fs::create_dir_all(&self.out_dir)?;
+
+        let stem = self.input.file_stem().map(|stem| stem.to_string_lossy()).unwrap_or_default();
+        let mut all_written = true;
+        for position in positions {
+            let path = self.out_dir.join(format!("{}-{:.3}s.png", stem, position));
+            if let Err(error) = write_thumbnail(&mut extractor, position, self.height, &path) {
+                eprintln!("Could not write the thumbnail at {:.3}s: {:#}", position, error);
+                all_written = false;
+            } else {
+                println!("{}", path.display());
+            }
+        }
+        Ok(all_written)
+    }
+}
+
+fn write_thumbnail(
+    extractor: &mut player::FrameExtractor,
+    position: f64,
+    height: Option<u32>,
+    path: &Path,
+) -> Result<(), anyhow::Error> {
+    let (_, frame) = extractor.frame_at(position, true)?;
+    let rgb_frame = extractor.to_rgb(&frame, height)?;
+    save_png(path, &video_frame_to_pixel_buffer(&rgb_frame, &PixelBufferPool::default()))
+}
+
+/// Parses a position or an interval, in seconds.
+fn parse_seconds(text: &str) -> Result<f64, anyhow::Error> {
+    text.parse::<f64>()
+        .ok()
+        .filter(|seconds| seconds.is_finite() && *seconds >= 0.)
+        .ok_or_else(|| anyhow::anyhow!("{:?} is not a number of seconds", text))
+}
@@ -0,0 +1,408 @@
+// Copyright © SixtyFPS GmbH <info@slint.dev>
+// SPDX-License-Identifier: MIT
+
+//! Converts decoded YUV frames to RGB with OpenGL instead of with swscale. The decoder's planes
+//! are uploaded as textures as they are, and a shader converts them into an RGBA texture that
+//! Slint shows directly. This skips the software scaler and the RGB copy of every frame.
+
+use std::num::NonZeroU32;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+
+use ffmpeg_next::format::Pixel;
+use glow::HasContext;
+
+/// A decoded frame waiting to be converted, with the clockwise rotation to show it at.
+struct PendingFrame {
+    frame: ffmpeg_next::util::frame::Video,
+    rotation: u32,
+}
+
+/// Hands frames from the video playback thread to the renderer.
+#[derive(Clone, Default)]
+pub struct FrameQueue {
+    pending: Arc<Mutex<Option<PendingFrame>>>,
+    /// Set while Slint renders with OpenGL, which the conversion needs.
+    available: Arc<AtomicBool>,
+}
+
+impl FrameQueue {
+    /// Queues `frame` for conversion, replacing a frame that wasn't converted yet. Only a
+    /// reference to the frame's buffers is taken. Returns false if the frame has to be
+    /// converted in software instead, for formats other than 8-bit 4:2:0 or without OpenGL.
+    pub fn push(&self, frame: &ffmpeg_next::util::frame::Video, rotation: u32) -> bool {
+        if !self.available.load(Ordering::Relaxed)
+            || !matches!(frame.format(), Pixel::NV12 | Pixel::YUV420P | Pixel::YUVJ420P)
+        {
+            return false;
+        }
+
+        let mut reference = ffmpeg_next::util::frame::Video::empty();
+        if unsafe { ffmpeg_next::ffi::av_frame_ref(reference.as_mut_ptr(), frame.as_ptr()) } < 0 {
+            return false;
+        }
+        *self.pending.lock().unwrap() = Some(PendingFrame { frame: reference, rotation });
+        true
+    }
+}
+
+/// Converts the frames pushed to the returned queue while rendering `app`, and shows them as
+/// its video frame.
+pub fn install(app: &crate::App) -> FrameQueue {
+    let queue = FrameQueue::default();
+
+    let app_weak = app.as_weak();
+    let renderer_queue = queue.clone();
+    let mut renderer = None;
+    // Without a renderer that supports the notifier, frames are converted in software.
+    app.window()
+        .set_rendering_notifier(move |state, graphics_api| match state {
+            slint::RenderingState::RenderingSetup => {
+                let slint::GraphicsAPI::NativeOpenGL { get_proc_address } = graphics_api else {
+                    return;
+                };
+                let gl = unsafe {
+                    glow::Context::from_loader_function_cstr(|name| get_proc_address(name))
+                };
+                // If this fails, frames keep being converted in software.
+                if let Ok(new_renderer) = unsafe { YuvRenderer::new(gl) } {
+                    renderer = Some(new_renderer);
+                    renderer_queue.available.store(true, Ordering::Relaxed);
+                }
+            }
+            slint::RenderingState::BeforeRendering => {
+                let (Some(renderer), Some(app)) = (renderer.as_mut(), app_weak.upgrade()) else {
+                    return;
+                };
+                let Some(pending) = renderer_queue.pending.lock().unwrap().take() else { return };
+                if let Some(image) = unsafe { renderer.render(&pending) } {
+                    app.set_video_frame(image);
+                }
+            }
+            slint::RenderingState::RenderingTeardown => {
+                renderer_queue.available.store(false, Ordering::Relaxed);
+                renderer = None;
+            }
+            _ => {}
+        })
+        .ok();
+
+    queue
+}
+
+const VERTEX_SHADER: &str = r#"
+in vec2 position;
+in vec2 tex_coord;
+out vec2 frag_tex_coord;
+
+void main() {
+    frag_tex_coord = tex_coord;
+    gl_Position = vec4(position, 0.0, 1.0);
+}
+"#;
+
+const FRAGMENT_SHADER: &str = r#"
+precision mediump float;
+in vec2 frag_tex_coord;
+out vec4 color;
+
+uniform sampler2D y_plane;
+// Both chroma components for NV12, only U for YUV420P.
+uniform sampler2D u_plane;
+uniform sampler2D v_plane;
+uniform bool interleaved_chroma;
+uniform mat3 yuv_to_rgb;
+uniform vec3 yuv_offset;
+
+void main() {
+    float y = texture(y_plane, frag_tex_coord).r;
+    vec2 chroma = interleaved_chroma
+        ? texture(u_plane, frag_tex_coord).rg
+        : vec2(texture(u_plane, frag_tex_coord).r, texture(v_plane, frag_tex_coord).r);
+    color = vec4(yuv_to_rgb * (vec3(y, chroma) - yuv_offset), 1.0);
+}
+"#;
+
+struct YuvRenderer {
+    gl: glow::Context,
+    program: glow::Program,
+    vertex_array: glow::VertexArray,
+    vertex_buffer: glow::Buffer,
+    plane_textures: [glow::Texture; 3],
+    framebuffer: glow::Framebuffer,
+    /// The RGBA texture Slint shows, and its size.
+    output: Option<(glow::Texture, u32, u32)>,
+}
+
+impl YuvRenderer {
+    unsafe fn new(gl: glow::Context) -> Result<Self, String> {
+        let version = if cfg!(target_arch = "wasm32") { "#version 300 es" } else { "#version 330" };
+
+        let program = gl.create_program()?;
+        let mut shaders = Vec::new();
+        for (shader_type, source) in
+            [(glow::VERTEX_SHADER, VERTEX_SHADER), (glow::FRAGMENT_SHADER, FRAGMENT_SHADER)]
+        {
+            let shader = gl.create_shader(shader_type)?;
+            gl.shader_source(shader, &format!("{}\n{}", version, source));
+            gl.compile_shader(shader);
+            if !gl.get_shader_compile_status(shader) {
+                return Err(gl.get_shader_info_log(shader));
+            }
+            gl.attach_shader(program, shader);
+            shaders.push(shader);
+        }
+        gl.link_program(program);
+        for shader in shaders {
+            gl.detach_shader(program, shader);
+            gl.delete_shader(shader);
+        }
+        if !gl.get_program_link_status(program) {
+            return Err(gl.get_program_info_log(program));
+        }
+
+        let vertex_array = gl.create_vertex_array()?;
+        let vertex_buffer = gl.create_buffer()?;
+        gl.bind_vertex_array(Some(vertex_array));
+        gl.bind_buffer(glow::ARRAY_BUFFER, Some(vertex_buffer));
+        // Interleaved positions and texture coordinates
+        let stride = 4 * std::mem::size_of::<f32>() as i32;
+        for (name, offset) in [("position", 0), ("tex_coord", 2 * std::mem::size_of::<f32>())] {
+            let location = gl
+                .get_attrib_location(program, name)
+                .ok_or_else(|| format!("missing attribute {}", name))?;
+            gl.enable_vertex_attrib_array(location);
+            gl.vertex_attrib_pointer_f32(location, 2, glow::FLOAT, false, stride, offset as i32);
+        }
+        gl.bind_vertex_array(None);
+        gl.bind_buffer(glow::ARRAY_BUFFER, None);
+
+        let mut plane_textures = Vec::new();
+        for _ in 0..3 {
+            let texture = gl.create_texture()?;
+            gl.bind_texture(glow::TEXTURE_2D, Some(texture));
+            set_texture_parameters(&gl);
+            plane_textures.push(texture);
+        }
+        gl.bind_texture(glow::TEXTURE_2D, None);
+
+        let framebuffer = gl.create_framebuffer()?;
+
+        Ok(Self {
+            plane_textures: plane_textures.try_into().unwrap(),
+            gl,
+            program,
+            vertex_array,
+            vertex_buffer,
+            framebuffer,
+            output: None,
+        })
+    }
+
+    /// Converts `pending` into the output texture, and returns it as an image for Slint.
+    unsafe fn render(&mut self, pending: &PendingFrame) -> Option<slint::Image> {
+        let gl = &self.gl;
+        let frame = &pending.frame;
+        let (width, height) = (frame.width(), frame.height());
+        let (output_width, output_height) =
+            if pending.rotation % 180 == 0 { (width, height) } else { (height, width) };
+
+        // Leave the state Slint relies on as it was.
+        let saved_framebuffer = gl.get_parameter_i32(glow::DRAW_FRAMEBUFFER_BINDING);
+        let mut saved_viewport = [0; 4];
+        gl.get_parameter_i32_slice(glow::VIEWPORT, &mut saved_viewport);
+        let saved_active_texture = gl.get_parameter_i32(glow::ACTIVE_TEXTURE);
+
+        if self.output.map_or(true, |(_, w, h)| (w, h) != (output_width, output_height)) {
+            if let Some((texture, _, _)) = self.output.take() {
+                gl.delete_texture(texture);
+            }
+            let texture = gl.create_texture().ok()?;
+            gl.bind_texture(glow::TEXTURE_2D, Some(texture));
+            set_texture_parameters(gl);
+            gl.tex_image_2d(
+                glow::TEXTURE_2D,
+                0,
+                glow::RGBA8 as i32,
+                output_width as i32,
+                output_height as i32,
+                0,
+                glow::RGBA,
+                glow::UNSIGNED_BYTE,
+                None,
+            );
+            self.output = Some((texture, output_width, output_height));
+        }
+        let (output_texture, _, _) = self.output.unwrap();
+
+        // NV12 has a full size luma plane and a half size plane of interleaved chroma, YUV420P
+        // has separate half size U and V planes.
+        let interleaved_chroma = frame.format() == Pixel::NV12;
+        let chroma_size = ((width + 1) / 2, (height + 1) / 2);
+        let planes: &[(usize, u32, u32, u32)] = if interleaved_chroma {
+            &[(0, width, height, glow::RED), (1, chroma_size.0, chroma_size.1, glow::RG)]
+        } else {
+            &[
+                (0, width, height, glow::RED),
+                (1, chroma_size.0, chroma_size.1, glow::RED),
+                (2, chroma_size.0, chroma_size.1, glow::RED),
+            ]
+        };
+        gl.pixel_store_i32(glow::UNPACK_ALIGNMENT, 1);
+        for &(plane, plane_width, plane_height, format) in planes {
+            let bytes_per_pixel = if format == glow::RG { 2 } else { 1 };
+            gl.active_texture(glow::TEXTURE0 + plane as u32);
+            gl.bind_texture(glow::TEXTURE_2D, Some(self.plane_textures[plane]));
+            gl.pixel_store_i32(
+                glow::UNPACK_ROW_LENGTH,
+                (frame.stride(plane) / bytes_per_pixel) as i32,
+            );
+            gl.tex_image_2d(
+                glow::TEXTURE_2D,
+                0,
+                if format == glow::RG { glow::RG8 } else { glow::R8 } as i32,
+                plane_width as i32,
+                plane_height as i32,
+                0,
+                format,
+                glow::UNSIGNED_BYTE,
+                Some(frame.data(plane)),
+            );
+        }
+        gl.pixel_store_i32(glow::UNPACK_ROW_LENGTH, 0);
+
+        gl.bind_framebuffer(glow::FRAMEBUFFER, Some(self.framebuffer));
+        gl.framebuffer_texture_2d(
+            glow::FRAMEBUFFER,
+            glow::COLOR_ATTACHMENT0,
+            glow::TEXTURE_2D,
+            Some(output_texture),
+            0,
+        );
+        gl.viewport(0, 0, output_width as i32, output_height as i32);
+
+        gl.use_program(Some(self.program));
+        for (plane, name) in ["y_plane", "u_plane", "v_plane"].into_iter().enumerate() {
+            gl.uniform_1_i32(gl.get_uniform_location(self.program, name).as_ref(), plane as i32);
+        }
+        gl.uniform_1_i32(
+            gl.get_uniform_location(self.program, "interleaved_chroma").as_ref(),
+            interleaved_chroma as i32,
+        );
+        let (matrix, offset) = yuv_to_rgb(frame);
+        gl.uniform_matrix_3_f32_slice(
+            gl.get_uniform_location(self.program, "yuv_to_rgb").as_ref(),
+            false,
+            &matrix,
+        );
+        gl.uniform_3_f32_slice(
+            gl.get_uniform_location(self.program, "yuv_offset").as_ref(),
+            &offset,
+        );
+
+        // The first row of the output texture is the top of the image, as Slint expects, so
+        // the output's top maps to the framebuffer's bottom.
+        let vertices: Vec<f32> = [(0., 0.), (1., 0.), (0., 1.), (1., 1.)]
+            .into_iter()
+            .flat_map(|(u, v): (f32, f32)| {
+                let (s, t) = match pending.rotation {
+                    90 => (v, 1. - u),
+                    180 => (1. - u, 1. - v),
+                    270 => (1. - v, u),
+                    _ => (u, v),
+                };
+                [u * 2. - 1., v * 2. - 1., s, t]
+            })
+            .collect();
+        gl.bind_vertex_array(Some(self.vertex_array));
+        gl.bind_buffer(glow::ARRAY_BUFFER, Some(self.vertex_buffer));
+        gl.buffer_data_u8_slice(
+            glow::ARRAY_BUFFER,
+            bytemuck::cast_slice(&vertices),
+            glow::STREAM_DRAW,
+        );
+        gl.draw_arrays(glow::TRIANGLE_STRIP, 0, 4);
+
+        gl.bind_buffer(glow::ARRAY_BUFFER, None);
+        gl.bind_vertex_array(None);
+        gl.use_program(None);
+        gl.bind_texture(glow::TEXTURE_2D, None);
+        gl.active_texture(saved_active_texture as u32);
+        gl.bind_framebuffer(
+            glow::FRAMEBUFFER,
+            NonZeroU32::new(saved_framebuffer as u32).map(glow::NativeFramebuffer),
+        );
+        gl.viewport(saved_viewport[0], saved_viewport[1], saved_viewport[2], saved_viewport[3]);
+
+        Some(
+            slint::BorrowedOpenGLTextureBuilder::new_gl_2d_rgba_texture(
+                output_texture.0,
+                (output_width, output_height).into(),
+            )
+            .build(),
+        )
+    }
+}
+
+impl Drop for YuvRenderer {
+    fn drop(&mut self) {
+        unsafe {
+            self.gl.delete_program(self.program);
+            self.gl.delete_vertex_array(self.vertex_array);
+            self.gl.delete_buffer(self.vertex_buffer);
+            for texture in self.plane_textures {
+                self.gl.delete_texture(texture);
+            }
+            if let Some((texture, _, _)) = self.output {
+                self.gl.delete_texture(texture);
+            }
+            self.gl.delete_framebuffer(self.framebuffer);
+        }
+    }
+}
+
+unsafe fn set_texture_parameters(gl: &glow::Context) {
+    gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_MIN_FILTER, glow::LINEAR as i32);
+    gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_MAG_FILTER, glow::LINEAR as i32);
+    gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_WRAP_S, glow::CLAMP_TO_EDGE as i32);
+    gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_WRAP_T, glow::CLAMP_TO_EDGE as i32);
+}
+
+/// The column-major matrix and the offset that convert the YUV components of `frame`, as read
+/// from the textures, to RGB. Like the software path, unspecified matrices of HD frames are
+/// taken to be BT.709 and those of smaller frames BT.601.
+fn yuv_to_rgb(frame: &ffmpeg_next::util::frame::Video) -> ([f32; 9], [f32; 3]) {
+    use ffmpeg_next::color::Space;
+
+    // The luma weights of red and blue
+    let (kr, kb) = match frame.color_space() {
+        Space::BT709 => (0.2126, 0.0722),
+        Space::Unspecified if frame.height() >= 720 => (0.2126, 0.0722),
+        Space::BT2020NCL | Space::BT2020CL => (0.2627, 0.0593),
+        _ => (0.299, 0.114),
+    };
+    let kg = 1. - kr - kb;
+
+    let full_range =
+        frame.color_range() == ffmpeg_next::color::Range::JPEG || frame.format() == Pixel::YUVJ420P;
+    let (luma_scale, chroma_scale, luma_offset) =
+        if full_range { (1., 1., 0.) } else { (255. / 219., 255. / 224., 16. / 255.) };
+
+    let matrix = [
+        // Y
+        luma_scale,
+        luma_scale,
+        luma_scale,
+        // U
+        0.,
+        -chroma_scale * 2. * kb * (1. - kb) / kg,
+        chroma_scale * 2. * (1. - kb),
+        // V
+        chroma_scale * 2. * (1. - kr),
+        -chroma_scale * 2. * kr * (1. - kr) / kg,
+        0.,
+    ];
+    (matrix, [luma_offset, 128. / 255., 128. / 255.])
+}
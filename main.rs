@@ -3,41 +3,1696 @@
 
 slint::include_modules!();
 
+use std::{
+    cell::{Cell, RefCell},
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
 use ffmpeg_next::format::Pixel;
+use slint::winit_030::{winit, EventResult, WinitWindowAccessor};
+use slint::Model;
 
+mod batch_thumbnails;
+#[cfg(feature = "gpu-yuv")]
+mod gpu_yuv;
+mod media_controls;
 mod player;
+mod playlist;
+mod resume;
+mod settings;
+mod subtitles;
+mod transcode;
+
+/// How long to wait for the first decoded video frame before reporting the input as broken
+/// rather than slow.
+const FIRST_FRAME_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// How often the settings are saved, if they changed.
+const SETTINGS_SAVE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// How much of a restored window has to be on a monitor, in physical pixels both across and
+/// down, for it to count as visible. Less would be hard to find and drag back.
+const MIN_VISIBLE_WINDOW_PART: i32 = 100;
+
+/// How often the playback statistics are updated while they are shown.
+const PLAYBACK_STATS_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// How often the waveform shown for inputs without video is redrawn while playing.
+const WAVEFORM_INTERVAL: std::time::Duration = std::time::Duration::from_millis(33);
+
+/// The height of the drawn waveform, in pixels. It is as wide as there are audio peaks, and
+/// scaled to the window like video.
+const WAVEFORM_HEIGHT: u32 = 200;
+
+/// How far back the shown frame and underrun rates reach, evening out single slow frames.
+const RATE_WINDOW: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// How often the playback position is saved, to resume from it even if the player doesn't
+/// shut down cleanly.
+const RESUME_SAVE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// How often the operating system's media controls are told the playback state and position.
+const MEDIA_CONTROLS_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// How long the controls stay revealed in fullscreen after the pointer stopped moving, before
+/// they start fading out.
+const POINTER_REVEAL_DURATION: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// How far into a chapter, in seconds, going to the previous chapter restarts the current one
+/// instead.
+const PREVIOUS_CHAPTER_GRACE: f32 = 2.;
+
+/// How close, in seconds, letting go of the seek bar has to be to a keyframe to play from the
+/// keyframe instead, which starts right away without decoding up to the position.
+const KEYFRAME_SNAP_DISTANCE: f64 = 0.5;
+
+/// Asks where to save an export, suggesting `file_name` and filtering by `(description,
+/// extension)`, then runs `export` on a background thread while the progress overlay shows.
+/// `what` names the exported thing in error messages.
+fn start_export(
+    app: &App,
+    cancelled: &Arc<AtomicBool>,
+    file_name: String,
+    (description, extension): (&str, &str),
+    what: &'static str,
+    export: impl FnOnce(&Path, &AtomicBool, &mut dyn FnMut(f64)) -> Result<bool, anyhow::Error>
+        + Send
+        + 'static,
+) {
+    cancelled.store(false, Ordering::Relaxed);
+    app.set_exporting(true);
+    app.set_export_progress(-1.);
+    let app_weak = app.as_weak();
+    let cancelled = cancelled.clone();
+    let (description, extension) = (description.to_owned(), extension.to_owned());
+    std::thread::Builder::new()
+        .name("export thread".into())
+        .spawn(move || {
+            let result = smol::block_on(
+                rfd::AsyncFileDialog::new()
+                    .add_filter(description, &[extension])
+                    .set_file_name(file_name)
+                    .save_file(),
+            )
+            .map(|file| {
+                export(file.path(), &cancelled, &mut |progress| {
+                    app_weak
+                        .upgrade_in_event_loop(move |app| app.set_export_progress(progress as f32))
+                        .ok();
+                })
+            });
+            app_weak
+                .upgrade_in_event_loop(move |app| {
+                    app.set_exporting(false);
+                    // Nothing to report when the dialog or the export was cancelled.
+                    if let Some(Err(err)) = result {
+                        app.set_status_text(format!("Could not export the {what}: {err}").into());
+                    }
+                })
+                .ok();
+        })
+        .unwrap();
+}
+
+/// How long notes about ignored input, unlike errors, are shown in the status text.
+const STATUS_NOTE_DURATION: std::time::Duration = std::time::Duration::from_secs(3);
+
+/// The extensions of the files that dropping onto the window plays. Others, like documents, are
+/// rejected rather than failing to open.
+const MEDIA_EXTENSIONS: &[&str] = &[
+    "3gp", "aac", "avi", "flac", "flv", "m2ts", "m4a", "m4v", "mka", "mkv", "mov", "mp3", "mp4",
+    "mpeg", "mpg", "mts", "oga", "ogg", "ogv", "opus", "ts", "wav", "webm", "wma", "wmv",
+];
+
+/// Environment variables that set how network inputs are requested, see
+/// [`network_options_from_env`].
+const USER_AGENT_VARIABLE: &str = "VIDEO_PLAYER_USER_AGENT";
+const HTTP_HEADERS_VARIABLE: &str = "VIDEO_PLAYER_HTTP_HEADERS";
+const NETWORK_TIMEOUT_VARIABLE: &str = "VIDEO_PLAYER_NETWORK_TIMEOUT";
+const PREBUFFER_VARIABLE: &str = "VIDEO_PLAYER_PREBUFFER";
+/// Environment variable that sets how far the seek keys jump, in seconds.
+const SEEK_STEP_VARIABLE: &str = "VIDEO_PLAYER_SEEK_STEP";
+
+/// How far the seek keys jump, unless set with [`SEEK_STEP_VARIABLE`].
+const DEFAULT_SEEK_STEP: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Played at startup when the command line names no input.
+const DEFAULT_INPUT: &str =
+    "http://commondatastorage.googleapis.com/gtv-videos-bucket/sample/TearsOfSteel.mp4";
+
+/// How to name what to play on the command line, see [`StartupArgs`].
+const USAGE: &str = "Usage: ffmpeg [<input>…] [--start-at <seconds>] [--paused] [--require-audio]";
+
+/// The frame the UI shows, at the resolution it was converted at. Keeping it here keeps its
+/// buffer out of the [`PixelBufferPool`] until the next frame replaces it.
+type LatestFrame = Arc<Mutex<Option<PooledPixelBuffer>>>;
+
+/// The external subtitles shown over the video, if any were opened for it.
+type LoadedSubtitles = Arc<Mutex<Option<subtitles::Subtitles>>>;
+
+/// How video frames are converted to RGB for display, as picked in the UI.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct ConversionSettings {
+    /// The largest height frames are converted at, or 0 for no limit.
+    max_height: u32,
+    scaling: ScalingAlgorithm,
+}
+
+/// The filter frames are scaled with at full quality.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum ScalingAlgorithm {
+    FastBilinear,
+    #[default]
+    Bilinear,
+    Bicubic,
+    /// The sharpest, and the most expensive.
+    Lanczos,
+}
+
+impl ScalingAlgorithm {
+    /// In the order they are offered in the UI.
+    const ALL: [Self; 4] = [Self::Bilinear, Self::FastBilinear, Self::Bicubic, Self::Lanczos];
+
+    fn flags(self) -> ffmpeg_next::software::scaling::Flags {
+        match self {
+            Self::FastBilinear => ffmpeg_next::software::scaling::Flags::FAST_BILINEAR,
+            Self::Bilinear => ffmpeg_next::software::scaling::Flags::BILINEAR,
+            Self::Bicubic => ffmpeg_next::software::scaling::Flags::BICUBIC,
+            Self::Lanczos => ffmpeg_next::software::scaling::Flags::LANCZOS,
+        }
+    }
+}
+
+/// Runs what the command line arguments ask for if it needs no window, and returns the exit code
+/// of the program then.
+fn run_headless(args: &[String]) -> Option<i32> {
+    let result = match batch_thumbnails::BatchThumbnails::from_args(args) {
+        Ok(Some(batch)) => batch.run(),
+        Ok(None) => match transcode::Transcode::from_args(args) {
+            Ok(Some(transcode)) => transcode.run(),
+            Ok(None) => return None,
+            Err(error) => return Some(usage_error(&error, transcode::USAGE)),
+        },
+        Err(error) => return Some(usage_error(&error, batch_thumbnails::USAGE)),
+    };
+    Some(match result {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(error) => {
+            eprintln!("{:#}", error);
+            1
+        }
+    })
+}
+
+/// What to play at startup, from the command line arguments after the program name, like
+/// `movie.mkv --start-at 90`. See [`USAGE`].
+#[derive(Debug, Default)]
+struct StartupArgs {
+    /// Files and URLs, played one after the other.
+    inputs: Vec<PathBuf>,
+    /// Where to start playing the first input, in seconds, instead of where it was left.
+    start_at: Option<f64>,
+    /// Opens the inputs paused on their first frame, see [`player::PlayerOptions::start_paused`].
+    paused: bool,
+    /// Refuses to play the inputs without an audio output device, see
+    /// [`player::MissingAudioDevice::Fail`].
+    require_audio: bool,
+}
+
+impl StartupArgs {
+    fn from_args(args: &[String]) -> Result<Self, anyhow::Error> {
+        let mut startup_args = Self::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--start-at" => {
+                    let seconds =
+                        args.next().ok_or_else(|| anyhow::anyhow!("{} needs a value", arg))?;
+                    startup_args.start_at = Some(
+                        seconds
+                            .parse::<f64>()
+                            .ok()
+                            .filter(|seconds| seconds.is_finite() && *seconds >= 0.)
+                            .ok_or_else(|| {
+                                anyhow::anyhow!("{:?} is not a number of seconds", seconds)
+                            })?,
+                    );
+                }
+                "--paused" => startup_args.paused = true,
+                "--require-audio" => startup_args.require_audio = true,
+                option if option.starts_with("--") => anyhow::bail!("Unknown option {}", option),
+                input => startup_args.inputs.push(PathBuf::from(input)),
+            }
+        }
+        Ok(startup_args)
+    }
+
+    /// The playlist of the inputs, and the files among them that don't exist, which are left out
+    /// of it. If none of the inputs exist, the first is played anyway, for the player to report
+    /// why it can't be opened.
+    fn playlist(&self) -> (playlist::Playlist, Vec<&Path>) {
+        let (found, missing): (Vec<_>, Vec<_>) = self.inputs.iter().partition(|input| {
+            input.to_str().is_some_and(|input| input.contains("://")) || input.exists()
+        });
+        let (inputs, missing) = if found.is_empty() {
+            (self.inputs.iter().take(1).collect(), Vec::new())
+        } else {
+            (found, missing)
+        };
+
+        let mut inputs = inputs.into_iter().cloned();
+        let mut playlist =
+            playlist::Playlist::new(inputs.next().unwrap_or_else(|| DEFAULT_INPUT.into()));
+        for input in inputs {
+            playlist.append(input);
+        }
+        (playlist, missing.into_iter().map(PathBuf::as_path).collect())
+    }
+}
+
+fn usage_error(error: &anyhow::Error, usage: &str) -> i32 {
+    eprintln!("{:#}", error);
+    eprintln!("{}", usage);
+    2
+}
 
 fn main() {
+    // Thumbnails and transcoding for scripts are done without opening a window.
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    if let Some(exit_code) = run_headless(&args) {
+        std::process::exit(exit_code);
+    }
+    let startup_args = StartupArgs::from_args(&args).unwrap_or_else(|error| {
+        std::process::exit(usage_error(&error, USAGE));
+    });
+
     let app = App::new().unwrap();
+    app.set_start_paused(startup_args.paused);
 
-    let mut to_rgba_rescaler: Option<Rescaler> = None;
+    let first_frame_timer = slint::Timer::default();
 
-    let mut player = player::Player::start(
-        "http://commondatastorage.googleapis.com/gtv-videos-bucket/sample/TearsOfSteel.mp4".into(),
+    // The most recently displayed frame, for screenshots.
+    let latest_frame: LatestFrame = Default::default();
+    let pixel_buffers = PixelBufferPool::default();
+    let conversion_settings = Arc::new(Mutex::new(ConversionSettings::default()));
+    let loaded_subtitles: LoadedSubtitles = Default::default();
+    #[cfg(feature = "gpu-yuv")]
+    let gpu_frames = gpu_yuv::install(&app);
+    let network = network_options_from_env();
+    app.set_audio_devices(
+        Rc::new(slint::VecModel::from(
+            std::iter::once("Default output".to_owned())
+                .chain(player::output_device_names())
+                .map(Into::into)
+                .collect::<Vec<slint::SharedString>>(),
+        ))
+        .into(),
+    );
+    // After listing the output devices, as the saved one is looked up among them.
+    let saved_settings = Rc::new(RefCell::new(settings::Settings::load()));
+    apply_settings(&app, &saved_settings.borrow());
+
+    let (startup_playlist, missing_inputs) = startup_args.playlist();
+    let playlist = Rc::new(RefCell::new(startup_playlist));
+    show_playlist(&app, &playlist.borrow());
+
+    // Whether the input being played showed a frame yet.
+    let first_frame_received = Arc::new(AtomicBool::new(false));
+    let player = Rc::new(RefCell::new(
+        start_player(
+            &app,
+            &first_frame_timer,
+            latest_frame.clone(),
+            pixel_buffers.clone(),
+            conversion_settings.clone(),
+            loaded_subtitles.clone(),
+            #[cfg(feature = "gpu-yuv")]
+            gpu_frames.clone(),
+            network.clone(),
+            if startup_args.require_audio {
+                player::MissingAudioDevice::Fail
+            } else {
+                player::MissingAudioDevice::PlayMuted
+            },
+            first_frame_received.clone(),
+            playlist.borrow().current().to_owned(),
+        )
+        .unwrap(),
+    ));
+
+    player.borrow_mut().set_volume(app.get_volume());
+    player.borrow_mut().set_speed(app.get_speed());
+    player.borrow_mut().set_smoothing_mode(smoothing_mode(app.get_smooth_motion()));
+    player.borrow_mut().set_adaptive_quality(adaptive_quality(app.get_adaptive_quality()));
+
+    // Saved while the player runs, so that they survive it crashing.
+    let settings_timer = slint::Timer::default();
+    settings_timer.start(slint::TimerMode::Repeated, SETTINGS_SAVE_INTERVAL, {
+        let app_weak = app.as_weak();
+        let saved_settings = saved_settings.clone();
+        move || save_settings(&app_weak.unwrap(), &mut saved_settings.borrow_mut())
+    });
+
+    let resume_positions = Rc::new(RefCell::new(resume::ResumePositions::load()));
+    resume_playback(&mut player.borrow_mut(), &resume_positions.borrow());
+    if let Some(start_at) = startup_args.start_at {
+        player.borrow_mut().seek(start_at, player::SeekMode::Accurate);
+    }
+    // Replaces the loading message, until the first frame of a played input clears it, like
+    // for dropped files.
+    if !missing_inputs.is_empty() {
+        let names = missing_inputs
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        app.set_status_text(format!("Not found: {}", names).into());
+    }
+    // Whether the input was played to the end, which leaves nothing to resume.
+    let played_to_end = Rc::new(Cell::new(false));
+
+    let resume_timer = slint::Timer::default();
+    resume_timer.start(slint::TimerMode::Repeated, RESUME_SAVE_INTERVAL, {
+        let app_weak = app.as_weak();
+        let player = player.clone();
+        let resume_positions = resume_positions.clone();
+        let played_to_end = played_to_end.clone();
+        move || {
+            if !played_to_end.get() {
+                let mut resume_positions = resume_positions.borrow_mut();
+                remember_position(&app_weak.unwrap(), &player.borrow(), &mut resume_positions);
+                // Resuming is a convenience, so failing to save is not worth interrupting for.
+                resume_positions.save().ok();
+            }
+        }
+    });
+
+    // Set while playback is paused because the window is minimized, to resume it when the
+    // window is restored. Pausing or resuming by hand clears it, so that restoring the window
+    // doesn't override that.
+    let auto_paused = Rc::new(Cell::new(false));
+
+    app.on_toggle_pause_play({
+        let player = player.clone();
+        let played_to_end = played_to_end.clone();
+        let auto_paused = auto_paused.clone();
+        let app_weak = app.as_weak();
+        move || {
+            auto_paused.set(false);
+            let mut player = player.borrow_mut();
+            // Starts the input over once it ended.
+            if player.is_ended() {
+                played_to_end.set(false);
+            }
+            player.toggle_pause_playing();
+            app_weak.unwrap().set_ended(false);
+        }
+    });
+
+    app.on_toggle_mute({
+        let player = player.clone();
+        let app_weak = app.as_weak();
+        move || {
+            let mut player = player.borrow_mut();
+            let muted = !player.is_muted();
+            player.set_muted(muted);
+            app_weak.unwrap().set_muted(muted);
+        }
+    });
+
+    app.on_volume_changed({
+        let player = player.clone();
+        move |volume| player.borrow_mut().set_volume(volume)
+    });
+
+    app.on_seek({
+        let player = player.clone();
+        let played_to_end = played_to_end.clone();
+        let app_weak = app.as_weak();
+        move |position| {
+            played_to_end.set(false);
+            app_weak.unwrap().set_ended(false);
+            // Holding a key seeks on every repeat, so favor quick updates over landing exactly
+            // on the position.
+            player.borrow_mut().seek(position as f64, player::SeekMode::Fast);
+        }
+    });
+
+    app.on_scrub_started({
+        let player = player.clone();
+        move || player.borrow_mut().begin_scrub()
+    });
+
+    app.on_scrubbed({
+        let player = player.clone();
+        move |position| player.borrow_mut().scrub_to(position as f64)
+    });
+
+    app.on_scrub_ended({
+        let player = player.clone();
+        let played_to_end = played_to_end.clone();
+        let app_weak = app.as_weak();
+        move |position| {
+            played_to_end.set(false);
+            app_weak.unwrap().set_ended(false);
+            // Playback continues where the seek bar was let go.
+            let mut player = player.borrow_mut();
+            let keyframes = player.keyframe_positions();
+            player.scrub_to(snap_to_keyframe(position as f64, keyframes.as_deref()));
+            player.end_scrub();
+        }
+    });
+
+    app.on_seek_by({
+        let app_weak = app.as_weak();
+        move |offset| seek_by(&app_weak.unwrap(), offset)
+    });
+
+    app.on_seek_to_fraction({
+        let app_weak = app.as_weak();
+        let note_timer = slint::Timer::default();
+        move |fraction| {
+            let app = app_weak.unwrap();
+            if app.get_duration() > 0. {
+                seek_by(&app, fraction * app.get_duration() - app.get_position());
+                return;
+            }
+            // Live streams have no duration to take a fraction of.
+            let note: slint::SharedString = "Can't jump within an input of unknown length".into();
+            app.set_status_text(note.clone());
+            let app_weak = app_weak.clone();
+            note_timer.start(slint::TimerMode::SingleShot, STATUS_NOTE_DURATION, move || {
+                let app = app_weak.unwrap();
+                // Unless replaced by another message meanwhile.
+                if app.get_status_text() == note {
+                    app.set_status_text(Default::default());
+                }
+            });
+        }
+    });
+
+    app.on_toggle_fullscreen({
+        let app_weak = app.as_weak();
+        move || {
+            let app = app_weak.unwrap();
+            let fullscreen = !app.window().is_fullscreen();
+            app.window().set_fullscreen(fullscreen);
+            app.set_fullscreen(fullscreen);
+            app.set_pointer_moved(false);
+        }
+    });
+
+    app.on_seek_hovered({
+        let player = player.clone();
+        let app_weak = app.as_weak();
+        let pixel_buffers = pixel_buffers.clone();
+        // Keeps the buffer of the preview shown out of the pool.
+        let shown_preview = Cell::new(None);
+        move |position| {
+            let preview = player
+                .borrow()
+                .thumbnail(position as f64)
+                .map(|frame| video_frame_to_pixel_buffer(&frame, &pixel_buffers));
+            let image = preview.as_ref().map(|buffer| slint::Image::from_rgb8((**buffer).clone()));
+            app_weak.unwrap().set_seek_preview(image.unwrap_or_default());
+            shown_preview.set(preview);
+        }
+    });
+
+    app.on_next_chapter({
+        let app_weak = app.as_weak();
+        move || {
+            let app = app_weak.unwrap();
+            let position = app.get_position();
+            // Past the chapter start that playback may have only just passed.
+            if let Some(start) =
+                app.get_chapter_starts().iter().find(|start| *start > position + 0.5)
+            {
+                seek_by(&app, start - position);
+            }
+        }
+    });
+
+    app.on_previous_chapter({
+        let app_weak = app.as_weak();
+        move || {
+            let app = app_weak.unwrap();
+            let position = app.get_position();
+            // Like the previous track button of a CD player: back to the start of the current
+            // chapter, or to the previous one if that was just now.
+            let start = app
+                .get_chapter_starts()
+                .iter()
+                .filter(|start| *start < position - PREVIOUS_CHAPTER_GRACE)
+                .last()
+                .unwrap_or(0.);
+            seek_by(&app, start - position);
+        }
+    });
+
+    app.on_speed_changed({
+        let player = player.clone();
+        move |speed| {
+            player.borrow_mut().set_speed(speed);
+        }
+    });
+
+    app.on_toggle_loop({
+        let player = player.clone();
+        let app_weak = app.as_weak();
+        move || {
+            let app = app_weak.unwrap();
+            let looping = !app.get_looping();
+            player.borrow_mut().set_loop(looping);
+            app.set_looping(looping);
+        }
+    });
+
+    app.on_toggle_hardware_decoding({
+        let player = player.clone();
+        let app_weak = app.as_weak();
+        move || {
+            let app = app_weak.unwrap();
+            let hardware_decoding = !app.get_hardware_decoding();
+            player.borrow_mut().set_hardware_decoding(hardware_decoding);
+            app.set_hardware_decoding(hardware_decoding);
+        }
+    });
+
+    // The start of the A-B loop, once marked.
+    let loop_start = Rc::new(Cell::new(None));
+    // The start and end of the active A-B loop, which can be exported as a clip.
+    let loop_region = Rc::new(Cell::new(None));
+
+    app.on_set_loop_a({
+        let player = player.clone();
+        let loop_start = loop_start.clone();
+        let loop_region = loop_region.clone();
+        let app_weak = app.as_weak();
+        move || {
+            let app = app_weak.unwrap();
+            let mut player = player.borrow_mut();
+            // Marking a new start replaces the previous loop.
+            player.set_loop_region(None);
+            loop_region.set(None);
+            loop_start.set(player.position());
+            app.set_loop_start_set(loop_start.get().is_some());
+            app.set_loop_region_active(false);
+        }
+    });
+
+    app.on_set_loop_b({
+        let player = player.clone();
+        let loop_start = loop_start.clone();
+        let loop_region = loop_region.clone();
+        let app_weak = app.as_weak();
+        move || {
+            let mut player = player.borrow_mut();
+            let (Some(start), Some(end)) = (loop_start.get(), player.position()) else { return };
+            if end > start {
+                player.set_loop_region(Some((start, end)));
+                loop_region.set(Some((start, end)));
+                app_weak.unwrap().set_loop_region_active(true);
+            }
+        }
+    });
+
+    app.on_clear_loop({
+        let player = player.clone();
+        let loop_start = loop_start.clone();
+        let loop_region = loop_region.clone();
+        let app_weak = app.as_weak();
+        move || {
+            player.borrow_mut().set_loop_region(None);
+            loop_start.set(None);
+            loop_region.set(None);
+            let app = app_weak.unwrap();
+            app.set_loop_start_set(false);
+            app.set_loop_region_active(false);
+        }
+    });
+
+    app.on_picture_adjustment_changed({
+        let player = player.clone();
+        let app_weak = app.as_weak();
+        move || player.borrow_mut().set_picture_adjustment(picture_adjustment(&app_weak.unwrap()))
+    });
+
+    app.on_deinterlace_mode_changed({
+        let player = player.clone();
+        let app_weak = app.as_weak();
+        move || player.borrow_mut().set_deinterlace_mode(deinterlace_mode(&app_weak.unwrap()))
+    });
+
+    app.on_sync_mode_changed({
+        let player = player.clone();
+        let app_weak = app.as_weak();
+        move || player.borrow_mut().set_sync_mode(sync_mode(&app_weak.unwrap()))
+    });
+
+    app.on_smooth_motion_changed({
+        let player = player.clone();
+        move |enabled| player.borrow_mut().set_smoothing_mode(smoothing_mode(enabled))
+    });
+
+    app.on_adaptive_quality_changed({
+        let player = player.clone();
+        move |enabled| player.borrow_mut().set_adaptive_quality(adaptive_quality(enabled))
+    });
+
+    app.on_video_filter_changed({
+        let player = player.clone();
+        let app_weak = app.as_weak();
+        move || {
+            let app = app_weak.unwrap();
+            match player.borrow_mut().set_video_filter(video_filter(&app)) {
+                // Clear a previous error.
+                Ok(()) => app.set_status_text(Default::default()),
+                Err(error) => app.set_status_text(error.to_string().into()),
+            }
+        }
+    });
+
+    app.on_balance_changed({
+        let player = player.clone();
+        move |balance| player.borrow_mut().set_balance(balance)
+    });
+
+    app.on_night_mode_changed({
+        let player = player.clone();
+        move |enabled| player.borrow_mut().set_night_mode(enabled)
+    });
+
+    app.on_downmix_changed({
+        let player = player.clone();
+        let app_weak = app.as_weak();
+        move || player.borrow_mut().set_downmix(downmix(&app_weak.unwrap()))
+    });
+
+    app.on_equalizer_changed({
+        let app_weak = app.as_weak();
+        let player = player.clone();
+        move || player.borrow_mut().set_equalizer(equalizer_gains(&app_weak.unwrap()))
+    });
+
+    app.on_loudness_target_changed({
+        let player = player.clone();
+        move |target| player.borrow_mut().set_loudness_target(loudness_target(target))
+    });
+
+    app.on_audio_delay_changed({
+        let player = player.clone();
+        move |milliseconds| player.borrow_mut().set_audio_delay(milliseconds)
+    });
+
+    app.on_open_file({
+        let app_weak = app.as_weak();
+        move || {
+            let app_weak = app_weak.clone();
+            // Drive the dialog from its own thread, so that the event loop keeps running while
+            // it is open.
+            std::thread::Builder::new()
+                .name("file dialog thread".into())
+                .spawn(move || {
+                    let Some(files) = smol::block_on(rfd::AsyncFileDialog::new().pick_files())
+                    else {
+                        // Dialog cancelled
+                        return;
+                    };
+                    let paths = files
+                        .iter()
+                        .map(|file| file.path().to_string_lossy().to_string())
+                        .collect::<Vec<_>>();
+                    app_weak
+                        .upgrade_in_event_loop(move |app| {
+                            // Several picked files play one after the other.
+                            let mut paths = paths.into_iter();
+                            if let Some(first) = paths.next() {
+                                app.invoke_load_file(first.into());
+                            }
+                            for path in paths {
+                                app.invoke_queue_file(path.into());
+                            }
+                        })
+                        .ok();
+                })
+                .unwrap();
+        }
+    });
+
+    let seek_step = duration_from_env(SEEK_STEP_VARIABLE).unwrap_or(DEFAULT_SEEK_STEP);
+    // The operating system's media controls, once registered.
+    let media_controls: Rc<RefCell<Option<media_controls::MediaControls>>> = Default::default();
+
+    // The window system reports the files of a drop one by one, so collect them until the
+    // event loop handled all of them.
+    let dropped_files: Rc<RefCell<Vec<PathBuf>>> = Default::default();
+    let drop_timer = slint::Timer::default();
+    // Hides the controls again once the pointer stopped moving in fullscreen.
+    let pointer_timer = slint::Timer::default();
+    app.window().on_winit_window_event({
+        let app_weak = app.as_weak();
+        let player = player.clone();
+        let media_controls = media_controls.clone();
+        let saved_settings = saved_settings.clone();
+        // The refresh rate of the monitor the window was last seen on.
+        let refresh_rate = Cell::new(None);
+        move |_, event| {
+            match event {
+                // The last chance to save the settings while the window, whose geometry is one
+                // of them, is still shown.
+                winit::event::WindowEvent::CloseRequested => {
+                    save_settings(&app_weak.unwrap(), &mut saved_settings.borrow_mut());
+                }
+                winit::event::WindowEvent::DroppedFile(path) => {
+                    dropped_files.borrow_mut().push(path.clone());
+                    let app_weak = app_weak.clone();
+                    let dropped_files = dropped_files.clone();
+                    drop_timer.start(
+                        slint::TimerMode::SingleShot,
+                        std::time::Duration::ZERO,
+                        move || {
+                            open_dropped_files(&app_weak.unwrap(), dropped_files.take());
+                        },
+                    );
+                }
+                // Where the operating system's media controls are registered, they receive the
+                // media keys themselves.
+                winit::event::WindowEvent::KeyboardInput { event: key_event, .. }
+                    if key_event.state.is_pressed() && media_controls.borrow().is_none() =>
+                {
+                    use winit::keyboard::{Key, NamedKey};
+                    let app = app_weak.unwrap();
+                    match key_event.logical_key {
+                        Key::Named(NamedKey::MediaPlayPause) => app.invoke_toggle_pause_play(),
+                        Key::Named(NamedKey::MediaTrackNext) => app.invoke_next(),
+                        Key::Named(NamedKey::MediaTrackPrevious) => app.invoke_previous(),
+                        Key::Named(NamedKey::MediaFastForward) => {
+                            seek_by(&app, seek_step.as_secs_f32())
+                        }
+                        Key::Named(NamedKey::MediaRewind) => {
+                            seek_by(&app, -seek_step.as_secs_f32())
+                        }
+                        _ => {}
+                    }
+                }
+                winit::event::WindowEvent::CursorMoved { .. } => {
+                    let app = app_weak.unwrap();
+                    if app.get_fullscreen() {
+                        app.set_pointer_moved(true);
+                        let app_weak = app_weak.clone();
+                        pointer_timer.start(
+                            slint::TimerMode::SingleShot,
+                            POINTER_REVEAL_DURATION,
+                            move || app_weak.unwrap().set_pointer_moved(false),
+                        );
+                    }
+                }
+                // Reported when the window is minimized or restored, as well as when it is
+                // completely covered on some platforms. Nobody watches the video then, so
+                // save the battery.
+                winit::event::WindowEvent::Occluded(occluded) => {
+                    let mut player = player.borrow_mut();
+                    if *occluded && player.is_playing() {
+                        player.pause();
+                        auto_paused.set(true);
+                    } else if !*occluded && auto_paused.take() && !player.is_playing() {
+                        player.toggle_pause_playing();
+                    }
+                }
+                // Possibly onto a monitor that refreshes at a different rate.
+                winit::event::WindowEvent::Moved(_) => {
+                    let rate = display_refresh_rate(&app_weak.unwrap());
+                    if rate != refresh_rate.replace(rate) {
+                        player.borrow_mut().set_display_refresh_rate(rate);
+                    }
+                }
+                _ => {}
+            }
+            EventResult::Propagate
+        }
+    });
+
+    app.on_screenshot({
+        let app_weak = app.as_weak();
+        let latest_frame = latest_frame.clone();
+        move || {
+            let Some(frame) = latest_frame.lock().unwrap().clone() else {
+                // Nothing displayed yet
+                return;
+            };
+            let app_weak = app_weak.clone();
+            let timestamp = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            std::thread::Builder::new()
+                .name("screenshot thread".into())
+                .spawn(move || {
+                    let Some(file) = smol::block_on(
+                        rfd::AsyncFileDialog::new()
+                            .add_filter("PNG image", &["png"])
+                            .set_file_name(format!("screenshot-{timestamp}.png"))
+                            .save_file(),
+                    ) else {
+                        // Dialog cancelled
+                        return;
+                    };
+                    if let Err(err) = save_png(file.path(), &frame) {
+                        let message = format!("Could not save the screenshot: {err}");
+                        app_weak
+                            .upgrade_in_event_loop(move |app| app.set_status_text(message.into()))
+                            .ok();
+                    }
+                })
+                .unwrap();
+        }
+    });
+
+    app.on_open_subtitles({
+        let app_weak = app.as_weak();
+        let loaded_subtitles = loaded_subtitles.clone();
+        move || {
+            let app_weak = app_weak.clone();
+            let loaded_subtitles = loaded_subtitles.clone();
+            std::thread::Builder::new()
+                .name("subtitle dialog thread".into())
+                .spawn(move || {
+                    let Some(file) = smol::block_on(
+                        rfd::AsyncFileDialog::new()
+                            .add_filter("SubRip subtitles", &["srt"])
+                            .pick_file(),
+                    ) else {
+                        // Dialog cancelled
+                        return;
+                    };
+                    match subtitles::Subtitles::load(file.path()) {
+                        Ok(mut subtitles) => {
+                            app_weak
+                                .upgrade_in_event_loop(move |app| {
+                                    subtitles.set_delay(app.get_subtitle_delay_ms() as f64 / 1000.);
+                                    app.set_subtitles_loaded(true);
+                                    // Show the cue at the current position right away, even
+                                    // while paused.
+                                    let position = app.get_position() as f64;
+                                    app.set_current_subtitle(subtitles.text_at(position).into());
+                                    *loaded_subtitles.lock().unwrap() = Some(subtitles);
+                                })
+                                .ok();
+                        }
+                        Err(err) => {
+                            let message = format!("Could not load the subtitles: {err}");
+                            app_weak
+                                .upgrade_in_event_loop(move |app| {
+                                    app.set_status_text(message.into())
+                                })
+                                .ok();
+                        }
+                    }
+                })
+                .unwrap();
+        }
+    });
+
+    app.on_subtitle_delay_changed({
+        let app_weak = app.as_weak();
+        let loaded_subtitles = loaded_subtitles.clone();
+        move |milliseconds| {
+            let app = app_weak.unwrap();
+            if let Some(subtitles) = loaded_subtitles.lock().unwrap().as_mut() {
+                subtitles.set_delay(milliseconds as f64 / 1000.);
+                // Update the shown cue right away, even while paused.
+                app.set_current_subtitle(subtitles.text_at(app.get_position() as f64).into());
+            }
+        }
+    });
+
+    app.on_select_video_stream({
+        let player = player.clone();
+        move |index| {
+            player.borrow_mut().select_video_stream(index as usize);
+        }
+    });
+
+    // The audio stream picked for the current item, if any, to export the one that is heard.
+    let selected_audio_stream = Rc::new(Cell::new(None));
+
+    app.on_select_audio_stream({
+        let player = player.clone();
+        let selected_audio_stream = selected_audio_stream.clone();
+        move |index| {
+            player.borrow_mut().select_audio_stream(index as usize);
+            selected_audio_stream.set(Some(index as usize));
+        }
+    });
+
+    let export_cancelled = Arc::new(AtomicBool::new(false));
+
+    app.on_export_audio({
+        let app_weak = app.as_weak();
+        let player = player.clone();
+        let selected_audio_stream = selected_audio_stream.clone();
+        let export_cancelled = export_cancelled.clone();
+        move || {
+            let source = player.borrow().path().to_owned();
+            let stream_index = selected_audio_stream.get();
+            let file_name = source
+                .file_stem()
+                .map(|stem| format!("{}.wav", stem.to_string_lossy()))
+                .unwrap_or_else(|| "audio.wav".into());
+            start_export(
+                &app_weak.unwrap(),
+                &export_cancelled,
+                file_name,
+                ("WAV audio", "wav"),
+                "audio",
+                move |destination, cancelled, progress| {
+                    player::export_audio(&source, stream_index, destination, cancelled, progress)
+                },
+            );
+        }
+    });
+
+    app.on_export_clip({
+        let app_weak = app.as_weak();
+        let player = player.clone();
+        let loop_region = loop_region.clone();
+        let export_cancelled = export_cancelled.clone();
+        move |frame_accurate| {
+            let Some(region) = loop_region.get() else { return };
+            let source = player.borrow().path().to_owned();
+            let extension = source
+                .extension()
+                .map(|extension| extension.to_string_lossy().into_owned())
+                .unwrap_or_else(|| "mkv".into());
+            let file_name = format!(
+                "{}-clip.{}",
+                source.file_stem().map(|stem| stem.to_string_lossy()).unwrap_or_default(),
+                extension
+            );
+            let mode = if frame_accurate {
+                player::ClipMode::Reencode
+            } else {
+                player::ClipMode::StreamCopy
+            };
+            start_export(
+                &app_weak.unwrap(),
+                &export_cancelled,
+                file_name,
+                ("Video", extension.as_str()),
+                "clip",
+                move |destination, cancelled, progress| {
+                    player::export_clip(&source, region, destination, mode, cancelled, progress)
+                },
+            );
+        }
+    });
+
+    app.on_recording_toggled({
+        let app_weak = app.as_weak();
+        let player = player.clone();
+        move || {
+            let app = app_weak.unwrap();
+            if !app.get_recording() {
+                player.borrow_mut().set_recording(None);
+                return;
+            }
+            let timestamp = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            let app_weak = app_weak.clone();
+            std::thread::Builder::new()
+                .name("recording dialog thread".into())
+                .spawn(move || {
+                    let file = smol::block_on(
+                        rfd::AsyncFileDialog::new()
+                            .add_filter("Matroska video", &["mkv"])
+                            .add_filter("MPEG transport stream", &["ts"])
+                            .set_file_name(format!("recording-{timestamp}.mkv"))
+                            .save_file(),
+                    );
+                    let path = file.map(|file| file.path().to_string_lossy().into_owned());
+                    app_weak
+                        .upgrade_in_event_loop(move |app| match path {
+                            Some(path) => app.invoke_record_to(path.into()),
+                            // Dialog cancelled
+                            None => app.set_recording(false),
+                        })
+                        .ok();
+                })
+                .unwrap();
+        }
+    });
+
+    app.on_record_to({
+        let app_weak = app.as_weak();
+        let player = player.clone();
+        move |path| {
+            // Stopped again while the dialog was open.
+            if app_weak.unwrap().get_recording() {
+                player.borrow_mut().set_recording(Some(PathBuf::from(path.as_str())));
+            }
+        }
+    });
+
+    app.on_cancel_export(move || export_cancelled.store(true, Ordering::Relaxed));
+
+    app.on_audio_device_changed({
+        let player = player.clone();
+        let app_weak = app.as_weak();
+        move || {
+            player.borrow_mut().set_audio_device(selected_audio_device(&app_weak.unwrap()));
+        }
+    });
+
+    // Changed settings are picked up with the next frame.
+    app.on_max_video_height_changed({
+        let conversion_settings = conversion_settings.clone();
+        move |height| {
+            conversion_settings.lock().unwrap().max_height = height.max(0) as u32;
+        }
+    });
+
+    app.on_scaling_algorithm_changed({
+        let conversion_settings = conversion_settings.clone();
+        move |index| {
+            if let Some(scaling) = ScalingAlgorithm::ALL.get(index as usize) {
+                conversion_settings.lock().unwrap().scaling = *scaling;
+            }
+        }
+    });
+
+    // Plays the current item of the playlist.
+    let play_current = Rc::new({
+        let app_weak = app.as_weak();
+        let player = player.clone();
+        let playlist = playlist.clone();
+        let resume_positions = resume_positions.clone();
+        let played_to_end = played_to_end.clone();
+        move || {
+            let app = app_weak.unwrap();
+            selected_audio_stream.set(None);
+            if !played_to_end.replace(false) {
+                remember_position(&app, &player.borrow(), &mut resume_positions.borrow_mut());
+            }
+            show_playlist(&app, &playlist.borrow());
+            let path = playlist.borrow().current().to_owned();
+            let mut player = player.borrow_mut();
+            // Stopped first, so no frame of the previous input shows up after the reset.
+            player.stop();
+            prepare_for_input(
+                &app,
+                &first_frame_timer,
+                &latest_frame,
+                &loaded_subtitles,
+                &network,
+                &first_frame_received,
+                &path,
+            );
+            player.set_start_paused(app.get_start_paused());
+            player.open(path).unwrap();
+            // A-B loops are positions within the previous file.
+            loop_start.set(None);
+            loop_region.set(None);
+            // Recordings end with the input they were made of.
+            app.set_recording(false);
+            app.set_loop_start_set(false);
+            app.set_loop_region_active(false);
+            // Out of sync audio is a property of the previous file, too.
+            app.set_audio_delay_ms(0);
+            resume_playback(&mut player, &resume_positions.borrow());
+        }
+    });
+
+    app.on_load_file({
+        let playlist = playlist.clone();
+        let play_current = play_current.clone();
+        move |path| {
+            *playlist.borrow_mut() = playlist::Playlist::new(PathBuf::from(path.as_str()));
+            play_current();
+        }
+    });
+
+    app.on_queue_file({
+        let app_weak = app.as_weak();
+        let playlist = playlist.clone();
+        move |path| {
+            playlist.borrow_mut().append(PathBuf::from(path.as_str()));
+            show_playlist(&app_weak.unwrap(), &playlist.borrow());
+        }
+    });
+
+    app.on_playback_ended({
+        let app_weak = app.as_weak();
+        let player = player.clone();
+        let resume_positions = resume_positions.clone();
+        let played_to_end = played_to_end.clone();
+        move || {
+            played_to_end.set(true);
+            let mut resume_positions = resume_positions.borrow_mut();
+            resume_positions.remove(player.borrow().path());
+            resume_positions.save().ok();
+            drop(resume_positions);
+            let app = app_weak.unwrap();
+            // The last frame stays, with the play button offering to play it again, unless
+            // the playlist continues with the next item.
+            app.set_ended(true);
+            app.invoke_next();
+        }
+    });
+
+    app.on_next({
+        let playlist = playlist.clone();
+        let play_current = play_current.clone();
+        move || {
+            // At the end of the playlist, the last frame stays.
+            if playlist.borrow_mut().next().is_some() {
+                play_current();
+            }
+        }
+    });
+
+    app.on_previous({
+        let playlist = playlist.clone();
+        move || {
+            if playlist.borrow_mut().previous().is_some() {
+                play_current();
+            }
+        }
+    });
+
+    let playback_stats_timer = slint::Timer::default();
+    playback_stats_timer.start(slint::TimerMode::Repeated, PLAYBACK_STATS_INTERVAL, {
+        let app_weak = app.as_weak();
+        let player = player.clone();
+        let mut meter = RateMeter::default();
+        move || {
+            let app = app_weak.unwrap();
+            if !app.get_show_playback_stats() {
+                // Rates from before hiding them would be stale.
+                meter = RateMeter::default();
+                return;
+            }
+            let player = player.borrow();
+            let [decoded, presented, underruns] = meter.sample([
+                player.decoded_video_frames(),
+                player.presented_video_frames(),
+                player.audio_underruns(),
+            ]);
+            app.set_frame_stats(
+                format!(
+                    "{:.1} fps rendered, {:.1} fps decoded, {} dropped",
+                    presented,
+                    decoded,
+                    player.dropped_video_frames()
+                )
+                .into(),
+            );
+            // Frequent underruns call for a larger audio buffer.
+            let audio_stats = player.audio_buffer_fill().map(|(buffered, capacity)| {
+                format!(
+                    "Audio buffer {:.0}% full, {} underruns ({:.1}/s)",
+                    buffered as f64 * 100. / capacity as f64,
+                    player.audio_underruns(),
+                    underruns
+                )
+            });
+            app.set_audio_stats(audio_stats.unwrap_or_default().into());
+        }
+    });
+
+    // Audio-only inputs show the audio they play instead, drawn like video frames.
+    let waveform_timer = slint::Timer::default();
+    waveform_timer.start(slint::TimerMode::Repeated, WAVEFORM_INTERVAL, {
+        let app_weak = app.as_weak();
+        let player = player.clone();
+        let pixel_buffers = pixel_buffers.clone();
+        // Keeps the buffer of the waveform shown out of the pool.
+        let shown_waveform = Cell::new(None);
+        move || {
+            let app = app_weak.unwrap();
+            if !app.get_audio_only() || !app.get_playing() {
+                return;
+            }
+            let pixel_buffer = draw_waveform(&player.borrow().audio_peaks(), &pixel_buffers);
+            app.set_video_aspect_ratio(pixel_buffer.width() as f32 / pixel_buffer.height() as f32);
+            app.set_video_frame(slint::Image::from_rgb8((*pixel_buffer).clone()));
+            shown_waveform.set(Some(pixel_buffer));
+        }
+    });
+
+    // Registered on the first tick, once the window is shown, which Windows needs. Without
+    // them the player works as before, so a failure is not worth interrupting for.
+    let media_controls_timer = slint::Timer::default();
+    media_controls_timer.start(slint::TimerMode::Repeated, MEDIA_CONTROLS_INTERVAL, {
+        let app_weak = app.as_weak();
+        let media_controls = media_controls.clone();
+        let mut registered = false;
+        move || {
+            let app = app_weak.unwrap();
+            if !std::mem::replace(&mut registered, true) {
+                *media_controls.borrow_mut() =
+                    media_controls::MediaControls::new(&app, seek_step).ok();
+            }
+            if let Some(media_controls) = &mut *media_controls.borrow_mut() {
+                media_controls.update(&app);
+            }
+        }
+    });
+
+    // The window is created by showing it, and only then knows the monitors it can be put on.
+    app.show().unwrap();
+    let saved_window_geometry = saved_settings.borrow().window;
+    if let Some(geometry) = &saved_window_geometry {
+        restore_window_geometry(&app, geometry);
+    }
+    player.borrow_mut().set_display_refresh_rate(display_refresh_rate(&app));
+    app.run().unwrap();
+
+    if !played_to_end.get() {
+        remember_position(&app, &player.borrow(), &mut resume_positions.borrow_mut());
+    }
+    resume_positions.borrow_mut().save().ok();
+}
+
+/// Counts of the player, like the decoded frames, sampled over the last [`RATE_WINDOW`], to
+/// tell how fast they grow.
+#[derive(Default)]
+struct RateMeter<const N: usize> {
+    /// When, and the counts by then.
+    samples: std::collections::VecDeque<(std::time::Instant, [u64; N])>,
+}
+
+impl<const N: usize> RateMeter<N> {
+    /// Adds the `counts` so far, and returns by how much each grew per second within the
+    /// window.
+    fn sample(&mut self, counts: [u64; N]) -> [f64; N] {
+        let now = std::time::Instant::now();
+        // Counting starts over with each input.
+        if self.samples.back().is_some_and(|(_, last)| counts.iter().zip(last).any(|(a, b)| a < b))
         {
-            let app_weak = app.as_weak();
+            self.samples.clear();
+        }
+        while self.samples.front().is_some_and(|&(time, _)| now - time > RATE_WINDOW) {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((now, counts));
 
-            move |new_frame| {
-                // TODO: use OpenGL bridge
+        let (start, start_counts) = self.samples.front().unwrap();
+        let seconds = (now - *start).as_secs_f64();
+        std::array::from_fn(|index| {
+            if seconds == 0. {
+                0.
+            } else {
+                (counts[index] - start_counts[index]) as f64 / seconds
+            }
+        })
+    }
+}
 
-                let rebuild_rescaler =
-                    to_rgba_rescaler.as_ref().map_or(true, |existing_rescaler| {
-                        existing_rescaler.input().format != new_frame.format()
-                    });
+/// Shows `settings` in the UI, where the player picks them up from.
+fn apply_settings(app: &App, settings: &settings::Settings) {
+    app.set_volume(settings.volume);
+    app.set_subtitle_delay_ms(settings.subtitle_delay_ms);
+    app.set_smooth_motion(settings.smooth_motion);
+    app.set_adaptive_quality(settings.adaptive_quality);
+    // Speeds other than the presets, and devices that aren't connected, keep the defaults.
+    if let Some(index) = app.get_speed_presets().iter().position(|speed| speed == settings.speed) {
+        app.set_speed_index(index as i32);
+    }
+    if let Some(name) = &settings.audio_device {
+        if let Some(index) =
+            app.get_audio_devices().iter().position(|device| device == name.as_str())
+        {
+            app.set_audio_device_index(index as i32);
+        }
+    }
+}
+
+/// Writes the settings shown in `app` to the config file if they differ from `saved`, the ones
+/// saved before.
+fn save_settings(app: &App, saved: &mut settings::Settings) {
+    let settings = settings::Settings {
+        volume: app.get_volume(),
+        audio_device: selected_audio_device(app),
+        subtitle_delay_ms: app.get_subtitle_delay_ms(),
+        speed: app.get_speed(),
+        smooth_motion: app.get_smooth_motion(),
+        adaptive_quality: app.get_adaptive_quality(),
+        // Fullscreen and the like are left for the window system to undo, keeping the geometry
+        // from before.
+        window: window_geometry(app).or(saved.window),
+    };
+    // The settings are a convenience, so failing to save them is not worth interrupting for.
+    if settings != *saved && settings.save().is_ok() {
+        *saved = settings;
+    }
+}
 
-                if rebuild_rescaler {
-                    to_rgba_rescaler = Some(rgba_rescaler_for_frame(new_frame));
+/// The position and size of the window, unless it is fullscreen, maximized or minimized.
+fn window_geometry(app: &App) -> Option<settings::WindowGeometry> {
+    let window = app.window();
+    if window.is_fullscreen() || window.is_maximized() || window.is_minimized() {
+        return None;
+    }
+    let (position, size) = (window.position(), window.size());
+    (size.width > 0 && size.height > 0).then_some(settings::WindowGeometry {
+        x: position.x,
+        y: position.y,
+        width: size.width,
+        height: size.height,
+    })
+}
+
+/// The refresh rate in Hz of the monitor the window is on, if known.
+fn display_refresh_rate(app: &App) -> Option<f64> {
+    app.window()
+        .with_winit_window(|window| window.current_monitor()?.refresh_rate_millihertz())
+        .flatten()
+        .map(|millihertz| millihertz as f64 / 1000.)
+}
+
+/// Moves the window to `geometry` and resizes it to match. A window that would be mostly off
+/// the monitors, like one last shown on a monitor that is no longer connected, is moved onto the
+/// primary monitor and shrunk to fit it instead.
+fn restore_window_geometry(app: &App, geometry: &settings::WindowGeometry) {
+    // As x, y, width and height, the primary monitor first.
+    let monitors = app
+        .window()
+        .with_winit_window(|window| {
+            window
+                .primary_monitor()
+                .into_iter()
+                .chain(window.available_monitors())
+                .map(|monitor| {
+                    let (position, size) = (monitor.position(), monitor.size());
+                    (position.x, position.y, size.width as i32, size.height as i32)
+                })
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    let visible_on = |&(x, y, width, height): &(i32, i32, i32, i32)| {
+        let overlap_width = (geometry.x + geometry.width as i32).min(x + width) - geometry.x.max(x);
+        let overlap_height =
+            (geometry.y + geometry.height as i32).min(y + height) - geometry.y.max(y);
+        overlap_width >= MIN_VISIBLE_WINDOW_PART && overlap_height >= MIN_VISIBLE_WINDOW_PART
+    };
+
+    let (mut width, mut height) = (geometry.width, geometry.height);
+    let position = match monitors.first() {
+        // Without knowing the monitors, the position can't be checked, so the window system
+        // places the window.
+        None => None,
+        Some(_) if monitors.iter().any(visible_on) => Some((geometry.x, geometry.y)),
+        Some(&(x, y, monitor_width, monitor_height)) => {
+            width = width.min(monitor_width as u32);
+            height = height.min(monitor_height as u32);
+            Some((
+                geometry.x.clamp(x, x + monitor_width - width as i32),
+                geometry.y.clamp(y, y + monitor_height - height as i32),
+            ))
+        }
+    };
+    app.window().set_size(slint::PhysicalSize::new(width, height));
+    if let Some((x, y)) = position {
+        app.window().set_position(slint::PhysicalPosition::new(x, y));
+    }
+}
+
+/// Seeks `offset` seconds forward, or backward for negative values, from the current position,
+/// within the input. Moves the seek bar right away, like dragging it.
+fn seek_by(app: &App, offset: f32) {
+    let mut position = (app.get_position() + offset).max(0.);
+    if app.get_duration() > 0. {
+        position = position.min(app.get_duration());
+    }
+    app.set_position(position);
+    app.invoke_seek(position);
+}
+
+/// Stores the position of `player`, to continue there when its input is opened again.
+fn remember_position(
+    app: &App,
+    player: &player::Player,
+    resume_positions: &mut resume::ResumePositions,
+) {
+    // Live streams have no duration, and continue at the live edge anyway.
+    if app.get_duration() <= 0. {
+        return;
+    }
+    if let Some(position) = player.position() {
+        resume_positions.set(player.path(), position);
+    }
+}
+
+/// Continues playback where it stopped the last time the input was played.
+fn resume_playback(player: &mut player::Player, resume_positions: &resume::ResumePositions) {
+    if let Some(position) = resume_positions.get(player.path()) {
+        player.seek(position, player::SeekMode::Accurate);
+    }
+}
+
+/// Plays the media files of `paths` one after the other, like files picked in the open dialog,
+/// and reports the ones that were left out.
+fn open_dropped_files(app: &App, paths: Vec<PathBuf>) {
+    let (media_files, others): (Vec<_>, Vec<_>) = paths.into_iter().partition(|path| {
+        path.extension().is_some_and(|extension| {
+            MEDIA_EXTENSIONS.contains(&extension.to_string_lossy().to_lowercase().as_str())
+        })
+    });
+
+    let mut media_files = media_files.into_iter();
+    if let Some(first) = media_files.next() {
+        app.invoke_load_file(first.to_string_lossy().as_ref().into());
+    }
+    for path in media_files {
+        app.invoke_queue_file(path.to_string_lossy().as_ref().into());
+    }
+
+    // Replaces the loading message, until the first frame of a played file clears it.
+    if !others.is_empty() {
+        let names = others
+            .iter()
+            .map(|path| path.file_name().unwrap_or(path.as_os_str()).to_string_lossy())
+            .collect::<Vec<_>>()
+            .join(", ");
+        app.set_status_text(format!("Not a media file: {}", names).into());
+    }
+}
+
+fn show_playlist(app: &App, playlist: &playlist::Playlist) {
+    app.set_playlist_item(playlist.describe_current().into());
+    app.set_has_previous(playlist.has_previous());
+    app.set_has_next(playlist.has_next());
+}
+
+/// The name of the audio output device picked in the UI, or `None` for the default output.
+fn selected_audio_device(app: &App) -> Option<String> {
+    match app.get_audio_device_index() {
+        0 => None,
+        index => app.get_audio_devices().row_data(index as usize).map(|name| name.to_string()),
+    }
+}
+
+/// The picture adjustment set with the sliders in the UI.
+fn picture_adjustment(app: &App) -> player::PictureAdjustment {
+    player::PictureAdjustment {
+        brightness: app.get_brightness(),
+        contrast: app.get_contrast(),
+        saturation: app.get_saturation(),
+    }
+}
+
+/// The deinterlacing mode picked in the UI.
+fn deinterlace_mode(app: &App) -> player::DeinterlaceMode {
+    match app.get_deinterlace_mode() {
+        1 => player::DeinterlaceMode::On,
+        2 => player::DeinterlaceMode::Off,
+        _ => player::DeinterlaceMode::Auto,
+    }
+}
+
+/// The clock picked in the UI to present the video against.
+fn sync_mode(app: &App) -> player::SyncMode {
+    match app.get_sync_mode() {
+        1 => player::SyncMode::Wallclock,
+        _ => player::SyncMode::AudioMaster,
+    }
+}
+
+/// How frames are paced with smooth motion `enabled` or not.
+fn smoothing_mode(enabled: bool) -> player::SmoothingMode {
+    if enabled {
+        player::SmoothingMode::FrameGrid
+    } else {
+        player::SmoothingMode::Off
+    }
+}
+
+/// The thresholds the video quality adapts at with adaptive quality `enabled`, or `None`.
+fn adaptive_quality(enabled: bool) -> Option<player::AdaptiveQuality> {
+    enabled.then(player::AdaptiveQuality::default)
+}
+
+/// The downmix levels picked in the UI.
+fn downmix(app: &App) -> player::DownmixMatrix {
+    match app.get_downmix() {
+        1 => player::DownmixMatrix::DIALOG_BOOST,
+        2 => player::DownmixMatrix::DOLBY_SURROUND,
+        _ => player::DownmixMatrix::DEFAULT,
+    }
+}
+
+/// The gains of the equalizer bands set in the UI, in decibels.
+fn equalizer_gains(app: &App) -> [f32; player::EQUALIZER_BANDS] {
+    let mut gains = [0.; player::EQUALIZER_BANDS];
+    for (gain, ui_gain) in gains.iter_mut().zip(app.get_equalizer_gains().iter()) {
+        *gain = ui_gain;
+    }
+    gains
+}
+
+/// The loudness target picked in the UI, in LUFS, where 0 stands for none.
+fn loudness_target(target: i32) -> Option<f32> {
+    (target != 0).then_some(target as f32)
+}
+
+/// The video filter entered in the UI, or `None` when it is empty.
+fn video_filter(app: &App) -> Option<String> {
+    Some(app.get_video_filter().trim().to_string()).filter(|filter| !filter.is_empty())
+}
+
+/// Clears what the UI shows about the previous input before `path` is opened, and starts
+/// probing its streams and waiting for its first frame.
+fn prepare_for_input(
+    app: &App,
+    first_frame_timer: &slint::Timer,
+    latest_frame: &LatestFrame,
+    loaded_subtitles: &LoadedSubtitles,
+    network: &player::NetworkOptions,
+    first_frame_received: &Arc<AtomicBool>,
+    path: &std::path::Path,
+) {
+    first_frame_received.store(false, Ordering::Relaxed);
+    *latest_frame.lock().unwrap() = None;
+    app.set_status_text("Loading…".into());
+    app.set_loading(false);
+    app.set_position(0.);
+    app.set_duration(0.);
+    app.set_ended(false);
+    // Subtitles are timed for the previous input.
+    *loaded_subtitles.lock().unwrap() = None;
+    app.set_current_subtitle(Default::default());
+    app.set_subtitles_loaded(false);
+    app.set_audio_only(false);
+    list_streams(app, path.to_owned(), network.clone(), first_frame_received.clone());
+    first_frame_timer.start(slint::TimerMode::SingleShot, FIRST_FRAME_TIMEOUT, {
+        let app_weak = app.as_weak();
+        let first_frame_received = first_frame_received.clone();
+        move || {
+            if !first_frame_received.load(Ordering::Relaxed) {
+                if let Some(app) = app_weak.upgrade() {
+                    app.set_status_text("No video frames decoded".into());
                 }
+            }
+        }
+    });
+}
+
+fn start_player(
+    app: &App,
+    first_frame_timer: &slint::Timer,
+    latest_frame: LatestFrame,
+    pixel_buffers: PixelBufferPool,
+    conversion_settings: Arc<Mutex<ConversionSettings>>,
+    loaded_subtitles: LoadedSubtitles,
+    #[cfg(feature = "gpu-yuv")] gpu_frames: gpu_yuv::FrameQueue,
+    network: player::NetworkOptions,
+    missing_audio_device: player::MissingAudioDevice,
+    first_frame_received: Arc<AtomicBool>,
+    path: PathBuf,
+) -> Result<player::Player, anyhow::Error> {
+    let mut rgb_converter = RgbConverter::default();
+
+    prepare_for_input(
+        app,
+        first_frame_timer,
+        &latest_frame,
+        &loaded_subtitles,
+        &network,
+        &first_frame_received,
+        &path,
+    );
+
+    player::Player::start(
+        path,
+        player::PlayerOptions {
+            hardware_decoding: app.get_hardware_decoding(),
+            start_paused: app.get_start_paused(),
+            audio_device: selected_audio_device(app),
+            video_filter: video_filter(app),
+            // Makes dragging the seek bar snappier.
+            keyframe_index: true,
+            seek_thumbnails: true,
+            network,
+            missing_audio_device,
+            ..Default::default()
+        },
+        {
+            let app_weak = app.as_weak();
+            let first_frame_received = first_frame_received.clone();
 
-                let rescaler = to_rgba_rescaler.as_mut().unwrap();
+            move |new_frame, quality| {
+                let rotation = display_rotation(new_frame);
+                let mut aspect_ratio = display_aspect_ratio(new_frame);
+                if rotation % 180 != 0 {
+                    aspect_ratio = 1. / aspect_ratio;
+                }
+                let first_frame = !first_frame_received.swap(true, Ordering::Relaxed);
 
-                let mut rgb_frame = ffmpeg_next::util::frame::Video::empty();
-                rescaler.run(&new_frame, &mut rgb_frame).unwrap();
+                #[cfg(feature = "gpu-yuv")]
+                if gpu_frames.push(new_frame, rotation) {
+                    // Converted while rendering, and not kept for screenshots.
+                    *latest_frame.lock().unwrap() = None;
+                    app_weak
+                        .upgrade_in_event_loop(move |app| {
+                            if first_frame {
+                                app.set_status_text(Default::default());
+                            }
+                            app.set_video_aspect_ratio(aspect_ratio);
+                            app.window().request_redraw();
+                        })
+                        .unwrap();
+                    return;
+                }
 
-                let pixel_buffer = video_frame_to_pixel_buffer(&rgb_frame);
+                let settings = *conversion_settings.lock().unwrap();
+                let rgb_frame = rgb_converter.convert(new_frame, quality, settings);
+
+                let pixel_buffer = rotate_pixel_buffer(
+                    video_frame_to_pixel_buffer(&rgb_frame, &pixel_buffers),
+                    rotation,
+                    &pixel_buffers,
+                );
+                let latest_frame = latest_frame.clone();
                 app_weak
-                    .upgrade_in_event_loop(|app| {
-                        app.set_video_frame(slint::Image::from_rgb8(pixel_buffer))
+                    .upgrade_in_event_loop(move |app| {
+                        if first_frame {
+                            app.set_status_text(Default::default());
+                        }
+                        app.set_video_aspect_ratio(aspect_ratio);
+                        app.set_video_frame(slint::Image::from_rgb8((*pixel_buffer).clone()));
+                        // Reduced in size by the height limit, and while the adaptive quality
+                        // asks for half resolution. Replacing the previous frame hands its buffer
+                        // back to the pool.
+                        *latest_frame.lock().unwrap() = Some(pixel_buffer);
                     })
                     .unwrap();
             }
@@ -49,14 +1704,278 @@ fn main() {
                 app_weak.upgrade_in_event_loop(move |app| app.set_playing(playing)).unwrap();
             }
         },
+        {
+            let app_weak = app.as_weak();
+
+            move |position| {
+                let subtitle = loaded_subtitles
+                    .lock()
+                    .unwrap()
+                    .as_ref()
+                    .map(|subtitles| subtitles.text_at(position))
+                    .unwrap_or_default();
+                app_weak
+                    .upgrade_in_event_loop(move |app| {
+                        app.set_position(position as f32);
+                        app.set_current_subtitle(subtitle.into());
+                        show_current_chapter(&app, position as f32);
+                    })
+                    .ok();
+            }
+        },
+        {
+            let app_weak = app.as_weak();
+
+            move |duration| {
+                app_weak
+                    .upgrade_in_event_loop(move |app| {
+                        app.set_duration(duration.unwrap_or_default() as f32)
+                    })
+                    .ok();
+            }
+        },
+        {
+            let app_weak = app.as_weak();
+
+            move |buffering| {
+                app_weak.upgrade_in_event_loop(move |app| app.set_loading(buffering)).ok();
+            }
+        },
+        {
+            let app_weak = app.as_weak();
+
+            move || {
+                app_weak.upgrade_in_event_loop(move |app| app.invoke_playback_ended()).ok();
+            }
+        },
+        {
+            let app_weak = app.as_weak();
+
+            move |message| {
+                // Keep the message rather than replacing it with the first frame timeout's.
+                first_frame_received.store(true, Ordering::Relaxed);
+                app_weak.upgrade_in_event_loop(move |app| app.set_status_text(message.into())).ok();
+            }
+        },
     )
-    .unwrap();
+}
 
-    app.on_toggle_pause_play(move || {
-        player.toggle_pause_playing();
-    });
+/// Reads how to request network inputs from the environment: a `User-Agent` from
+/// `VIDEO_PLAYER_USER_AGENT`, extra headers as `Name: value` lines from
+/// `VIDEO_PLAYER_HTTP_HEADERS`, a timeout in seconds from `VIDEO_PLAYER_NETWORK_TIMEOUT`, and how
+/// many seconds to buffer from `VIDEO_PLAYER_PREBUFFER`.
+fn network_options_from_env() -> player::NetworkOptions {
+    let headers = std::env::var(HTTP_HEADERS_VARIABLE).unwrap_or_default();
+    player::NetworkOptions {
+        user_agent: std::env::var(USER_AGENT_VARIABLE).ok(),
+        headers: headers
+            .lines()
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+            .collect(),
+        timeout: duration_from_env(NETWORK_TIMEOUT_VARIABLE).filter(|timeout| !timeout.is_zero()),
+        prebuffer: duration_from_env(PREBUFFER_VARIABLE),
+    }
+}
 
-    app.run().unwrap();
+/// Reads a duration in seconds from the environment variable `name`.
+fn duration_from_env(name: &str) -> Option<std::time::Duration> {
+    std::env::var(name)
+        .ok()
+        .and_then(|seconds| seconds.parse::<f64>().ok())
+        .filter(|seconds| *seconds >= 0. && seconds.is_finite())
+        .map(std::time::Duration::from_secs_f64)
+}
+
+/// Probes `path`, requested with `network` if it is a URL, in the background and offers its
+/// video and audio streams for selection once known.
+/// Inputs without video never deliver a first frame, so `first_frame_received` is set for them
+/// to keep the first frame timeout from reporting them as broken.
+fn list_streams(
+    app: &App,
+    path: PathBuf,
+    network: player::NetworkOptions,
+    first_frame_received: Arc<AtomicBool>,
+) {
+    app.set_video_streams(Default::default());
+    app.set_video_stream_indices(Default::default());
+    app.set_audio_streams(Default::default());
+    app.set_audio_stream_indices(Default::default());
+    app.set_video_info(Default::default());
+    app.set_audio_info(Default::default());
+    app.set_bit_rate_info(Default::default());
+    app.set_duration_info(Default::default());
+    app.set_chapter_starts(Default::default());
+    app.set_chapter_ends(Default::default());
+    app.set_chapter_titles(Default::default());
+    app.set_current_chapter(Default::default());
+    app.set_current_chapter_index(-1);
+
+    let app_weak = app.as_weak();
+    std::thread::Builder::new()
+        .name("probe thread".into())
+        .spawn(move || {
+            let Ok(summary) = player::can_play(&path, &network) else {
+                // Reported by the player itself
+                return;
+            };
+            let (names, indices): (Vec<slint::SharedString>, Vec<i32>) = summary
+                .streams
+                .iter()
+                .filter(|stream| {
+                    stream.decodable && stream.medium == ffmpeg_next::media::Type::Video
+                })
+                .enumerate()
+                .map(|(number, stream)| {
+                    (format!("Video {} ({})", number + 1, stream.codec).into(), stream.index as i32)
+                })
+                .unzip();
+            let (audio_names, audio_indices): (Vec<slint::SharedString>, Vec<i32>) = summary
+                .streams
+                .iter()
+                .filter(|stream| {
+                    stream.decodable && stream.medium == ffmpeg_next::media::Type::Audio
+                })
+                .enumerate()
+                .map(|(number, stream)| {
+                    let mut details = vec![stream.codec.to_string()];
+                    if let Some(channels) = stream.channels {
+                        details.push(format!("{} ch", channels));
+                    }
+                    let name = match &stream.language {
+                        Some(language) => format!("Audio {} [{}]", number + 1, language),
+                        None => format!("Audio {}", number + 1),
+                    };
+                    (format!("{} ({})", name, details.join(", ")).into(), stream.index as i32)
+                })
+                .unzip();
+            // Describes the streams the player picks by default, which come first.
+            let video_info = summary
+                .streams
+                .iter()
+                .find(|stream| stream.decodable && stream.medium == ffmpeg_next::media::Type::Video)
+                .map(describe_video_stream)
+                .unwrap_or_default();
+            let audio_info = summary
+                .streams
+                .iter()
+                .find(|stream| stream.decodable && stream.medium == ffmpeg_next::media::Type::Audio)
+                .map(describe_audio_stream)
+                .unwrap_or_default();
+            let bit_rate_info = summary
+                .bit_rate
+                .map(|bit_rate| format!("Bit rate: {:.0} kbit/s", bit_rate as f64 / 1000.))
+                .unwrap_or_default();
+            let duration_info = summary
+                .duration
+                .map(|duration| format!("Duration: {}", format_duration(duration)))
+                .unwrap_or_default();
+            let chapter_starts =
+                summary.chapters.iter().map(|chapter| chapter.start as f32).collect::<Vec<_>>();
+            let chapter_ends =
+                summary.chapters.iter().map(|chapter| chapter.end as f32).collect::<Vec<_>>();
+            let chapter_titles = summary
+                .chapters
+                .iter()
+                .enumerate()
+                .map(|(number, chapter)| match &chapter.title {
+                    Some(title) => title.into(),
+                    None => format!("Chapter {}", number + 1).into(),
+                })
+                .collect::<Vec<slint::SharedString>>();
+            let audio_only = names.is_empty();
+            if audio_only {
+                first_frame_received.store(true, Ordering::Relaxed);
+            }
+            app_weak
+                .upgrade_in_event_loop(move |app| {
+                    if audio_only {
+                        app.set_status_text("Audio only".into());
+                    }
+                    app.set_audio_only(audio_only);
+                    app.set_video_streams(Rc::new(slint::VecModel::from(names)).into());
+                    app.set_video_stream_indices(Rc::new(slint::VecModel::from(indices)).into());
+                    app.set_audio_streams(Rc::new(slint::VecModel::from(audio_names)).into());
+                    app.set_audio_stream_indices(
+                        Rc::new(slint::VecModel::from(audio_indices)).into(),
+                    );
+                    app.set_video_info(video_info.into());
+                    app.set_audio_info(audio_info.into());
+                    app.set_bit_rate_info(bit_rate_info.into());
+                    app.set_duration_info(duration_info.into());
+                    app.set_chapter_starts(Rc::new(slint::VecModel::from(chapter_starts)).into());
+                    app.set_chapter_ends(Rc::new(slint::VecModel::from(chapter_ends)).into());
+                    app.set_chapter_titles(Rc::new(slint::VecModel::from(chapter_titles)).into());
+                    show_current_chapter(&app, app.get_position());
+                })
+                .ok();
+        })
+        .unwrap();
+}
+
+/// `seconds` as hours, minutes and seconds, like `1:02:03`, leaving out the hours when zero.
+fn format_duration(seconds: f64) -> String {
+    let seconds = seconds.round() as u64;
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{hours}:{minutes:02}:{seconds:02}")
+    } else {
+        format!("{minutes}:{seconds:02}")
+    }
+}
+
+/// Shows the title of the chapter at `position` and highlights it on the seek bar, or clears both
+/// before the first chapter, between chapters and for inputs without chapters.
+fn show_current_chapter(app: &App, position: f32) {
+    // The starts are sorted, so the chapter at `position` is the last one starting before it,
+    // unless that one already ended.
+    let count = app.get_chapter_starts().iter().take_while(|start| *start <= position).count();
+    let index = count
+        .checked_sub(1)
+        .filter(|index| app.get_chapter_ends().row_data(*index).is_some_and(|end| position < end));
+    let title = index.and_then(|index| app.get_chapter_titles().row_data(index));
+    app.set_current_chapter(title.unwrap_or_default());
+    app.set_current_chapter_index(index.map_or(-1, |index| index as i32));
+}
+
+/// The keyframe within [`KEYFRAME_SNAP_DISTANCE`] of `position` that is closest to it, or
+/// `position` itself if there is none or the keyframes aren't indexed yet.
+fn snap_to_keyframe(position: f64, keyframes: Option<&[f64]>) -> f64 {
+    keyframes
+        .unwrap_or_default()
+        .iter()
+        .copied()
+        .filter(|keyframe| (keyframe - position).abs() <= KEYFRAME_SNAP_DISTANCE)
+        .min_by(|a, b| (a - position).abs().total_cmp(&(b - position).abs()))
+        .unwrap_or(position)
+}
+
+/// A description of a video stream for the info overlay, like
+/// `Video: h264, 1920×1080, yuv420p, 23.98 fps`.
+fn describe_video_stream(stream: &player::StreamSummary) -> String {
+    let mut details = vec![stream.codec.to_string()];
+    if let Some((width, height)) = stream.resolution {
+        details.push(format!("{}×{}", width, height));
+    }
+    if let Some(descriptor) = stream.pixel_format.and_then(|format| format.descriptor()) {
+        details.push(descriptor.name().to_string());
+    }
+    if let Some(frame_rate) = stream.frame_rate {
+        details.push(format!("{:.2} fps", frame_rate));
+    }
+    format!("Video: {}", details.join(", "))
+}
+
+/// A description of an audio stream for the info overlay, like `Audio: aac, 48000 Hz, 2 ch`.
+fn describe_audio_stream(stream: &player::StreamSummary) -> String {
+    let mut details = vec![stream.codec.to_string()];
+    if let Some(sample_rate) = stream.sample_rate {
+        details.push(format!("{} Hz", sample_rate));
+    }
+    if let Some(channels) = stream.channels {
+        details.push(format!("{} ch", channels));
+    }
+    format!("Audio: {}", details.join(", "))
 }
 
 // Work around https://github.com/zmwangx/rust-ffmpeg/issues/102
@@ -64,26 +1983,336 @@ fn main() {
 struct Rescaler(ffmpeg_next::software::scaling::Context);
 unsafe impl std::marker::Send for Rescaler {}
 
-fn rgba_rescaler_for_frame(frame: &ffmpeg_next::util::frame::Video) -> Rescaler {
-    Rescaler(
+/// Converts decoded frames to RGB, creating the rescaler anew only when the frames or the way
+/// they are converted change.
+#[derive(Default)]
+struct RgbConverter {
+    rescaler: Option<Rescaler>,
+    quality: player::VideoQuality,
+    settings: ConversionSettings,
+    colors: Option<(ffmpeg_next::color::Space, ffmpeg_next::color::Range)>,
+}
+
+impl RgbConverter {
+    /// Converts `frame` to an RGB24 frame at `quality` with `settings`.
+    fn convert(
+        &mut self,
+        frame: &ffmpeg_next::util::frame::Video,
+        quality: player::VideoQuality,
+        settings: ConversionSettings,
+    ) -> ffmpeg_next::util::frame::Video {
+        let colors = Some((frame.color_space(), frame.color_range()));
+        let rebuild_rescaler = self.rescaler.as_ref().map_or(true, |existing_rescaler| {
+            let input = existing_rescaler.input();
+            // Switching video streams may change the frame format and size, and so may adaptive
+            // streams and concatenated files in the middle of a stream.
+            input.format != frame.format()
+                || input.width != frame.width()
+                || input.height != frame.height()
+                || self.colors != colors
+                || self.quality != quality
+                || self.settings != settings
+        });
+
+        if rebuild_rescaler {
+            self.rescaler = Some(rgba_rescaler_for_frame(frame, quality, settings));
+            self.colors = colors;
+            self.quality = quality;
+            self.settings = settings;
+        }
+
+        let mut rgb_frame = ffmpeg_next::util::frame::Video::empty();
+        self.rescaler.as_mut().unwrap().run(frame, &mut rgb_frame).unwrap();
+        rgb_frame
+    }
+}
+
+/// Creates a rescaler that converts frames like `frame` to RGB. Frames taller than the maximum
+/// height of `settings` are scaled down proportionally, which makes the conversion and the copy
+/// into the pixel buffer cheaper for large videos.
+fn rgba_rescaler_for_frame(
+    frame: &ffmpeg_next::util::frame::Video,
+    quality: player::VideoQuality,
+    settings: ConversionSettings,
+) -> Rescaler {
+    let max_height = settings.max_height;
+    let (mut flags, scale_divisor) = match quality {
+        player::VideoQuality::Full => (settings.scaling.flags(), 1),
+        player::VideoQuality::Fast => (ffmpeg_next::software::scaling::Flags::FAST_BILINEAR, 1),
+        player::VideoQuality::HalfResolution => (ffmpeg_next::software::scaling::Flags::POINT, 2),
+    };
+    // Sources with more than 8 bits per component, like 10-bit HEVC (YUV420P10LE, or P010LE
+    // from hardware decoders), band visibly when rounded carelessly down to RGB24.
+    if quality == player::VideoQuality::Full && bits_per_component(frame.format()) > 8 {
+        flags |= ffmpeg_next::software::scaling::Flags::ACCURATE_RND
+            | ffmpeg_next::software::scaling::Flags::FULL_CHR_H_INT;
+    }
+
+    let (mut width, mut height) = (frame.width(), frame.height());
+    if max_height > 0 && height > max_height {
+        width = (width as u64 * max_height as u64 / height as u64).max(1) as u32;
+        height = max_height;
+    }
+
+    let mut rescaler = Rescaler(
         ffmpeg_next::software::scaling::Context::get(
             frame.format(),
             frame.width(),
             frame.height(),
             Pixel::RGB24,
-            frame.width(),
-            frame.height(),
-            ffmpeg_next::software::scaling::Flags::BILINEAR,
+            (width / scale_divisor).max(1),
+            (height / scale_divisor).max(1),
+            flags,
         )
         .unwrap(),
-    )
+    );
+    set_source_colorspace(&mut rescaler, frame);
+    rescaler
+}
+
+/// The number of bits of each component of `format`, such as 10 for YUV420P10LE.
+fn bits_per_component(format: Pixel) -> i32 {
+    let descriptor = unsafe { ffmpeg_next::ffi::av_pix_fmt_desc_get(format.into()) };
+    if descriptor.is_null() {
+        return 8;
+    }
+    unsafe { (*descriptor).comp[0].depth }
+}
+
+/// Converts with the YUV matrix and range `frame` was encoded with, rather than swscale's
+/// default of limited range BT.601, which shifts the colors of HD and BT.2020 content.
+/// Unspecified matrices are guessed from the frame size.
+fn set_source_colorspace(rescaler: &mut Rescaler, frame: &ffmpeg_next::util::frame::Video) {
+    let colorspace = match frame.color_space() {
+        ffmpeg_next::color::Space::Unspecified if frame.height() >= 720 => {
+            ffmpeg_next::color::Space::BT709
+        }
+        colorspace => colorspace,
+    };
+    let full_range = i32::from(frame.color_range() == ffmpeg_next::color::Range::JPEG);
+
+    unsafe {
+        let context = rescaler.as_mut_ptr();
+        let mut inverse_table = std::ptr::null_mut();
+        let mut source_range = 0;
+        let mut table = std::ptr::null_mut();
+        let mut destination_range = 0;
+        let (mut brightness, mut contrast, mut saturation) = (0, 0, 0);
+        if ffmpeg_next::ffi::sws_getColorspaceDetails(
+            context,
+            &mut inverse_table,
+            &mut source_range,
+            &mut table,
+            &mut destination_range,
+            &mut brightness,
+            &mut contrast,
+            &mut saturation,
+        ) < 0
+        {
+            // Colorspace details are unsupported for this conversion
+            return;
+        }
+        let coefficients = ffmpeg_next::ffi::sws_getCoefficients(
+            ffmpeg_next::ffi::AVColorSpace::from(colorspace) as i32,
+        );
+        ffmpeg_next::ffi::sws_setColorspaceDetails(
+            context,
+            coefficients,
+            full_range,
+            table,
+            destination_range,
+            brightness,
+            contrast,
+            saturation,
+        );
+    }
+}
+
+/// The width divided by the height `frame` is meant to be shown at. The decoder takes the sample
+/// aspect ratio from the stream parameters; an unknown one means square pixels.
+fn display_aspect_ratio(frame: &ffmpeg_next::util::frame::Video) -> f32 {
+    let sample_aspect_ratio = frame.aspect_ratio();
+    let sample_aspect_ratio =
+        if sample_aspect_ratio.numerator() > 0 && sample_aspect_ratio.denominator() > 0 {
+            f64::from(sample_aspect_ratio)
+        } else {
+            1.
+        };
+    (frame.width() as f64 * sample_aspect_ratio / frame.height().max(1) as f64) as f32
+}
+
+/// The clockwise rotation in degrees (0, 90, 180 or 270) that shows `frame` upright, such as for
+/// videos recorded with a phone held upright. Decoders attach the stream's display matrix to
+/// the frames.
+fn display_rotation(frame: &ffmpeg_next::util::frame::Video) -> u32 {
+    let Some(display_matrix) =
+        frame.side_data(ffmpeg_next::util::frame::side_data::Type::DisplayMatrix)
+    else {
+        return 0;
+    };
+    let matrix = display_matrix.data();
+    if matrix.len() < 9 * std::mem::size_of::<i32>() {
+        return 0;
+    }
+    // Counterclockwise, in degrees
+    let angle = unsafe { ffmpeg_next::ffi::av_display_rotation_get(matrix.as_ptr().cast()) };
+    if !angle.is_finite() {
+        return 0;
+    }
+    ((-angle / 90.).round() as i32).rem_euclid(4) as u32 * 90
+}
+
+/// Rotates `buffer` clockwise by `rotation` degrees, a multiple of 90, into a buffer of `pool`.
+fn rotate_pixel_buffer(
+    buffer: PooledPixelBuffer,
+    rotation: u32,
+    pool: &PixelBufferPool,
+) -> PooledPixelBuffer {
+    if rotation == 0 {
+        return buffer;
+    }
+
+    let (width, height) = (buffer.width(), buffer.height());
+    let rotated_width = if rotation == 180 { width } else { height };
+    let rotated_height = if rotation == 180 { height } else { width };
+    let mut rotated = pool.next_buffer(rotated_width, rotated_height);
+
+    let source = buffer.as_slice();
+    let dest = rotated.make_mut_slice();
+    for y in 0..height {
+        for x in 0..width {
+            let (dest_x, dest_y) = match rotation {
+                90 => (height - 1 - y, x),
+                180 => (width - 1 - x, height - 1 - y),
+                _ => (y, width - 1 - x),
+            };
+            dest[(dest_y * rotated_width + dest_x) as usize] = source[(y * width + x) as usize];
+        }
+    }
+
+    rotated
+}
+
+fn save_png(
+    path: &Path,
+    frame: &slint::SharedPixelBuffer<slint::Rgb8Pixel>,
+) -> Result<(), anyhow::Error> {
+    let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    let mut encoder = png::Encoder::new(file, frame.width(), frame.height());
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(frame.as_bytes())?;
+    Ok(())
+}
+
+/// Recycles the pixel buffers frames are copied into, rather than allocating one per frame.
+/// Buffers are handed out as [`PooledPixelBuffer`]s and only return to the pool once every clone
+/// of those was dropped, so a buffer is never reused while the UI shows it or a screenshot is
+/// being saved from it. Clones share the same buffers.
+#[derive(Clone, Default)]
+struct PixelBufferPool {
+    free: Arc<Mutex<Vec<slint::SharedPixelBuffer<slint::Rgb8Pixel>>>>,
+}
+
+impl PixelBufferPool {
+    /// Enough for the frames being converted and the previews, while the oldest of more free
+    /// buffers, like ones left over from before a resolution change, are released.
+    const MAX_FREE: usize = 4;
+
+    /// A buffer of the given size to copy the next frame into. It still holds an earlier frame.
+    fn next_buffer(&self, width: u32, height: u32) -> PooledPixelBuffer {
+        let mut free = self.free.lock().unwrap();
+        let reused = free
+            .iter()
+            .rposition(|buffer| buffer.width() == width && buffer.height() == height)
+            .map(|index| free.remove(index));
+        PooledPixelBuffer(Arc::new(PixelBufferLease {
+            buffer: reused.unwrap_or_else(|| slint::SharedPixelBuffer::new(width, height)),
+            free: Arc::downgrade(&self.free),
+        }))
+    }
+}
+
+/// A buffer handed out by a [`PixelBufferPool`], which gets it back once every clone of this was
+/// dropped. Images made of it should be dropped no later than that, as writing to a buffer an
+/// image still uses copies it first.
+#[derive(Clone)]
+struct PooledPixelBuffer(Arc<PixelBufferLease>);
+
+impl PooledPixelBuffer {
+    /// The pixels to write the frame to, before the buffer is shared with clones.
+    fn make_mut_slice(&mut self) -> &mut [slint::Rgb8Pixel] {
+        Arc::get_mut(&mut self.0)
+            .expect("pixel buffer written to after sharing")
+            .buffer
+            .make_mut_slice()
+    }
+
+    /// Like [`Self::make_mut_slice`], as bytes.
+    fn make_mut_bytes(&mut self) -> &mut [u8] {
+        Arc::get_mut(&mut self.0)
+            .expect("pixel buffer written to after sharing")
+            .buffer
+            .make_mut_bytes()
+    }
+}
+
+impl std::ops::Deref for PooledPixelBuffer {
+    type Target = slint::SharedPixelBuffer<slint::Rgb8Pixel>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0.buffer
+    }
+}
+
+struct PixelBufferLease {
+    buffer: slint::SharedPixelBuffer<slint::Rgb8Pixel>,
+    free: std::sync::Weak<Mutex<Vec<slint::SharedPixelBuffer<slint::Rgb8Pixel>>>>,
+}
+
+impl Drop for PixelBufferLease {
+    fn drop(&mut self) {
+        let Some(free) = self.free.upgrade() else {
+            // The pool is gone.
+            return;
+        };
+        let mut free = free.lock().unwrap();
+        if free.len() == PixelBufferPool::MAX_FREE {
+            free.remove(0);
+        }
+        free.push(std::mem::replace(&mut self.buffer, slint::SharedPixelBuffer::new(0, 0)));
+    }
+}
+
+/// Draws `peaks`, see [`player::Player::audio_peaks`], as a waveform into a buffer of `pool`,
+/// the newest at the right edge.
+fn draw_waveform(peaks: &[[f32; 2]], pool: &PixelBufferPool) -> PooledPixelBuffer {
+    const BACKGROUND: slint::Rgb8Pixel = slint::Rgb8Pixel { r: 0, g: 0, b: 0 };
+    const WAVE: slint::Rgb8Pixel = slint::Rgb8Pixel { r: 0x4f, g: 0xc3, b: 0xf7 };
+
+    let width = player::WAVEFORM_PEAKS;
+    let height = WAVEFORM_HEIGHT as usize;
+    let mut pixel_buffer = pool.next_buffer(width as u32, WAVEFORM_HEIGHT);
+    let pixels = pixel_buffer.make_mut_slice();
+    pixels.fill(BACKGROUND);
+
+    // Full scale reaches the top and the bottom edge.
+    let row = |sample: f32| ((1. - sample.clamp(-1., 1.)) / 2. * (height - 1) as f32) as usize;
+    let first_column = width.saturating_sub(peaks.len());
+    for (column, [low, high]) in (first_column..width).zip(peaks) {
+        for y in row(*high)..=row(*low) {
+            pixels[y * width + column] = WAVE;
+        }
+    }
+
+    pixel_buffer
 }
 
 fn video_frame_to_pixel_buffer(
     frame: &ffmpeg_next::util::frame::Video,
-) -> slint::SharedPixelBuffer<slint::Rgb8Pixel> {
-    let mut pixel_buffer =
-        slint::SharedPixelBuffer::<slint::Rgb8Pixel>::new(frame.width(), frame.height());
+    pool: &PixelBufferPool,
+) -> PooledPixelBuffer {
+    let mut pixel_buffer = pool.next_buffer(frame.width(), frame.height());
 
     let ffmpeg_line_iter = frame.data(0).chunks_exact(frame.stride(0));
     let slint_pixel_line_iter = pixel_buffer
@@ -96,3 +2325,138 @@ fn video_frame_to_pixel_buffer(
 
     pixel_buffer
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::alloc::{GlobalAlloc, Layout, System};
+
+    const WIDTH: u32 = 320;
+    const HEIGHT: u32 = 180;
+
+    thread_local! {
+        /// Allocations the size of a frame or larger made on this thread, counted by
+        /// [`CountingAllocator`]. Per thread, as other tests convert frames at the same time.
+        static FRAME_ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    /// Counts the allocations of pixel buffers, whether the pool makes them or a buffer that is
+    /// still in use is copied before writing to it.
+    struct CountingAllocator;
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            if layout.size() >= (WIDTH * HEIGHT) as usize * std::mem::size_of::<slint::Rgb8Pixel>()
+            {
+                // Not counted while the thread is torn down.
+                FRAME_ALLOCATIONS.try_with(|count| count.set(count.get() + 1)).ok();
+            }
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    /// Converts ten seconds of 60 fps video, rotated like phone recordings, while the UI holds
+    /// on to the frame it shows and to the one queued for it.
+    #[test]
+    fn pixel_buffers_are_reused_at_frame_rate() {
+        let pool = PixelBufferPool::default();
+        // Each frame held with the image the UI made of it, and the shade it was filled with.
+        let mut held = std::collections::VecDeque::new();
+        let allocations_before = FRAME_ALLOCATIONS.get();
+
+        for frame in 0..600 {
+            let shade = frame as u8;
+            let mut buffer = pool.next_buffer(WIDTH, HEIGHT);
+            buffer.make_mut_slice().fill(slint::Rgb8Pixel { r: shade, g: shade, b: shade });
+            let buffer = rotate_pixel_buffer(buffer, 90, &pool);
+            let image = (*buffer).clone();
+            held.push_back((buffer, image, shade));
+            if held.len() > 2 {
+                held.pop_front();
+            }
+
+            // Reusing buffers never changes a frame that is still in use.
+            for (buffer, image, shade) in &held {
+                assert_eq!(buffer.as_slice()[0].r, *shade);
+                assert_eq!(image.as_slice()[0].r, *shade);
+            }
+        }
+
+        // One to convert into and three to rotate into, instead of two for every frame.
+        let allocations = FRAME_ALLOCATIONS.get() - allocations_before;
+        assert!(allocations <= 4, "{allocations} pixel buffers allocated");
+    }
+
+    /// A 10-bit 4:2:0 frame, mid-gray in limited range.
+    fn ten_bit_frame(width: u32, height: u32) -> ffmpeg_next::util::frame::Video {
+        let mut frame = ffmpeg_next::util::frame::Video::new(Pixel::YUV420P10LE, width, height);
+        for plane in 0..frame.planes() {
+            for sample in frame.data_mut(plane).chunks_exact_mut(2) {
+                sample.copy_from_slice(&512u16.to_le_bytes());
+            }
+        }
+        frame
+    }
+
+    #[test]
+    fn ten_bit_frames_convert_to_rgb() {
+        ffmpeg_next::init().unwrap();
+        let frame = ten_bit_frame(1920, 1080);
+
+        for (quality, max_height, size) in [
+            (player::VideoQuality::Full, 0, (1920, 1080)),
+            (player::VideoQuality::Full, 720, (1280, 720)),
+            (player::VideoQuality::HalfResolution, 0, (960, 540)),
+        ] {
+            let settings = ConversionSettings { max_height, ..Default::default() };
+            let rgb_frame = RgbConverter::default().convert(&frame, quality, settings);
+
+            assert_eq!(rgb_frame.format(), Pixel::RGB24);
+            assert_eq!((rgb_frame.width(), rgb_frame.height()), size);
+            // Gray stays gray.
+            let [r, g, b] = [0, 1, 2].map(|component| rgb_frame.data(0)[component] as i32);
+            assert!((r - g).abs() <= 2 && (g - b).abs() <= 2, "{r}, {g}, {b}");
+        }
+    }
+
+    #[test]
+    fn rescaler_follows_a_change_of_frame_size() {
+        ffmpeg_next::init().unwrap();
+        let mut converter = RgbConverter::default();
+        let settings = ConversionSettings::default();
+
+        let first =
+            converter.convert(&ten_bit_frame(640, 360), player::VideoQuality::Full, settings);
+        assert_eq!((first.width(), first.height()), (640, 360));
+
+        // Like an adaptive stream switching to another rendition.
+        let second =
+            converter.convert(&ten_bit_frame(1280, 720), player::VideoQuality::Full, settings);
+        assert_eq!((second.width(), second.height()), (1280, 720));
+    }
+
+    #[test]
+    fn letting_go_of_the_seek_bar_snaps_to_a_close_keyframe() {
+        let keyframes = [0., 2., 4., 10.];
+        assert_eq!(snap_to_keyframe(3.7, Some(&keyframes)), 4.);
+        assert_eq!(snap_to_keyframe(2.2, Some(&keyframes)), 2.);
+        // Too far from any keyframe.
+        assert_eq!(snap_to_keyframe(7., Some(&keyframes)), 7.);
+        // Not indexed yet.
+        assert_eq!(snap_to_keyframe(3.7, None), 3.7);
+    }
+
+    #[test]
+    fn durations_are_shown_in_hours_minutes_and_seconds() {
+        assert_eq!(format_duration(0.), "0:00");
+        assert_eq!(format_duration(59.6), "1:00");
+        assert_eq!(format_duration(3723.), "1:02:03");
+    }
+}
@@ -0,0 +1,114 @@
+// Copyright © SixtyFPS GmbH <info@slint.dev>
+// SPDX-License-Identifier: MIT
+
+use std::time::Duration;
+
+use slint::ComponentHandle;
+use souvlaki::{MediaControlEvent, MediaMetadata, MediaPlayback, MediaPosition, SeekDirection};
+
+use crate::App;
+
+/// Shows what is playing in the operating system's media controls, MPRIS on Linux and the
+/// System Media Transport Controls on Windows, and lets them and the keyboard's media keys
+/// control playback.
+pub struct MediaControls {
+    controls: souvlaki::MediaControls,
+    /// The title and duration last reported, to only report changes.
+    metadata: Option<(String, f32)>,
+}
+
+impl MediaControls {
+    /// Registers with the media controls, passing their commands on to the callbacks of `app`.
+    /// Windows ties the controls to a window, so this has to wait until the window is shown.
+    /// Their seek buttons jump by `seek_step`, unless they say how far.
+    pub fn new(app: &App, seek_step: Duration) -> Result<Self, anyhow::Error> {
+        let config = souvlaki::PlatformConfig {
+            dbus_name: "ffmpeg_video_player",
+            display_name: "FFmpeg Video Player",
+            hwnd: window_handle(app),
+        };
+        let mut controls = souvlaki::MediaControls::new(config)
+            .map_err(|error| anyhow::anyhow!("Failed to register media controls: {:?}", error))?;
+
+        // Called on a thread of the platform integration.
+        let app_weak = app.as_weak();
+        controls
+            .attach(move |event| {
+                app_weak
+                    .upgrade_in_event_loop(move |app| handle_event(&app, event, seek_step))
+                    .ok();
+            })
+            .map_err(|error| anyhow::anyhow!("Failed to attach media controls: {:?}", error))?;
+
+        Ok(Self { controls, metadata: None })
+    }
+
+    /// Reports the playing input, and whether and where it plays, as shown in `app`.
+    pub fn update(&mut self, app: &App) {
+        let metadata = (app.get_playlist_item().to_string(), app.get_duration());
+        if self.metadata.as_ref() != Some(&metadata) {
+            let (title, duration) = &metadata;
+            self.controls
+                .set_metadata(MediaMetadata {
+                    title: Some(title),
+                    duration: (*duration > 0.).then(|| Duration::from_secs_f32(*duration)),
+                    ..Default::default()
+                })
+                .ok();
+            self.metadata = Some(metadata);
+        }
+
+        let progress = Some(MediaPosition(Duration::from_secs_f32(app.get_position().max(0.))));
+        let playback = if app.get_playing() {
+            MediaPlayback::Playing { progress }
+        } else {
+            MediaPlayback::Paused { progress }
+        };
+        self.controls.set_playback(playback).ok();
+    }
+}
+
+fn handle_event(app: &App, event: MediaControlEvent, seek_step: Duration) {
+    match event {
+        MediaControlEvent::Toggle => app.invoke_toggle_pause_play(),
+        MediaControlEvent::Play if !app.get_playing() => app.invoke_toggle_pause_play(),
+        MediaControlEvent::Pause | MediaControlEvent::Stop if app.get_playing() => {
+            app.invoke_toggle_pause_play()
+        }
+        MediaControlEvent::Next => app.invoke_next(),
+        MediaControlEvent::Previous => app.invoke_previous(),
+        MediaControlEvent::SetPosition(MediaPosition(position)) => {
+            crate::seek_by(app, position.as_secs_f32() - app.get_position())
+        }
+        MediaControlEvent::Seek(direction) => seek_by(app, direction, seek_step),
+        MediaControlEvent::SeekBy(direction, offset) => seek_by(app, direction, offset),
+        _ => {}
+    }
+}
+
+fn seek_by(app: &App, direction: SeekDirection, offset: Duration) {
+    match direction {
+        SeekDirection::Forward => crate::seek_by(app, offset.as_secs_f32()),
+        SeekDirection::Backward => crate::seek_by(app, -offset.as_secs_f32()),
+    }
+}
+
+/// The window handle the System Media Transport Controls are tied to on Windows.
+#[cfg(target_os = "windows")]
+fn window_handle(app: &App) -> Option<*mut std::ffi::c_void> {
+    use slint::winit_030::winit::raw_window_handle::{HasWindowHandle, RawWindowHandle};
+    use slint::winit_030::WinitWindowAccessor;
+
+    app.window()
+        .with_winit_window(|window| match window.window_handle().ok()?.as_raw() {
+            RawWindowHandle::Win32(handle) => Some(handle.hwnd.get() as *mut std::ffi::c_void),
+            _ => None,
+        })
+        .flatten()
+}
+
+/// Only Windows needs a window handle.
+#[cfg(not(target_os = "windows"))]
+fn window_handle(_app: &App) -> Option<*mut std::ffi::c_void> {
+    None
+}
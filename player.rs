@@ -1,139 +1,2137 @@
 // Copyright © SixtyFPS GmbH <info@slint.dev>
 // SPDX-License-Identifier: MIT
 
-use std::path::PathBuf;
+use std::{
+    collections::VecDeque,
+    path::PathBuf,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
 
-use futures::{future::OptionFuture, FutureExt};
+use futures::{
+    future::{Fuse, FusedFuture, LocalBoxFuture},
+    FutureExt,
+};
 
 mod audio;
+mod export;
+mod keyframes;
+mod loudness;
+mod probe;
+mod record;
+mod thumbnails;
 mod video;
 
+pub use audio::{output_device_names, DownmixMatrix, EQUALIZER_BANDS, WAVEFORM_PEAKS};
+pub use export::{export_audio, export_clip, transcode, ClipMode, TranscodeOptions};
+pub use probe::{can_play, Chapter, MediaSummary, StreamSummary};
+pub use thumbnails::FrameExtractor;
+pub use video::{
+    AdaptiveQuality, DeinterlaceMode, PictureAdjustment, SmoothingMode, SyncMode, VideoQuality,
+};
+
 #[derive(Clone, Copy)]
 pub enum ControlCommand {
     Play,
     Pause,
+    SetLoop(bool),
+    /// Loop between two positions (in seconds), or stop doing so with `None`.
+    SetLoopRegion(Option<(f64, f64)>),
+    SetSmoothingMode(SmoothingMode),
+    SetSyncMode(SyncMode),
+    SetAdaptiveQuality(Option<AdaptiveQuality>),
+    SetDisplayRefreshRate(Option<f64>),
+    SetPictureAdjustment(PictureAdjustment),
+    SetDeinterlaceMode(DeinterlaceMode),
+    /// Apply the video filter set with [`Player::set_video_filter`].
+    ChangeVideoFilter,
+    SetDownmix(DownmixMatrix),
+    SetVolume(f32),
+    SetMuted(bool),
+    /// Delay of the audio against the video, in milliseconds.
+    SetAudioDelay(i32),
+    /// Balance of stereo audio, from -1 (left only) to 1 (right only).
+    SetBalance(f32),
+    /// Compress the dynamic range of the audio, for listening at a low volume.
+    SetNightMode(bool),
+    /// Gains of the equalizer bands, in decibels.
+    SetEqualizer([f32; EQUALIZER_BANDS]),
+    /// The loudness (in LUFS) audio is brought to, or `None` to play it at its own loudness.
+    SetLoudnessTarget(Option<f32>),
+    /// The integrated loudness of the playing audio stream, in LUFS, once it was found out.
+    SetTrackLoudness(f32),
+    SetSpeed(f32),
+    BeginScrub,
+    ScrubTo(f64),
+    EndScrub,
+    Seek(f64, SeekMode),
+    /// Switch to decoding the video stream with the given index.
+    SelectVideoStream(usize),
+    /// Switch to playing the audio stream with the given index.
+    SelectAudioStream(usize),
+    /// Restart the audio playback on the output device picked with [`Player::set_audio_device`].
+    ChangeAudioDevice,
+    SetHardwareDecoding(bool),
+    /// Start or stop recording to the file set with [`Player::set_recording`].
+    ChangeRecording,
+}
+
+/// How exactly [`Player::seek`] lands on the requested position.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SeekMode {
+    /// Continue from the closest keyframe at or before the position. This only decodes one
+    /// frame to show, so it is quick, but lands up to a keyframe interval (often a few seconds)
+    /// early.
+    Fast,
+    /// Continue exactly at the position. The frames from the closest keyframe up to it are
+    /// decoded and discarded first, which takes longer for inputs with few keyframes.
+    #[default]
+    Accurate,
+}
+
+/// Messages sent from the demuxer thread to the playback threads, in stream order.
+pub enum PacketMessage {
+    Packet(ffmpeg_next::codec::packet::packet::Packet),
+    /// The demuxer jumped to a new position (in seconds). Decoders drop their buffered state
+    /// and clocks re-base to the new position. Frames before it are decoded, as the frames that
+    /// follow may depend on them, but not played.
+    Flush(f64),
+    /// The input ended. Decoders output the frames they still hold.
+    EndOfStream,
+}
+
+/// Options that apply whenever the player opens an input.
+#[derive(Clone, Debug, Default)]
+pub struct PlayerOptions {
+    pub missing_audio_device: MissingAudioDevice,
+    /// The name of the audio output device to play on, one of [`output_device_names`]. `None`
+    /// (the default) plays on the system's default output device.
+    pub audio_device: Option<String>,
+    /// An FFmpeg filter graph description, like `hqdn3d,unsharp`, that decoded video frames are
+    /// passed through before they reach the frame callback, see [`Player::set_video_filter`].
+    pub video_filter: Option<String>,
+    /// Opens the input paused, showing its first video frame as a poster, instead of playing it
+    /// right away. [`Player::play`] then starts playback from that frame.
+    pub start_paused: bool,
+    /// Fades the audio in over this many milliseconds when playback starts, instead of
+    /// starting at full volume. 0 (the default) disables the fade.
+    pub fade_in_ms: u32,
+    /// Decodes video on the GPU where the platform supports it (VAAPI on Linux, D3D11VA on
+    /// Windows, VideoToolbox on macOS), falling back to software decoding otherwise.
+    pub hardware_decoding: bool,
+    /// Scans the whole input in the background after opening it, to find its video keyframes.
+    /// Once done, [`SeekMode::Fast`] seeks jump straight to the right keyframe and
+    /// [`Player::keyframe_positions`] lists them. Skipped for network and live streams, which
+    /// can't be read ahead.
+    pub keyframe_index: bool,
+    /// Decodes small images of the video at evenly spaced positions in the background after
+    /// opening the input, for [`Player::thumbnail`] to preview seeking. Skipped for network and
+    /// live streams, like the keyframe index.
+    pub seek_thumbnails: bool,
+    pub network: NetworkOptions,
+    /// How many audio frames (one sample for each channel) are buffered for the output device.
+    /// A small buffer makes changes like the volume audible sooner, but runs out when decoding
+    /// is briefly held up, which is heard as crackling, see [`Player::audio_underrun_frames`].
+    /// A large one rides out such hiccups at the cost of that latency. Defaults to
+    /// [`DEFAULT_AUDIO_LATENCY`] at the output device's sample rate.
+    pub audio_buffer_frames: Option<usize>,
+    /// How many decoded video frames may wait for their presentation. Decoding ahead absorbs
+    /// frames that take longer to decode than their interval, at the cost of memory for the
+    /// frames. Defaults to [`DEFAULT_FRAME_QUEUE_DEPTH`].
+    pub frame_queue_depth: Option<usize>,
+}
+
+/// How much audio is buffered for the output device, unless set otherwise.
+pub const DEFAULT_AUDIO_LATENCY: std::time::Duration = std::time::Duration::from_millis(100);
+
+/// How many decoded video frames are queued for presentation, unless set otherwise.
+pub const DEFAULT_FRAME_QUEUE_DEPTH: usize = 4;
+
+/// How long network inputs may stall before a read fails, unless set otherwise.
+pub const DEFAULT_NETWORK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(15);
+
+/// How much of network inputs is read ahead, unless set otherwise.
+pub const DEFAULT_PREBUFFER: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// How many packets each playback thread queues for decoding.
+const PACKET_QUEUE_SIZE: usize = 128;
+
+/// How many packets in a row a decoder may reject as damaged before the playback thread gives up
+/// on its stream. Fewer are skipped, so that damaged downloads stay playable.
+const MAX_CONSECUTIVE_DECODE_ERRORS: u32 = 50;
+
+/// Sends the packets of a stream to its decoder for a playback thread. Damaged packets the
+/// decoder rejects are skipped, and the stream is given up on after
+/// [`MAX_CONSECUTIVE_DECODE_ERRORS`] of them in a row.
+struct DecoderInput {
+    /// The medium of the stream, like "video", for messages.
+    medium: &'static str,
+    /// Packets the decoder rejected in a row.
+    consecutive_errors: u32,
+    /// A packet the decoder had no room for, until its frames are received.
+    pending: Option<ffmpeg_next::Packet>,
+    error_callback: Arc<ErrorCallback>,
+}
+
+impl DecoderInput {
+    fn new(medium: &'static str, error_callback: Arc<ErrorCallback>) -> Self {
+        Self { medium, consecutive_errors: 0, pending: None, error_callback }
+    }
+
+    /// Whether the stream was given up on. Its packets are still to be taken, so that the
+    /// demuxer goes on with the other streams.
+    fn gave_up(&self) -> bool {
+        self.consecutive_errors >= MAX_CONSECUTIVE_DECODE_ERRORS
+    }
+
+    /// Sends `packet` to `decoder`. If the decoder asks for its frames to be received first,
+    /// the packet is kept for [`Self::send_pending`]. Returns `false` if the packet was skipped
+    /// as damaged, leaving no frames to receive.
+    fn send(
+        &mut self,
+        decoder: &mut ffmpeg_next::decoder::Opened,
+        packet: &ffmpeg_next::Packet,
+    ) -> bool {
+        match decoder.send_packet(packet) {
+            Ok(()) => {
+                self.consecutive_errors = 0;
+                true
+            }
+            Err(ffmpeg_next::Error::Other { errno }) if errno == ffmpeg_next::error::EAGAIN => {
+                self.pending = Some(packet.clone());
+                true
+            }
+            Err(error) => {
+                self.skip(error);
+                false
+            }
+        }
+    }
+
+    /// Sends the packet the decoder had no room for, once its frames were received. Returns
+    /// whether there was one, which leaves more frames to receive.
+    fn send_pending(&mut self, decoder: &mut ffmpeg_next::decoder::Opened) -> bool {
+        let Some(packet) = self.pending.take() else { return false };
+        // Having taken the frames, the decoder has room, so failing again is not retried.
+        match decoder.send_packet(&packet) {
+            Ok(()) => {
+                self.consecutive_errors = 0;
+                true
+            }
+            Err(error) => {
+                self.skip(error);
+                false
+            }
+        }
+    }
+
+    /// Skips a packet the decoder rejected, like a damaged one in a partly downloaded file,
+    /// which the decoder picks up again after.
+    fn skip(&mut self, error: ffmpeg_next::Error) {
+        // Noted once per run of damaged packets, as a damaged stretch can be thousands long.
+        if self.consecutive_errors == 0 {
+            eprintln!("Skipping damaged {} packets: {}", self.medium, error);
+        }
+        self.consecutive_errors += 1;
+        if self.gave_up() {
+            (self.error_callback)(format!(
+                "Stopped decoding the {} after {} damaged packets in a row",
+                self.medium, MAX_CONSECUTIVE_DECODE_ERRORS
+            ));
+        }
+    }
+}
+
+/// How to request network inputs, like HTTP URLs. Ignored for files.
+#[derive(Clone, Debug, Default)]
+pub struct NetworkOptions {
+    /// Sent instead of FFmpeg's default `User-Agent` header.
+    pub user_agent: Option<String>,
+    /// Additional HTTP request headers as name and value, such as `Referer` or `Authorization`
+    /// for servers that only deliver to certain clients.
+    pub headers: Vec<(String, String)>,
+    /// Gives up connecting or reading when the server doesn't respond within this time, instead
+    /// of waiting for a stalled connection forever. Defaults to [`DEFAULT_NETWORK_TIMEOUT`].
+    pub timeout: Option<std::time::Duration>,
+    /// How much of the input to read ahead before playback starts, and whenever it caught up
+    /// with the data that arrived, so that it then plays smoothly rather than stuttering while
+    /// packets trickle in. Defaults to [`DEFAULT_PREBUFFER`]. Zero disables buffering.
+    pub prebuffer: Option<std::time::Duration>,
+}
+
+impl NetworkOptions {
+    /// The options in the form FFmpeg's protocols take them when opening an input.
+    pub fn to_dictionary(&self) -> ffmpeg_next::Dictionary<'static> {
+        let mut dictionary = ffmpeg_next::Dictionary::new();
+        if let Some(user_agent) = &self.user_agent {
+            dictionary.set("user_agent", user_agent);
+        }
+        if !self.headers.is_empty() {
+            let headers = self
+                .headers
+                .iter()
+                .map(|(name, value)| format!("{}: {}\r\n", name, value))
+                .collect::<String>();
+            dictionary.set("headers", &headers);
+        }
+        let timeout = self.timeout.unwrap_or(DEFAULT_NETWORK_TIMEOUT).as_micros().to_string();
+        // The connection timeout of the HTTP and TCP protocols, and the read timeout of all.
+        dictionary.set("timeout", &timeout);
+        dictionary.set("rw_timeout", &timeout);
+        dictionary
+    }
+}
+
+/// What to do when there is no audio output device, for example on a headless machine.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MissingAudioDevice {
+    /// Don't play the input at all.
+    Fail,
+    /// Play the video without sound.
+    #[default]
+    PlayMuted,
+}
+
+/// Playback positions reported by the playback threads, for syncing external effects to the
+/// player.
+#[derive(Default)]
+struct PlaybackClock {
+    /// Timestamp of the most recently presented video frame, in seconds.
+    video_time: Mutex<Option<f64>>,
+    /// Told about every new video time.
+    position_changed_callback: Option<Arc<PositionCallback>>,
+    /// The audio position at the last flush, in seconds, and the number of output frames per
+    /// second of the input, which differs from the sample rate when not playing at normal speed.
+    audio_base: Mutex<Option<(f64, f64)>>,
+    /// Number of audio frames handed to the output device since the last flush.
+    audio_frames_played: AtomicU64,
+    /// Number of video frames decoded, and of those presented.
+    decoded_video_frames: AtomicU64,
+    presented_video_frames: AtomicU64,
+    /// Number of video frames skipped because they were decoded too late to present.
+    dropped_video_frames: AtomicU64,
+    /// Number of audio frames the output device played as silence because the audio to play
+    /// wasn't buffered in time, and number of times that happened.
+    audio_underrun_frames: AtomicU64,
+    audio_underruns: AtomicU64,
+    /// Number of audio frames buffered for the output device as of its last callback, and how
+    /// many the buffer holds, which is 0 without audio.
+    audio_buffered_frames: AtomicU64,
+    audio_buffer_capacity: AtomicU64,
+    /// The lowest and the highest sample of each short stretch of the audio the output device
+    /// played last, oldest first, for drawing a waveform.
+    audio_peaks: Mutex<VecDeque<[f32; 2]>>,
+}
+
+impl PlaybackClock {
+    fn video_time(&self) -> Option<f64> {
+        *self.video_time.lock().unwrap()
+    }
+
+    fn set_video_time(&self, time: f64) {
+        *self.video_time.lock().unwrap() = Some(time);
+        if let Some(position_changed_callback) = &self.position_changed_callback {
+            position_changed_callback(time);
+        }
+    }
+
+    fn audio_time(&self) -> Option<f64> {
+        let (base, rate) = (*self.audio_base.lock().unwrap())?;
+        Some(base + self.audio_frames_played.load(Ordering::Relaxed) as f64 / rate)
+    }
+
+    /// Restarts counting played audio at `position` (in seconds), with `rate` output frames per
+    /// second of the input.
+    fn rebase_audio(&self, position: f64, rate: f64) {
+        let mut audio_base = self.audio_base.lock().unwrap();
+        *audio_base = Some((position, rate));
+        self.audio_frames_played.store(0, Ordering::Relaxed);
+    }
+
+    /// Called from the audio output callback, so this must not block.
+    fn add_audio_frames_played(&self, frames: u64) {
+        self.audio_frames_played.fetch_add(frames, Ordering::Relaxed);
+    }
+
+    fn decoded_video_frames(&self) -> u64 {
+        self.decoded_video_frames.load(Ordering::Relaxed)
+    }
+
+    fn add_decoded_video_frame(&self) {
+        self.decoded_video_frames.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn presented_video_frames(&self) -> u64 {
+        self.presented_video_frames.load(Ordering::Relaxed)
+    }
+
+    fn add_presented_video_frame(&self) {
+        self.presented_video_frames.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn dropped_video_frames(&self) -> u64 {
+        self.dropped_video_frames.load(Ordering::Relaxed)
+    }
+
+    fn add_dropped_video_frame(&self) {
+        self.dropped_video_frames.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn audio_underrun_frames(&self) -> u64 {
+        self.audio_underrun_frames.load(Ordering::Relaxed)
+    }
+
+    fn audio_underruns(&self) -> u64 {
+        self.audio_underruns.load(Ordering::Relaxed)
+    }
+
+    /// Called from the audio output callback, so this must not block.
+    fn add_audio_underrun(&self, frames: u64) {
+        self.audio_underrun_frames.fetch_add(frames, Ordering::Relaxed);
+        self.audio_underruns.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn audio_buffer_fill(&self) -> Option<(u64, u64)> {
+        let capacity = self.audio_buffer_capacity.load(Ordering::Relaxed);
+        (capacity > 0).then(|| (self.audio_buffered_frames.load(Ordering::Relaxed), capacity))
+    }
+
+    fn set_audio_buffer_capacity(&self, frames: u64) {
+        self.audio_buffered_frames.store(0, Ordering::Relaxed);
+        self.audio_buffer_capacity.store(frames, Ordering::Relaxed);
+    }
+
+    /// Called from the audio output callback, so this must not block.
+    fn set_audio_buffered_frames(&self, frames: u64) {
+        self.audio_buffered_frames.store(frames, Ordering::Relaxed);
+    }
+
+    fn audio_peaks(&self) -> Vec<[f32; 2]> {
+        self.audio_peaks.lock().unwrap().iter().copied().collect()
+    }
+
+    /// Called from the audio output callback, so this must not block. While the peaks are
+    /// being read, `peaks` are kept to be added along with the next ones.
+    fn add_audio_peaks(&self, peaks: &mut Vec<[f32; 2]>) {
+        let Ok(mut audio_peaks) = self.audio_peaks.try_lock() else { return };
+        for peak in peaks.drain(..) {
+            if audio_peaks.len() == WAVEFORM_PEAKS {
+                audio_peaks.pop_front();
+            }
+            audio_peaks.push_back(peak);
+        }
+    }
 }
 
+type VideoFrameCallback = dyn FnMut(&ffmpeg_next::util::frame::Video, VideoQuality) + Send;
+type PositionCallback = dyn Fn(f64) + Send + Sync;
+type DurationCallback = dyn Fn(Option<f64>) + Send + Sync;
+type BufferingCallback = dyn Fn(bool) + Send + Sync;
+type EndedCallback = dyn Fn() + Send + Sync;
+type ErrorCallback = dyn Fn(String) + Send + Sync;
+
+/// Plays an input on a set of background threads. [`Player::stop`] shuts those threads down
+/// but keeps the player usable: [`Player::play`] then starts the input over from the beginning,
+/// and [`Player::open`] switches to another input, keeping the callbacks and settings.
 pub struct Player {
+    path: PathBuf,
+    options: PlayerOptions,
+    video_frame_callback: Arc<Mutex<Box<VideoFrameCallback>>>,
     control_sender: smol::channel::Sender<ControlCommand>,
     demuxer_thread: Option<std::thread::JoinHandle<()>>,
+    /// The most recent command for each setting, re-applied whenever the input is (re)opened.
+    settings: Vec<ControlCommand>,
     playing: bool,
+    muted: bool,
     playing_changed_callback: Box<dyn Fn(bool)>,
+    position_changed_callback: Arc<PositionCallback>,
+    duration_callback: Arc<DurationCallback>,
+    buffering_callback: Arc<BufferingCallback>,
+    ended_callback: Arc<EndedCallback>,
+    error_callback: Arc<ErrorCallback>,
+    /// Set once the input was played to the end, after which playing starts it over. Replaced
+    /// whenever the demuxer is spawned.
+    ended: Arc<AtomicBool>,
+    /// Replaced with a fresh clock whenever the demuxer is spawned.
+    clock: Arc<PlaybackClock>,
+    /// Replaced along with the clock, which stops building the index of the previous input.
+    keyframe_index: keyframes::SharedKeyframeIndex,
+    /// Replaced along with the clock, like the keyframe index.
+    thumbnails: thumbnails::SharedThumbnails,
+    /// The name of the picked audio output device, shared with the demuxer so that it can
+    /// switch devices while playing.
+    audio_device: Arc<Mutex<Option<String>>>,
+    /// The user's video filter, shared with the video playback thread so that it can be
+    /// changed while playing.
+    video_filter: Arc<Mutex<Option<String>>>,
+    /// The file the input is recorded to, shared with the demuxer.
+    recording: Arc<Mutex<Option<PathBuf>>>,
 }
 
 impl Player {
+    /// Starts playing `path`. Besides every decoded video frame, the callbacks are told when
+    /// playback starts or stops, the timestamp in seconds of each presented video frame, and,
+    /// once the input is opened, its duration in seconds if known. While a network input is
+    /// buffered, see [`NetworkOptions::prebuffer`], the buffering callback is told so, to show
+    /// that playback is waiting for data rather than stuck. The ended callback is called once
+    /// the last frame and the last audio of the input were played. Audio-only inputs present no
+    /// frames, so their position is only available through [`Self::audio_time`]. Problems like
+    /// an input that can't be opened are passed to the error callback as a readable message.
     pub fn start(
         path: PathBuf,
-        video_frame_callback: impl FnMut(&ffmpeg_next::util::frame::Video) + Send + 'static,
+        options: PlayerOptions,
+        video_frame_callback: impl FnMut(&ffmpeg_next::util::frame::Video, VideoQuality)
+            + Send
+            + 'static,
         playing_changed_callback: impl Fn(bool) + 'static,
+        position_changed_callback: impl Fn(f64) + Send + Sync + 'static,
+        duration_callback: impl Fn(Option<f64>) + Send + Sync + 'static,
+        buffering_callback: impl Fn(bool) + Send + Sync + 'static,
+        ended_callback: impl Fn() + Send + Sync + 'static,
+        error_callback: impl Fn(String) + Send + Sync + 'static,
     ) -> Result<Self, anyhow::Error> {
+        let audio_device = Arc::new(Mutex::new(options.audio_device.clone()));
+        let video_filter = Arc::new(Mutex::new(options.video_filter.clone()));
+        let mut player = Self {
+            path,
+            options,
+            video_frame_callback: Arc::new(Mutex::new(Box::new(video_frame_callback))),
+            // Replaced with the channel of the demuxer thread in spawn_demuxer()
+            control_sender: smol::channel::unbounded().0,
+            demuxer_thread: None,
+            settings: Vec::new(),
+            playing: false,
+            muted: false,
+            playing_changed_callback: Box::new(playing_changed_callback),
+            position_changed_callback: Arc::new(position_changed_callback),
+            duration_callback: Arc::new(duration_callback),
+            buffering_callback: Arc::new(buffering_callback),
+            ended_callback: Arc::new(ended_callback),
+            error_callback: Arc::new(error_callback),
+            ended: Default::default(),
+            clock: Default::default(),
+            keyframe_index: Default::default(),
+            thumbnails: Default::default(),
+            audio_device,
+            video_filter,
+            recording: Default::default(),
+        };
+
+        let start_paused = player.options.start_paused;
+        player.spawn_demuxer(start_paused)?;
+        player.set_playing(!start_paused);
+
+        Ok(player)
+    }
+
+    /// Stops playing the current input and starts playing `path` instead, keeping the callbacks
+    /// and settings like the volume and speed. It opens paused with
+    /// [`PlayerOptions::start_paused`], see [`Self::set_start_paused`].
+    pub fn open(&mut self, path: PathBuf) -> Result<(), anyhow::Error> {
+        self.stop();
+        self.path = path;
+        // Recordings, loop regions and audio delays belong to the previous input.
+        *self.recording.lock().unwrap() = None;
+        self.settings.retain(|setting| {
+            !matches!(setting, ControlCommand::SetLoopRegion(_) | ControlCommand::SetAudioDelay(_))
+        });
+        let start_paused = self.options.start_paused;
+        self.spawn_demuxer(start_paused)?;
+        self.set_playing(!start_paused);
+        Ok(())
+    }
+
+    /// Changes [`PlayerOptions::start_paused`] for the inputs opened next with [`Self::open`].
+    pub fn set_start_paused(&mut self, start_paused: bool) {
+        self.options.start_paused = start_paused;
+    }
+
+    /// Resumes playback, or starts the input over from the beginning after [`Self::stop`] or
+    /// once it was played to the end.
+    pub fn play(&mut self) -> Result<(), anyhow::Error> {
+        if self.demuxer_thread.is_some() {
+            // The input is still open, so jump back to its start rather than opening it again.
+            if self.ended.swap(false, Ordering::Relaxed) {
+                self.send_command(ControlCommand::Seek(0., SeekMode::Accurate));
+            }
+            self.send_command(ControlCommand::Play);
+        } else {
+            self.spawn_demuxer(false)?;
+        }
+        self.set_playing(true);
+        Ok(())
+    }
+
+    pub fn pause(&mut self) {
+        if self.demuxer_thread.is_some() {
+            self.send_command(ControlCommand::Pause);
+            self.set_playing(false);
+        }
+    }
+
+    /// Stops playback and shuts down the playback threads.
+    pub fn stop(&mut self) {
+        self.control_sender.close();
+        if let Some(demuxer_thread) = self.demuxer_thread.take() {
+            demuxer_thread.join().unwrap();
+        }
+        if self.playing {
+            self.set_playing(false);
+        }
+    }
+
+    /// Whether the input was played to the end, see [`Self::play`].
+    pub fn is_ended(&self) -> bool {
+        self.ended.load(Ordering::Relaxed)
+    }
+
+    /// Whether playback is running, rather than paused.
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    pub fn toggle_pause_playing(&mut self) {
+        if self.playing && !self.is_ended() {
+            self.pause();
+        } else if let Err(error) = self.play() {
+            (self.error_callback)(format!("Error restarting playback: {}", error));
+        }
+    }
+
+    /// Enables or disables looping. When enabled, playback wraps around to the start once the
+    /// end of the input is reached instead of finishing.
+    pub fn set_loop(&mut self, enabled: bool) {
+        self.send_setting(ControlCommand::SetLoop(enabled));
+    }
+
+    /// Repeats the section between `start` and `end` (in seconds) of the input, for example to
+    /// practice a passage of music, until it is cleared with `None`. The region is kept while
+    /// paused, but not when another input is opened.
+    pub fn set_loop_region(&mut self, region: Option<(f64, f64)>) {
+        self.send_setting(ControlCommand::SetLoopRegion(region));
+    }
+
+    /// Selects how video frames are paced against their timestamps. Defaults to
+    /// [`SmoothingMode::Off`], which preserves the exact timing of the stream.
+    pub fn set_smoothing_mode(&mut self, mode: SmoothingMode) {
+        self.send_setting(ControlCommand::SetSmoothingMode(mode));
+    }
+
+    /// Selects the clock video frames are presented against. Defaults to
+    /// [`SyncMode::AudioMaster`], which keeps the video in sync with the audio.
+    pub fn set_sync_mode(&mut self, mode: SyncMode) {
+        self.send_setting(ControlCommand::SetSyncMode(mode));
+    }
+
+    /// Changes the brightness, contrast and saturation of the video while it plays. Frames that
+    /// were already decoded are shown unchanged, so this takes effect within a few frames.
+    /// [`PictureAdjustment::default`] restores the original picture.
+    pub fn set_picture_adjustment(&mut self, adjustment: PictureAdjustment) {
+        self.send_setting(ControlCommand::SetPictureAdjustment(adjustment));
+    }
+
+    /// Selects when interlaced video, like broadcast captures, is deinterlaced to remove the
+    /// combing on moving edges. Defaults to [`DeinterlaceMode::Auto`], which leaves progressive
+    /// video alone.
+    pub fn set_deinterlace_mode(&mut self, mode: DeinterlaceMode) {
+        self.send_setting(ControlCommand::SetDeinterlaceMode(mode));
+    }
+
+    /// Opts into lowering the [`VideoQuality`] requested from the frame callback while frames
+    /// can't be decoded and converted within their frame interval, and restoring it once there
+    /// is headroom again. Pass `None` to always render at full quality (the default).
+    pub fn set_adaptive_quality(&mut self, settings: Option<AdaptiveQuality>) {
+        self.send_setting(ControlCommand::SetAdaptiveQuality(settings));
+    }
+
+    /// Aligns the presentation of video frames to the refresh interval of a display running at
+    /// `rate` Hz, so that each frame stays on screen for a whole number of refreshes. Frames
+    /// that are presented in between two refreshes otherwise show for a varying number of
+    /// them, which is visible as judder: 24 fps content on a 60 Hz display, for example, then
+    /// follows a steady 3:2 pulldown cadence instead of an irregular one. Pass `None` to present
+    /// frames exactly at their timestamps (the default).
+    pub fn set_display_refresh_rate(&mut self, rate: Option<f64>) {
+        self.send_setting(ControlCommand::SetDisplayRefreshRate(rate));
+    }
+
+    /// Starts scrubbing: audio is muted and frames are only decoded to preview the positions
+    /// passed to [`Self::scrub_to`], until [`Self::end_scrub`] resumes normal playback there.
+    pub fn begin_scrub(&mut self) {
+        self.send_command(ControlCommand::BeginScrub);
+    }
+
+    /// Shows the frame at `position` (in seconds) while scrubbing.
+    pub fn scrub_to(&mut self, position: f64) {
+        self.send_command(ControlCommand::ScrubTo(position));
+    }
+
+    pub fn end_scrub(&mut self) {
+        self.ended.store(false, Ordering::Relaxed);
+        self.send_command(ControlCommand::EndScrub);
+    }
+
+    /// Continues playback at `position` (in seconds), or at the keyframe before it with
+    /// [`SeekMode::Fast`]. Positions before the start are clamped to the start, and seeking to
+    /// the end finishes playback, or wraps around to the start when looping.
+    pub fn seek(&mut self, position: f64, mode: SeekMode) {
+        self.ended.store(false, Ordering::Relaxed);
+        self.send_command(ControlCommand::Seek(position, mode));
+    }
+
+    /// Switches to another video stream of the input, such as another camera angle, by its index
+    /// in [`MediaSummary::streams`]. Audio keeps playing, and the new stream is shown from its
+    /// next keyframe on. Indices that don't refer to a decodable video stream are ignored.
+    pub fn select_video_stream(&mut self, index: usize) {
+        self.send_command(ControlCommand::SelectVideoStream(index));
+    }
+
+    /// Switches video decoding between the GPU and the CPU, see
+    /// [`PlayerOptions::hardware_decoding`]. The video continues from its next keyframe.
+    pub fn set_hardware_decoding(&mut self, enabled: bool) {
+        self.options.hardware_decoding = enabled;
+        self.send_command(ControlCommand::SetHardwareDecoding(enabled));
+    }
+
+    /// Switches to another audio stream of the input, such as another language, by its index in
+    /// [`MediaSummary::streams`]. Playback continues at the current position with the new
+    /// stream. Indices that don't refer to an audio stream are ignored.
+    pub fn select_audio_stream(&mut self, index: usize) {
+        self.send_command(ControlCommand::SelectAudioStream(index));
+    }
+
+    /// Sets the levels used when downmixing a source with more channels than the audio output,
+    /// such as 5.1 audio on stereo speakers. See [`DownmixMatrix::DIALOG_BOOST`] to make dialog
+    /// more prominent.
+    pub fn set_downmix(&mut self, matrix: DownmixMatrix) {
+        self.send_setting(ControlCommand::SetDownmix(matrix));
+    }
+
+    /// The timestamp of the most recently presented video frame, in seconds, or `None` before
+    /// the first frame.
+    pub fn video_time(&self) -> Option<f64> {
+        self.clock.video_time()
+    }
+
+    /// The position of the audio that was handed to the output device so far, in seconds, or
+    /// `None` when the input is played without sound. Video frames follow it with
+    /// [`SyncMode::AudioMaster`], the default, and are paced by the wall clock with
+    /// [`SyncMode::Wallclock`]. Either way, this is the authoritative position to sync external
+    /// effects to: it is what the listener hears.
+    pub fn audio_time(&self) -> Option<f64> {
+        self.clock.audio_time()
+    }
+
+    /// The positions in seconds of the keyframes of the video stream, or `None` until they were
+    /// indexed, see [`PlayerOptions::keyframe_index`].
+    pub fn keyframe_positions(&self) -> Option<Vec<f64>> {
+        self.keyframe_index.lock().unwrap().as_ref().map(|index| index.positions())
+    }
+
+    /// A small RGB image of the video close to `position` (in seconds), or `None` until one was
+    /// generated, see [`PlayerOptions::seek_thumbnails`].
+    pub fn thumbnail(&self, position: f64) -> Option<ffmpeg_next::util::frame::Video> {
+        self.thumbnails.lock().unwrap().nearest(position).cloned()
+    }
+
+    /// The number of video frames of the current input that were decoded, for diagnostics.
+    /// Sampled over time, it tells the rate decoding keeps up.
+    pub fn decoded_video_frames(&self) -> u64 {
+        self.clock.decoded_video_frames()
+    }
+
+    /// The number of video frames of the current input that were passed to the frame callback,
+    /// for diagnostics. Sampled over time, it tells the frame rate actually shown.
+    pub fn presented_video_frames(&self) -> u64 {
+        self.clock.presented_video_frames()
+    }
+
+    /// The number of video frames of the current input that were skipped because decoding
+    /// them fell behind, for diagnostics.
+    pub fn dropped_video_frames(&self) -> u64 {
+        self.clock.dropped_video_frames()
+    }
+
+    /// The number of audio frames (one sample for each channel) of the current input that the
+    /// output device played as silence because decoding didn't keep up, for diagnostics. A
+    /// growing count, heard as crackling, calls for a larger
+    /// [`PlayerOptions::audio_buffer_frames`].
+    pub fn audio_underrun_frames(&self) -> u64 {
+        self.clock.audio_underrun_frames()
+    }
+
+    /// The number of times the output device ran out of audio of the current input to play,
+    /// each of which played [`Self::audio_underrun_frames`] as silence, for diagnostics.
+    pub fn audio_underruns(&self) -> u64 {
+        self.clock.audio_underruns()
+    }
+
+    /// How many audio frames are buffered for the output device, and how many the buffer holds,
+    /// see [`PlayerOptions::audio_buffer_frames`]. `None` when no audio is playing. A buffer
+    /// that is mostly empty while playing is about to run out.
+    pub fn audio_buffer_fill(&self) -> Option<(u64, u64)> {
+        self.clock.audio_buffer_fill()
+    }
+
+    /// The lowest and the highest sample, from -1 to 1, of each of the last [`WAVEFORM_PEAKS`]
+    /// short stretches of the audio the output device played, oldest first, to draw a waveform
+    /// of. Fewer right after the audio started, and none without audio.
+    pub fn audio_peaks(&self) -> Vec<[f32; 2]> {
+        self.clock.audio_peaks()
+    }
+
+    /// Sets the audio volume level: 0 is silent and 1 (the default) plays the audio unchanged.
+    /// Levels in between follow a perceptual curve, so that they can be taken straight from a
+    /// volume slider. Larger values amplify the audio linearly, and a soft limiter compresses
+    /// the peaks that would exceed full scale, rather than clipping them.
+    pub fn set_volume(&mut self, volume: f32) {
+        self.send_setting(ControlCommand::SetVolume(volume));
+    }
+
+    /// Shifts stereo audio towards the left (-1 plays only the left channel) or the right (1
+    /// plays only the right channel). The default of 0 plays both channels unchanged. Output
+    /// with another number of channels isn't affected.
+    pub fn set_balance(&mut self, balance: f32) {
+        self.send_setting(ControlCommand::SetBalance(balance));
+    }
+
+    /// Evens out quiet and loud passages of the audio with a compressor, so that dialogue can be
+    /// followed at a low volume without loud scenes blasting. Off by default.
+    pub fn set_night_mode(&mut self, enabled: bool) {
+        self.send_setting(ControlCommand::SetNightMode(enabled));
+    }
+
+    /// Turns the bands of the graphic equalizer, centred at 60 Hz, 250 Hz, 1 kHz, 4 kHz and
+    /// 12 kHz, up or down by `gains` decibels, from -12 to 12. The change is heard right away,
+    /// without a gap. All zeros, the default, leaves the audio unchanged.
+    pub fn set_equalizer(&mut self, gains: [f32; EQUALIZER_BANDS]) {
+        self.send_setting(ControlCommand::SetEqualizer(gains));
+    }
+
+    /// Turns each input's audio up or down to the integrated loudness `target` (in LUFS, like
+    /// -18), so that the volume doesn't jump between the inputs of a playlist, or leaves it
+    /// alone with `None`, the default. The loudness is taken from ReplayGain tags, or measured
+    /// with EBU R128 on a background thread when a local file has none, in which case the gain
+    /// applies once the measurement is done.
+    pub fn set_loudness_target(&mut self, target: Option<f32>) {
+        self.send_setting(ControlCommand::SetLoudnessTarget(target));
+    }
+
+    /// Silences the audio without changing the volume, so that unmuting restores it.
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+        self.send_setting(ControlCommand::SetMuted(muted));
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.muted
+    }
+
+    /// Moves the audio playback to the output device called `name`, one of
+    /// [`output_device_names`], or to the system's default output device for `None`. Playback
+    /// continues at the current position.
+    pub fn set_audio_device(&mut self, name: Option<String>) {
+        *self.audio_device.lock().unwrap() = name;
+        self.send_command(ControlCommand::ChangeAudioDevice);
+    }
+
+    /// Passes the video through the FFmpeg filter graph described by `filter`, like
+    /// `hqdn3d,unsharp` or `-vf` of the `ffmpeg` tool, before the frame callback, or stops
+    /// filtering with `None`. Descriptions that can't be parsed are rejected with an error,
+    /// keeping the previous filter. A filter that turns out not to work on the frames of the
+    /// input is skipped.
+    pub fn set_video_filter(&mut self, filter: Option<String>) -> Result<(), anyhow::Error> {
+        if let Some(filter) = &filter {
+            video::check_video_filter(filter)
+                .map_err(|error| anyhow::anyhow!("Invalid video filter {:?}: {}", filter, error))?;
+        }
+        *self.video_filter.lock().unwrap() = filter;
+        self.send_command(ControlCommand::ChangeVideoFilter);
+        Ok(())
+    }
+
+    /// Records the input to `path` while it plays, or stops recording with `None`, which
+    /// finalizes the file. The packets are copied as they are read, without re-encoding, into
+    /// the container the extension of `path` names. Recording starts at the next keyframe of
+    /// the video, and includes what is played, so it is meant for live streams that play
+    /// straight through. Problems are passed to the error callback, ending the recording.
+    pub fn set_recording(&mut self, path: Option<PathBuf>) {
+        *self.recording.lock().unwrap() = path;
+        self.send_command(ControlCommand::ChangeRecording);
+    }
+
+    /// Plays the audio `milliseconds` later than the video, or earlier for negative values, to
+    /// correct inputs whose audio and video are out of sync. Changes take effect right away,
+    /// so the delay can be adjusted while watching until the two match.
+    pub fn set_audio_delay(&mut self, milliseconds: i32) {
+        self.send_setting(ControlCommand::SetAudioDelay(milliseconds));
+    }
+
+    /// Plays the input faster or slower, for example 1.5 to play it at one and a half times the
+    /// normal speed. The audio keeps its pitch, so speech stays natural to listen to.
+    pub fn set_speed(&mut self, speed: f32) {
+        self.send_setting(ControlCommand::SetSpeed(speed));
+    }
+
+    /// The input that is playing.
+    pub fn path(&self) -> &std::path::Path {
+        &self.path
+    }
+
+    /// The current playback position in seconds: the [`Self::audio_time`] when there is audio,
+    /// otherwise the [`Self::video_time`].
+    pub fn position(&self) -> Option<f64> {
+        self.audio_time().or_else(|| self.video_time())
+    }
+
+    /// Opens the input on a new demuxer thread, paused on its first frame with `start_paused`.
+    fn spawn_demuxer(&mut self, start_paused: bool) -> Result<(), anyhow::Error> {
         let (control_sender, control_receiver) = smol::channel::unbounded();
 
+        for setting in &self.settings {
+            control_sender.send_blocking(*setting).unwrap();
+        }
+
+        let path = self.path.clone();
+        let options = self.options.clone();
+        let video_frame_callback = self.video_frame_callback.clone();
+        let duration_callback = self.duration_callback.clone();
+        let buffering_callback = self.buffering_callback.clone();
+        let ended = Arc::new(AtomicBool::new(false));
+        let ended_callback: Arc<EndedCallback> = Arc::new({
+            let ended = ended.clone();
+            let ended_callback = self.ended_callback.clone();
+            move || {
+                ended.store(true, Ordering::Relaxed);
+                ended_callback();
+            }
+        });
+        let error_callback = self.error_callback.clone();
+        let clock = Arc::new(PlaybackClock {
+            position_changed_callback: Some(self.position_changed_callback.clone()),
+            ..Default::default()
+        });
+        let demuxer_clock = clock.clone();
+        let keyframe_index = keyframes::SharedKeyframeIndex::default();
+        let demuxer_keyframe_index = keyframe_index.clone();
+        let thumbnails = thumbnails::SharedThumbnails::default();
+        let demuxer_thumbnails = thumbnails.clone();
+        let audio_device = self.audio_device.clone();
+        let video_filter = self.video_filter.clone();
+        let recording = self.recording.clone();
+
         let demuxer_thread =
             std::thread::Builder::new().name("demuxer thread".into()).spawn(move || {
-                smol::block_on(async move {
-                    let mut input_context = ffmpeg_next::format::input(&path).unwrap();
-
-                    let video_stream =
-                        input_context.streams().best(ffmpeg_next::media::Type::Video).unwrap();
-                    let video_stream_index = video_stream.index();
-                    let video_playback_thread = video::VideoPlaybackThread::start(
-                        &video_stream,
-                        Box::new(video_frame_callback),
-                    )
-                    .unwrap();
-
-                    let audio_stream =
-                        input_context.streams().best(ffmpeg_next::media::Type::Audio).unwrap();
-                    let audio_stream_index = audio_stream.index();
-                    let audio_playback_thread =
-                        audio::AudioPlaybackThread::start(&audio_stream).unwrap();
-
-                    let mut playing = true;
-
-                    // This is sub-optimal, as reading the packets from ffmpeg might be blocking
-                    // and the future won't yield for that. So while ffmpeg sits on some blocking
-                    // I/O operation, the caller here will also block and we won't end up polling
-                    // the control_receiver future further down.
-                    let packet_forwarder_impl = async {
-                        for (stream, packet) in input_context.packets() {
-                            if stream.index() == audio_stream_index {
-                                audio_playback_thread.receive_packet(packet).await;
-                            } else if stream.index() == video_stream_index {
-                                video_playback_thread.receive_packet(packet).await;
-                            }
+                smol::block_on(run_demuxer(
+                    path,
+                    options,
+                    start_paused,
+                    video_frame_callback,
+                    demuxer_clock,
+                    demuxer_keyframe_index,
+                    demuxer_thumbnails,
+                    audio_device,
+                    video_filter,
+                    recording,
+                    duration_callback,
+                    buffering_callback,
+                    ended_callback,
+                    error_callback,
+                    control_receiver,
+                ))
+            })?;
+
+        self.clock = clock;
+        self.keyframe_index = keyframe_index;
+        self.thumbnails = thumbnails;
+        self.ended = ended;
+        self.control_sender = control_sender;
+        self.demuxer_thread = Some(demuxer_thread);
+
+        Ok(())
+    }
+
+    fn set_playing(&mut self, playing: bool) {
+        self.playing = playing;
+        (self.playing_changed_callback)(playing);
+    }
+
+    fn send_command(&self, command: ControlCommand) {
+        // While stopped the channel is closed and there is nothing to control.
+        self.control_sender.send_blocking(command).ok();
+    }
+
+    fn send_setting(&mut self, command: ControlCommand) {
+        self.settings
+            .retain(|setting| std::mem::discriminant(setting) != std::mem::discriminant(&command));
+        self.settings.push(command);
+        self.send_command(command);
+    }
+}
+
+async fn run_demuxer(
+    path: PathBuf,
+    options: PlayerOptions,
+    start_paused: bool,
+    video_frame_callback: Arc<Mutex<Box<VideoFrameCallback>>>,
+    clock: Arc<PlaybackClock>,
+    keyframe_index: keyframes::SharedKeyframeIndex,
+    thumbnails: thumbnails::SharedThumbnails,
+    audio_device: Arc<Mutex<Option<String>>>,
+    video_filter: Arc<Mutex<Option<String>>>,
+    recording: Arc<Mutex<Option<PathBuf>>>,
+    duration_callback: Arc<DurationCallback>,
+    buffering_callback: Arc<BufferingCallback>,
+    ended_callback: Arc<EndedCallback>,
+    error_callback: Arc<ErrorCallback>,
+    control_receiver: smol::channel::Receiver<ControlCommand>,
+) {
+    let mut input_context =
+        match ffmpeg_next::format::input_with_dictionary(&path, options.network.to_dictionary()) {
+            Ok(input_context) => input_context,
+            Err(error) => {
+                error_callback(format!("Failed to open {}: {}", path.display(), error));
+                return;
+            }
+        };
+
+    let is_url = path.to_str().is_some_and(|path| path.contains("://"));
+    // Reconnection attempts since the last packet that could be read from a network input.
+    let mut reconnect_attempts = 0;
+
+    let start_video_playback_thread =
+        |stream: &ffmpeg_next::format::stream::Stream, hardware_decoding: bool| {
+            let video_frame_callback = video_frame_callback.clone();
+            video::VideoPlaybackThread::start(
+                stream,
+                Box::new(move |frame: &ffmpeg_next::util::frame::Video, quality| {
+                    (video_frame_callback.lock().unwrap())(frame, quality)
+                }),
+                clock.clone(),
+                hardware_decoding,
+                options.frame_queue_depth.unwrap_or(DEFAULT_FRAME_QUEUE_DEPTH),
+                video_filter.clone(),
+                error_callback.clone(),
+            )
+        };
+    let mut hardware_decoding = options.hardware_decoding;
+
+    // Audio-only inputs, like music, have no video stream.
+    let video_stream = input_context.streams().best(ffmpeg_next::media::Type::Video);
+    let mut video_stream_index = video_stream.as_ref().map(|stream| stream.index());
+    // Shared with the packet forwarder, so that it can be replaced when switching streams.
+    let mut video_playback_thread =
+        match video_stream.map(|stream| start_video_playback_thread(&stream, hardware_decoding)) {
+            Some(Ok(video_playback_thread)) => Some(Rc::new(video_playback_thread)),
+            Some(Err(error)) => {
+                error_callback(format!("Failed to start video playback: {}", error));
+                return;
+            }
+            None => None,
+        };
+
+    let open_audio_device = || audio::output_device(audio_device.lock().unwrap().as_deref());
+    let start_audio_playback_thread = |stream: &ffmpeg_next::format::stream::Stream,
+                                       device: cpal::Device| {
+        audio::AudioPlaybackThread::start(
+            stream,
+            device,
+            options.audio_buffer_frames,
+            std::time::Duration::from_millis(options.fade_in_ms.into()),
+            clock.clone(),
+            error_callback.clone(),
+        )
+    };
+
+    // Silent videos and GIF-like clips have no audio stream, and are paced by the video alone.
+    let audio_stream = input_context.streams().best(ffmpeg_next::media::Type::Audio);
+    let mut audio_stream_index = audio_stream.as_ref().map(|stream| stream.index());
+    if video_playback_thread.is_none() && audio_stream.is_none() {
+        error_callback(format!("{} has neither a video nor an audio stream", path.display()));
+        return;
+    }
+    // Shared with the packet forwarder, so that it can be replaced when switching streams.
+    let mut audio_playback_thread = match audio_stream {
+        Some(audio_stream) => match open_audio_device() {
+            Some(device) => match start_audio_playback_thread(&audio_stream, device) {
+                Ok(audio_playback_thread) => Some(Rc::new(audio_playback_thread)),
+                Err(error) => {
+                    error_callback(format!("Failed to start audio playback: {}", error));
+                    return;
+                }
+            },
+            None => match options.missing_audio_device {
+                MissingAudioDevice::Fail => {
+                    error_callback("No audio output device".into());
+                    return;
+                }
+                MissingAudioDevice::PlayMuted => {
+                    // The video thread paces itself, so it doesn't need the audio output.
+                    error_callback("No audio output device, playing muted".into());
+                    None
+                }
+            },
+        },
+        None => None,
+    };
+
+    let duration = (input_context.duration() > 0)
+        .then(|| input_context.duration() as f64 / f64::from(ffmpeg_next::ffi::AV_TIME_BASE));
+    duration_callback(duration);
+
+    // Live streams have no duration, and reading a network stream twice would double the
+    // traffic.
+    if let Some(index) =
+        video_stream_index.filter(|_| options.keyframe_index && duration.is_some() && !is_url)
+    {
+        keyframes::spawn_indexer(path.clone(), index, &keyframe_index);
+    }
+    if let Some((index, duration)) =
+        video_stream_index.zip(duration).filter(|_| options.seek_thumbnails && !is_url)
+    {
+        thumbnails::spawn_generator(path.clone(), index, duration, &thumbnails);
+    }
+
+    // Network inputs are buffered before playback starts, and again whenever a playback thread
+    // ran out of packets, with the playback threads paused until enough are queued.
+    let prebuffer = options.network.prebuffer.unwrap_or(DEFAULT_PREBUFFER).as_secs_f64();
+    let prebuffering = is_url && prebuffer > 0.;
+    let mut buffering = false;
+    // The positions (in seconds) of the earliest and the latest packet read while buffering.
+    let mut buffered_range: Option<(f64, f64)> = None;
+    if prebuffering {
+        control_playback(
+            video_playback_thread.as_deref(),
+            audio_playback_thread.as_deref(),
+            ControlCommand::Pause,
+        )
+        .await;
+        buffering = true;
+        buffering_callback(true);
+    }
+
+    let mut playing = true;
+    let mut looping = false;
+    let mut finished = false;
+
+    let mut loop_region: Option<(f64, f64)> = None;
+    // After jumping back to the start of the loop region, the position only reflects the jump
+    // once the playback threads catch up.
+    let mut awaiting_loop_start = false;
+
+    let mut scrubbing = false;
+    // The position the last scrub preview was decoded for.
+    let mut scrub_position = None;
+    // The latest requested scrub position, applied once the current preview
+    // has been presented, so that rapid scrubbing doesn't queue up seeks.
+    let mut pending_scrub_position = None;
+    let mut reading_preview = false;
+
+    // Settings of the video playback thread, re-applied when switching video streams.
+    let mut video_settings: Vec<ControlCommand> = Vec::new();
+    // Settings of the audio playback thread, re-applied when switching audio streams.
+    let mut audio_settings: Vec<ControlCommand> = Vec::new();
+    // The loudness of the played audio stream is only found out once loudness normalization is
+    // turned on, as measuring it decodes all of the audio.
+    let mut loudness_target: Option<f32> = None;
+    let mut loudness_receiver: Option<smol::channel::Receiver<f32>> = None;
+    // After switching video streams, packets are skipped until the new stream's next keyframe,
+    // as the decoder can't start in between.
+    let mut awaiting_video_keyframe = false;
+
+    // Resolves to whether the input played out, which only the future from `play_out` does.
+    let mut packet_forwarder: Fuse<LocalBoxFuture<'_, bool>> = Fuse::terminated();
+
+    // Dropping it finalizes the recording, also when the demuxer stops.
+    let mut recorder: Option<record::Recorder> = None;
+
+    // Opening paused shows the first frame as a poster. It is decoded like a scrub preview,
+    // which doesn't start playback, and the scrub ends once it was presented.
+    let mut showing_poster = false;
+    if start_paused {
+        playing = false;
+        control_playback(
+            video_playback_thread.as_deref(),
+            audio_playback_thread.as_deref(),
+            ControlCommand::Pause,
+        )
+        .await;
+        if let Some(video_playback_thread) = &video_playback_thread {
+            video_playback_thread.send_control_message(ControlCommand::BeginScrub).await;
+            scrubbing = true;
+            pending_scrub_position = Some(0.);
+            showing_poster = true;
+        }
+    }
+
+    loop {
+        // Previews need video, so without it scrubbing only seeks once it ends.
+        if let Some(video_playback_thread) =
+            video_playback_thread.as_ref().filter(|_| scrubbing && !reading_preview)
+        {
+            if let Some(position) = pending_scrub_position.take() {
+                if seek_input(&mut input_context, position).is_ok() {
+                    packet_forwarder = Fuse::terminated();
+                    video_playback_thread.flush(position).await;
+                    scrub_position = Some(position);
+                    reading_preview = true;
+                }
+            }
+        }
+
+        if let Some((start, end)) = loop_region.filter(|_| playing && !scrubbing) {
+            let position = clock.audio_time().or_else(|| clock.video_time()).unwrap_or_default();
+            if awaiting_loop_start {
+                awaiting_loop_start = position >= end;
+            } else if position >= end && seek_input(&mut input_context, start).is_ok() {
+                packet_forwarder = Fuse::terminated();
+                flush_playback(
+                    video_playback_thread.as_deref(),
+                    audio_playback_thread.as_deref(),
+                    start,
+                )
+                .await;
+                finished = false;
+                awaiting_loop_start = true;
+            }
+        }
+
+        let reading = if scrubbing { reading_preview } else { playing && !finished };
+
+        // This is sub-optimal, as reading the packets from ffmpeg might be blocking
+        // and the future won't yield for that. So while ffmpeg sits on some blocking
+        // I/O operation, the caller here will also block and we won't end up polling
+        // the control_receiver future further down.
+        if reading && packet_forwarder.is_terminated() {
+            let mut packet = ffmpeg_next::Packet::empty();
+            match packet.read(&mut input_context) {
+                Ok(()) => {
+                    reconnect_attempts = 0;
+                    // Previews only need the video stream.
+                    if scrubbing && Some(packet.stream()) != video_stream_index {
+                        continue;
+                    }
+                    if awaiting_video_keyframe && Some(packet.stream()) == video_stream_index {
+                        if !packet.is_key() {
+                            continue;
                         }
+                        awaiting_video_keyframe = false;
+                    }
+                    // Scrub previews jump around, so they aren't recorded.
+                    if let Some(error) = recorder
+                        .as_mut()
+                        .filter(|_| !scrubbing)
+                        .and_then(|recorder| recorder.write(&packet).err())
+                    {
+                        error_callback(format!("Stopped recording: {}", error));
+                        recorder = None;
                     }
-                    .fuse()
-                    .shared();
-
-                    loop {
-                        // This is sub-optimal, as reading the packets from ffmpeg might be blocking
-                        // and the future won't yield for that. So while ffmpeg sits on some blocking
-                        // I/O operation, the caller here will also block and we won't end up polling
-                        // the control_receiver future further down.
-                        let packet_forwarder: OptionFuture<_> =
-                            if playing { Some(packet_forwarder_impl.clone()) } else { None }.into();
-
-                        smol::pin!(packet_forwarder);
-
-                        futures::select! {
-                            _ = packet_forwarder => {}, // playback finished
-                            received_command = control_receiver.recv().fuse() => {
-                                match received_command {
-                                    Ok(command) => {
-                                        video_playback_thread.send_control_message(command).await;
-                                        audio_playback_thread.send_control_message(command).await;
-                                        match command {
-                                            ControlCommand::Play => {
-                                                // Continue in the loop, polling the packet forwarder future to forward
-                                                // packets
-                                                playing = true;
-                                            },
-                                            ControlCommand::Pause => {
-                                                playing = false;
-                                            }
-                                        }
-                                    }
-                                    Err(_) => {
-                                        // Channel closed -> quit
-                                        return;
-                                    }
-                                }
+                    let played_stream = Some(packet.stream()) == video_stream_index
+                        || Some(packet.stream()) == audio_stream_index;
+                    if prebuffering && !scrubbing && played_stream {
+                        let queued_packets = video_playback_thread
+                            .iter()
+                            .map(|thread| thread.queued_packets())
+                            .chain(
+                                audio_playback_thread.iter().map(|thread| thread.queued_packets()),
+                            );
+                        if !buffering && queued_packets.clone().any(|queued| queued == 0) {
+                            control_playback(
+                                video_playback_thread.as_deref(),
+                                audio_playback_thread.as_deref(),
+                                ControlCommand::Pause,
+                            )
+                            .await;
+                            buffering = true;
+                            buffered_range = None;
+                            buffering_callback(true);
+                        }
+                        if buffering {
+                            if let Some(position) = packet_position(&input_context, &packet) {
+                                let (start, end) = buffered_range.unwrap_or((position, position));
+                                buffered_range = Some((start.min(position), end.max(position)));
+                            }
+                            // A full queue can't take more, even if it holds less than asked for.
+                            let buffered = buffered_range
+                                .is_some_and(|(start, end)| end - start >= prebuffer)
+                                || queued_packets.clone().any(|queued| queued >= PACKET_QUEUE_SIZE);
+                            if buffered {
+                                buffering = false;
+                                end_buffering(
+                                    video_playback_thread.as_deref(),
+                                    audio_playback_thread.as_deref(),
+                                    playing,
+                                    &buffering_callback,
+                                )
+                                .await;
                             }
                         }
                     }
-                })
-            })?;
+                    packet_forwarder = forward_packet(
+                        packet,
+                        video_stream_index.zip(video_playback_thread.clone()),
+                        audio_stream_index.zip(audio_playback_thread.clone()),
+                    );
+                }
+                Err(ffmpeg_next::Error::Eof) => {
+                    // There is nothing more to wait for.
+                    if buffering {
+                        buffering = false;
+                        end_buffering(
+                            video_playback_thread.as_deref(),
+                            audio_playback_thread.as_deref(),
+                            playing,
+                            &buffering_callback,
+                        )
+                        .await;
+                    }
+                    if scrubbing {
+                        reading_preview = false;
+                    } else {
+                        finished = true;
+                        // Queued after the last packets, like those, so that they all play
+                        // before the input starts over or playback ends.
+                        packet_forwarder =
+                            play_out(video_playback_thread.clone(), audio_playback_thread.clone());
+                    }
+                    continue;
+                }
+                // Network inputs fail to read once the connection dropped or stalled for
+                // longer than the timeout. Open them again and continue where playback is.
+                // Corrupt packets are skipped like in files.
+                Err(error) if is_url && !scrubbing && error != ffmpeg_next::Error::InvalidData => {
+                    if reconnect_attempts == MAX_RECONNECT_ATTEMPTS {
+                        error_callback(format!(
+                            "Lost the connection to {}: {}",
+                            path.display(),
+                            error
+                        ));
+                        finished = true;
+                        continue;
+                    }
+                    reconnect_attempts += 1;
+                    smol::Timer::after(RECONNECT_DELAY * reconnect_attempts).await;
+
+                    let Ok(new_input_context) = ffmpeg_next::format::input_with_dictionary(
+                        &path,
+                        options.network.to_dictionary(),
+                    ) else {
+                        continue;
+                    };
+                    input_context = new_input_context;
+                    // Live streams continue at the live edge, which their timestamps carry on
+                    // to. Others are read again from the current position.
+                    if duration.is_some() {
+                        let position =
+                            clock.audio_time().or_else(|| clock.video_time()).unwrap_or_default();
+                        if seek_input(&mut input_context, position).is_ok() {
+                            packet_forwarder = Fuse::terminated();
+                            flush_playback(
+                                video_playback_thread.as_deref(),
+                                audio_playback_thread.as_deref(),
+                                position,
+                            )
+                            .await;
+                        }
+                    }
+                    continue;
+                }
+                // Skip unreadable packets, like `Input::packets()` does.
+                Err(_) => continue,
+            }
+        }
 
-        let playing = true;
-        playing_changed_callback(playing);
+        let preview_presented = async {
+            match &video_playback_thread {
+                Some(video_playback_thread) => video_playback_thread.preview_presented().await,
+                // Without video there are no previews.
+                None => futures::future::pending().await,
+            }
+        };
 
-        Ok(Self {
-            control_sender,
-            demuxer_thread: Some(demuxer_thread),
-            playing,
-            playing_changed_callback: Box::new(playing_changed_callback),
-        })
+        // Check for the end of the loop region regularly, also after all packets were read.
+        let loop_region_check = async {
+            if loop_region.is_some() && playing {
+                smol::Timer::after(LOOP_REGION_CHECK_INTERVAL).await;
+            } else {
+                futures::future::pending::<()>().await;
+            }
+        };
+
+        // Resolves once, as the loudness is only found out once, and never if that fails.
+        let found_loudness = async {
+            match loudness_receiver.as_ref().map(|receiver| receiver.recv()) {
+                Some(received) => match received.await {
+                    Ok(loudness) => loudness,
+                    Err(_) => futures::future::pending().await,
+                },
+                None => futures::future::pending().await,
+            }
+        };
+
+        let received_command = futures::select! {
+            played_out = packet_forwarder => {
+                if !played_out {
+                    continue;
+                }
+                // Looping is only decided now, so that turning it on or off while the last
+                // packets play still counts.
+                match SeekTarget::End
+                    .resolve(looping)
+                    .filter(|position| seek_input(&mut input_context, *position).is_ok())
+                {
+                    Some(position) => {
+                        flush_playback(
+                            video_playback_thread.as_deref(),
+                            audio_playback_thread.as_deref(),
+                            position,
+                        )
+                        .await;
+                        finished = false;
+                    }
+                    None => ended_callback(),
+                }
+                continue;
+            }
+            _ = loop_region_check.fuse() => continue,
+            _ = preview_presented.fuse() => {
+                reading_preview = false;
+                if !showing_poster {
+                    continue;
+                }
+                showing_poster = false;
+                // Ready to play from the poster on.
+                Ok(ControlCommand::EndScrub)
+            }
+            loudness = found_loudness.fuse() => Ok(ControlCommand::SetTrackLoudness(loudness)),
+            received_command = control_receiver.recv().fuse() => received_command,
+        };
+
+        match received_command {
+            Ok(command @ (ControlCommand::Play | ControlCommand::Pause)) => {
+                // While buffering, the playback threads stay paused until enough is buffered.
+                if let Some(video_playback_thread) =
+                    video_playback_thread.as_ref().filter(|_| !buffering)
+                {
+                    video_playback_thread.send_control_message(command).await;
+                }
+                // Audio stays muted while scrubbing and resumes afterwards.
+                if let Some(audio_playback_thread) =
+                    audio_playback_thread.as_ref().filter(|_| !scrubbing && !buffering)
+                {
+                    audio_playback_thread.send_control_message(command).await;
+                }
+                // Continue in the loop, reading and forwarding packets
+                // only while playing.
+                playing = matches!(command, ControlCommand::Play);
+            }
+            Ok(command @ ControlCommand::SetSpeed(speed)) => {
+                if !(speed > 0. && speed.is_finite()) {
+                    error_callback(format!("Ignoring invalid playback speed {}", speed));
+                    continue;
+                }
+                video_settings.retain(|setting| {
+                    std::mem::discriminant(setting) != std::mem::discriminant(&command)
+                });
+                video_settings.push(command);
+                if let Some(video_playback_thread) = &video_playback_thread {
+                    video_playback_thread.send_control_message(command).await;
+                }
+                audio_settings.retain(|setting| {
+                    std::mem::discriminant(setting) != std::mem::discriminant(&command)
+                });
+                audio_settings.push(command);
+                if let Some(audio_playback_thread) = &audio_playback_thread {
+                    audio_playback_thread.send_control_message(command).await;
+                }
+            }
+            Ok(command @ ControlCommand::SetSmoothingMode(_))
+            | Ok(command @ ControlCommand::SetSyncMode(_))
+            | Ok(command @ ControlCommand::SetAdaptiveQuality(_))
+            | Ok(command @ ControlCommand::SetDisplayRefreshRate(_))
+            | Ok(command @ ControlCommand::SetPictureAdjustment(_))
+            | Ok(command @ ControlCommand::SetDeinterlaceMode(_)) => {
+                video_settings.retain(|setting| {
+                    std::mem::discriminant(setting) != std::mem::discriminant(&command)
+                });
+                video_settings.push(command);
+                if let Some(video_playback_thread) = &video_playback_thread {
+                    video_playback_thread.send_control_message(command).await;
+                }
+            }
+            Ok(ControlCommand::ChangeVideoFilter) => {
+                // The filter itself is shared with the video playback threads, so this isn't
+                // re-applied when switching video streams.
+                if let Some(video_playback_thread) = &video_playback_thread {
+                    video_playback_thread
+                        .send_control_message(ControlCommand::ChangeVideoFilter)
+                        .await;
+                }
+            }
+            Ok(ControlCommand::ChangeRecording) => {
+                // Finalize the previous recording before starting another one.
+                recorder = None;
+                let path = recording.lock().unwrap().clone();
+                if let Some(path) = path {
+                    match record::Recorder::new(&input_context, &path) {
+                        Ok(new_recorder) => recorder = Some(new_recorder),
+                        Err(error) => error_callback(format!("Failed to record: {:#}", error)),
+                    }
+                }
+            }
+            Ok(command @ ControlCommand::SetDownmix(_))
+            | Ok(command @ ControlCommand::SetVolume(_))
+            | Ok(command @ ControlCommand::SetMuted(_))
+            | Ok(command @ ControlCommand::SetAudioDelay(_))
+            | Ok(command @ ControlCommand::SetBalance(_))
+            | Ok(command @ ControlCommand::SetNightMode(_))
+            | Ok(command @ ControlCommand::SetEqualizer(_))
+            | Ok(command @ ControlCommand::SetLoudnessTarget(_))
+            | Ok(command @ ControlCommand::SetTrackLoudness(_)) => {
+                if let ControlCommand::SetLoudnessTarget(target) = command {
+                    loudness_target = target;
+                    if let Some(index) = audio_stream_index.filter(|_| loudness_receiver.is_none())
+                    {
+                        loudness_receiver = target
+                            .map(|_| loudness::find_out(&path, &input_context, index, is_url));
+                    }
+                }
+                audio_settings.retain(|setting| {
+                    std::mem::discriminant(setting) != std::mem::discriminant(&command)
+                });
+                audio_settings.push(command);
+                if let Some(audio_playback_thread) = &audio_playback_thread {
+                    audio_playback_thread.send_control_message(command).await;
+                }
+            }
+            Ok(ControlCommand::SetLoop(enabled)) => {
+                // Takes effect the next time the end of the input is reached.
+                looping = enabled;
+            }
+            Ok(ControlCommand::SetLoopRegion(region)) => {
+                loop_region = region
+                    .map(|(start, end)| (start.max(0.), end))
+                    .filter(|(start, end)| start < end);
+                awaiting_loop_start = false;
+            }
+            Ok(command @ ControlCommand::BeginScrub) => {
+                if !scrubbing {
+                    scrubbing = true;
+                    if let Some(audio_playback_thread) = &audio_playback_thread {
+                        audio_playback_thread.send_control_message(ControlCommand::Pause).await;
+                    }
+                    if let Some(video_playback_thread) = &video_playback_thread {
+                        video_playback_thread.send_control_message(command).await;
+                    }
+                }
+            }
+            Ok(ControlCommand::ScrubTo(position)) => {
+                if scrubbing {
+                    pending_scrub_position =
+                        Some(SeekTarget::new(position, duration).preview_position(duration));
+                }
+            }
+            Ok(command @ ControlCommand::EndScrub) => {
+                if scrubbing {
+                    scrubbing = false;
+                    reading_preview = false;
+                    let position = pending_scrub_position.take().or(scrub_position.take());
+                    if let Some(position) = position {
+                        if seek_input(&mut input_context, position).is_ok() {
+                            packet_forwarder = Fuse::terminated();
+                            flush_playback(
+                                video_playback_thread.as_deref(),
+                                audio_playback_thread.as_deref(),
+                                position,
+                            )
+                            .await;
+                            finished = false;
+                        }
+                    }
+                    if let Some(video_playback_thread) = &video_playback_thread {
+                        video_playback_thread.send_control_message(command).await;
+                    }
+                    if let Some(audio_playback_thread) =
+                        audio_playback_thread.as_ref().filter(|_| playing && !buffering)
+                    {
+                        audio_playback_thread.send_control_message(ControlCommand::Play).await;
+                    }
+                }
+            }
+            Ok(ControlCommand::Seek(position, mode)) => {
+                let target = SeekTarget::new(position, duration);
+                if scrubbing {
+                    pending_scrub_position = Some(target.preview_position(duration));
+                    continue;
+                }
+                let position = match target.resolve(looping) {
+                    Some(position) => position,
+                    None => {
+                        // Drop what is still queued, so that playback stops right away.
+                        packet_forwarder = Fuse::terminated();
+                        let end = duration.unwrap_or_default();
+                        flush_playback(
+                            video_playback_thread.as_deref(),
+                            audio_playback_thread.as_deref(),
+                            end,
+                        )
+                        .await;
+                        finished = true;
+                        ended_callback();
+                        continue;
+                    }
+                };
+                // With an index of the keyframes, a fast seek jumps straight to the right one.
+                let indexed_keyframe = match mode {
+                    SeekMode::Fast => keyframe_index
+                        .lock()
+                        .unwrap()
+                        .as_ref()
+                        .filter(|index| Some(index.stream_index) == video_stream_index)
+                        .and_then(|index| {
+                            Some((index.stream_index, index.keyframe_before(position)?))
+                        }),
+                    SeekMode::Accurate => None,
+                };
+                if let Some((index, (pts, keyframe_position))) = indexed_keyframe {
+                    if seek_to_keyframe(&mut input_context, index, pts).is_ok() {
+                        packet_forwarder = Fuse::terminated();
+                        flush_playback(
+                            video_playback_thread.as_deref(),
+                            audio_playback_thread.as_deref(),
+                            keyframe_position,
+                        )
+                        .await;
+                        finished = false;
+                        continue;
+                    }
+                }
+                if seek_input(&mut input_context, position).is_ok() {
+                    // A fast seek continues from the keyframe the input was positioned at,
+                    // which is the first packet of the video stream, or of the audio stream
+                    // without video.
+                    let keyframe = match mode {
+                        SeekMode::Fast => video_stream_index
+                            .or(audio_stream_index)
+                            .and_then(|index| read_first_packet(&mut input_context, index)),
+                        SeekMode::Accurate => None,
+                    };
+                    let position = keyframe
+                        .as_ref()
+                        .and_then(|packet| packet_position(&input_context, packet))
+                        .map_or(position, |keyframe_position| keyframe_position.max(0.));
+                    packet_forwarder = Fuse::terminated();
+                    flush_playback(
+                        video_playback_thread.as_deref(),
+                        audio_playback_thread.as_deref(),
+                        position,
+                    )
+                    .await;
+                    if let Some(packet) = keyframe {
+                        packet_forwarder = forward_packet(
+                            packet,
+                            video_stream_index.zip(video_playback_thread.clone()),
+                            audio_stream_index.zip(audio_playback_thread.clone()),
+                        );
+                    }
+                    finished = false;
+                }
+            }
+            Ok(
+                command @ (ControlCommand::SelectVideoStream(_)
+                | ControlCommand::SetHardwareDecoding(_)),
+            ) => {
+                // Both start a new video playback thread, for another stream or for the same
+                // stream with another decoder.
+                let index = match command {
+                    ControlCommand::SelectVideoStream(index)
+                        if Some(index) != video_stream_index =>
+                    {
+                        index
+                    }
+                    ControlCommand::SetHardwareDecoding(enabled)
+                        if enabled != hardware_decoding =>
+                    {
+                        hardware_decoding = enabled;
+                        let Some(index) = video_stream_index else { continue };
+                        index
+                    }
+                    _ => continue,
+                };
+                let Some(stream) = input_context.stream(index) else { continue };
+                if stream.parameters().medium() != ffmpeg_next::media::Type::Video {
+                    continue;
+                }
+                let new_playback_thread =
+                    match start_video_playback_thread(&stream, hardware_decoding) {
+                        Ok(thread) => thread,
+                        Err(error) => {
+                            error_callback(format!(
+                                "Failed to switch to video stream {}: {}",
+                                index, error
+                            ));
+                            continue;
+                        }
+                    };
+
+                // Bring the new thread into the state of the old one, continuing at the
+                // position that is currently shown.
+                for setting in &video_settings {
+                    new_playback_thread.send_control_message(*setting).await;
+                }
+                if scrubbing {
+                    new_playback_thread.send_control_message(ControlCommand::BeginScrub).await;
+                }
+                if !playing || buffering {
+                    new_playback_thread.send_control_message(ControlCommand::Pause).await;
+                }
+                new_playback_thread.flush(clock.video_time().unwrap_or_default()).await;
+
+                // Release the forwarder's reference, so that the old thread shuts down here.
+                packet_forwarder = Fuse::terminated();
+                video_playback_thread = Some(Rc::new(new_playback_thread));
+                video_stream_index = Some(index);
+                awaiting_video_keyframe = true;
+            }
+            Ok(command @ ControlCommand::SelectAudioStream(_))
+            | Ok(command @ ControlCommand::ChangeAudioDevice) => {
+                // A new device plays the current stream, restarted on the new device.
+                let (index, change_device) = match (command, audio_stream_index) {
+                    (ControlCommand::SelectAudioStream(index), _) => (index, false),
+                    (_, Some(index)) => (index, true),
+                    (_, None) => continue,
+                };
+                if Some(index) == audio_stream_index && !change_device {
+                    continue;
+                }
+                let Some(stream) = input_context.stream(index) else { continue };
+                if stream.parameters().medium() != ffmpeg_next::media::Type::Audio {
+                    continue;
+                }
+                if !change_device {
+                    // The new stream is as loud as it is, until its own loudness is found out.
+                    audio_settings
+                        .retain(|setting| !matches!(setting, ControlCommand::SetTrackLoudness(_)));
+                }
+                // Without an audio output there is nothing to switch, besides the packets that
+                // are skipped, unless the new device is the first one available.
+                if audio_playback_thread.is_some() || change_device {
+                    let new_playback_thread = match open_audio_device()
+                        .ok_or_else(|| anyhow::anyhow!("No audio output device"))
+                        .and_then(|device| start_audio_playback_thread(&stream, device))
+                    {
+                        Ok(thread) => thread,
+                        Err(error) if change_device => {
+                            error_callback(format!(
+                                "Failed to switch the audio output device: {}",
+                                error
+                            ));
+                            continue;
+                        }
+                        Err(error) => {
+                            error_callback(format!(
+                                "Failed to switch to audio stream {}: {}",
+                                index, error
+                            ));
+                            continue;
+                        }
+                    };
+
+                    for setting in &audio_settings {
+                        new_playback_thread.send_control_message(*setting).await;
+                    }
+                    if !playing || scrubbing || buffering {
+                        new_playback_thread.send_control_message(ControlCommand::Pause).await;
+                    }
+
+                    // Release the forwarder's reference, so that the old thread shuts down here.
+                    packet_forwarder = Fuse::terminated();
+                    audio_playback_thread = Some(Rc::new(new_playback_thread));
+                }
+                audio_stream_index = Some(index);
+                if !change_device {
+                    loudness_receiver = loudness_target
+                        .map(|_| loudness::find_out(&path, &input_context, index, is_url));
+                }
+
+                // The packets read so far are ahead of what is playing, so read the new stream
+                // again from the current position.
+                let position =
+                    clock.audio_time().or_else(|| clock.video_time()).unwrap_or_default();
+                if !scrubbing && seek_input(&mut input_context, position).is_ok() {
+                    packet_forwarder = Fuse::terminated();
+                    flush_playback(
+                        video_playback_thread.as_deref(),
+                        audio_playback_thread.as_deref(),
+                        position,
+                    )
+                    .await;
+                    finished = false;
+                }
+            }
+            Err(_) => {
+                // Channel closed -> quit
+                return;
+            }
+        }
     }
+}
 
-    pub fn toggle_pause_playing(&mut self) {
-        if self.playing {
-            self.playing = false;
-            self.control_sender.send_blocking(ControlCommand::Pause).unwrap();
-        } else {
-            self.playing = true;
-            self.control_sender.send_blocking(ControlCommand::Play).unwrap();
+/// How often a network input is opened again after reading from it failed, before giving up.
+const MAX_RECONNECT_ATTEMPTS: u32 = 5;
+
+/// How long to wait before the first attempt to reconnect. Each further attempt waits longer.
+const RECONNECT_DELAY: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// How often the demuxer checks whether playback passed the end of the loop region.
+const LOOP_REGION_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_millis(20);
+
+/// Seeks closer than this (in seconds) to the end of the input count as seeking to the end.
+const SEEK_END_EPSILON: f64 = 0.1;
+
+/// Where a requested seek position ends up, after clamping it to the input.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum SeekTarget {
+    /// A position (in seconds) within the input.
+    Position(f64),
+    /// At or past the end of the input.
+    End,
+}
+
+impl SeekTarget {
+    /// Clamps `position` (in seconds) to the input, as FFmpeg can't seek outside of it. The
+    /// duration is unknown for some inputs, like live streams, which are only clamped to the
+    /// start.
+    fn new(position: f64, duration: Option<f64>) -> Self {
+        let position = if position.is_nan() { 0. } else { position.max(0.) };
+        match duration {
+            Some(duration)
+                if duration > SEEK_END_EPSILON && position >= duration - SEEK_END_EPSILON =>
+            {
+                Self::End
+            }
+            _ => Self::Position(position),
+        }
+    }
+
+    /// The position to decode a preview frame for. Previews of the end show the last frames.
+    fn preview_position(self, duration: Option<f64>) -> f64 {
+        match self {
+            Self::Position(position) => position,
+            Self::End => duration.map_or(0., |duration| duration - SEEK_END_EPSILON),
         }
-        (self.playing_changed_callback)(self.playing);
+    }
+
+    /// The position (in seconds) playback continues from, where the end wraps around to the
+    /// start when `looping`, or `None` when playback ends there.
+    fn resolve(self, looping: bool) -> Option<f64> {
+        match self {
+            Self::Position(position) => Some(position),
+            Self::End if looping => Some(0.),
+            Self::End => None,
+        }
+    }
+}
+
+/// Tells the playback threads to drop what they have queued and continue from `position`.
+async fn flush_playback(
+    video_playback_thread: Option<&video::VideoPlaybackThread>,
+    audio_playback_thread: Option<&audio::AudioPlaybackThread>,
+    position: f64,
+) {
+    if let Some(video_playback_thread) = video_playback_thread {
+        video_playback_thread.flush(position).await;
+    }
+    if let Some(audio_playback_thread) = audio_playback_thread {
+        audio_playback_thread.flush(position).await;
+    }
+}
+
+/// Sends `command` to the playback threads.
+async fn control_playback(
+    video_playback_thread: Option<&video::VideoPlaybackThread>,
+    audio_playback_thread: Option<&audio::AudioPlaybackThread>,
+    command: ControlCommand,
+) {
+    if let Some(video_playback_thread) = video_playback_thread {
+        video_playback_thread.send_control_message(command).await;
+    }
+    if let Some(audio_playback_thread) = audio_playback_thread {
+        audio_playback_thread.send_control_message(command).await;
+    }
+}
+
+/// Resumes the playback threads paused for buffering, unless playback was paused meanwhile.
+async fn end_buffering(
+    video_playback_thread: Option<&video::VideoPlaybackThread>,
+    audio_playback_thread: Option<&audio::AudioPlaybackThread>,
+    playing: bool,
+    buffering_callback: &BufferingCallback,
+) {
+    if playing {
+        control_playback(video_playback_thread, audio_playback_thread, ControlCommand::Play).await;
+    }
+    buffering_callback(false);
+}
+
+/// Sends `packet` to the playback thread of its stream, given with the index of the stream each
+/// thread plays. Packets of other streams are dropped. Resolves to `false`, as more follow.
+fn forward_packet(
+    packet: ffmpeg_next::Packet,
+    video: Option<(usize, Rc<video::VideoPlaybackThread>)>,
+    audio: Option<(usize, Rc<audio::AudioPlaybackThread>)>,
+) -> Fuse<LocalBoxFuture<'static, bool>> {
+    async move {
+        if let Some((_, audio_playback_thread)) =
+            audio.filter(|(index, _)| *index == packet.stream())
+        {
+            audio_playback_thread.receive_packet(packet).await;
+        } else if let Some((_, video_playback_thread)) =
+            video.filter(|(index, _)| *index == packet.stream())
+        {
+            video_playback_thread.receive_packet(packet).await;
+        }
+        false
+    }
+    .boxed_local()
+    .fuse()
+}
+
+/// Tells the playback threads that the input ended, after the packets they still have queued.
+/// Resolves to `true` once both presented everything. A jump before that drops the future, as
+/// what plays after the jump doesn't end there.
+fn play_out(
+    video_playback_thread: Option<Rc<video::VideoPlaybackThread>>,
+    audio_playback_thread: Option<Rc<audio::AudioPlaybackThread>>,
+) -> Fuse<LocalBoxFuture<'static, bool>> {
+    async move {
+        if let Some(video_playback_thread) = &video_playback_thread {
+            video_playback_thread.end_of_stream().await;
+        }
+        if let Some(audio_playback_thread) = &audio_playback_thread {
+            audio_playback_thread.end_of_stream().await;
+        }
+        if let Some(video_playback_thread) = &video_playback_thread {
+            video_playback_thread.ended().await;
+        }
+        if let Some(audio_playback_thread) = &audio_playback_thread {
+            audio_playback_thread.ended().await;
+        }
+        true
+    }
+    .boxed_local()
+    .fuse()
+}
+
+/// Reads up to the first packet of the stream with index `stream_index`, dropping the packets of
+/// other streams before it. Returns `None` at the end of the input.
+fn read_first_packet(
+    input_context: &mut ffmpeg_next::format::context::Input,
+    stream_index: usize,
+) -> Option<ffmpeg_next::Packet> {
+    loop {
+        let mut packet = ffmpeg_next::Packet::empty();
+        match packet.read(input_context) {
+            Ok(()) if packet.stream() == stream_index => return Some(packet),
+            Err(ffmpeg_next::Error::Eof) => return None,
+            // Skip unreadable packets, like `Input::packets()` does.
+            _ => continue,
+        }
+    }
+}
+
+/// The presentation time of `packet` in seconds, if it has one.
+fn packet_position(
+    input_context: &ffmpeg_next::format::context::Input,
+    packet: &ffmpeg_next::Packet,
+) -> Option<f64> {
+    let time_base = input_context.stream(packet.stream())?.time_base();
+    let timestamp = packet.pts().or(packet.dts())?;
+    Some(timestamp as f64 * f64::from(time_base))
+}
+
+/// Seeks to the closest keyframe at or before `position` (in seconds).
+fn seek_input(
+    input_context: &mut ffmpeg_next::format::context::Input,
+    position: f64,
+) -> Result<(), ffmpeg_next::Error> {
+    let timestamp = (position * f64::from(ffmpeg_next::ffi::AV_TIME_BASE)) as i64;
+    input_context.seek(timestamp, ..timestamp)
+}
+
+/// Seeks to the keyframe with the timestamp `pts` of the stream with index `stream_index`, or the
+/// closest one before it.
+fn seek_to_keyframe(
+    input_context: &mut ffmpeg_next::format::context::Input,
+    stream_index: usize,
+    pts: i64,
+) -> Result<(), ffmpeg_next::Error> {
+    match unsafe {
+        ffmpeg_next::ffi::avformat_seek_file(
+            input_context.as_mut_ptr(),
+            stream_index as i32,
+            i64::MIN,
+            pts,
+            pts,
+            0,
+        )
+    } {
+        0.. => Ok(()),
+        error => Err(ffmpeg_next::Error::from(error)),
     }
 }
 
 impl Drop for Player {
     fn drop(&mut self) {
         self.control_sender.close();
-        if let Some(decoder_thread) = self.demuxer_thread.take() {
-            decoder_thread.join().unwrap();
+        if let Some(demuxer_thread) = self.demuxer_thread.take() {
+            demuxer_thread.join().unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seek_targets_are_clamped_to_the_input() {
+        let duration = Some(60.);
+        assert_eq!(SeekTarget::new(-5., duration), SeekTarget::Position(0.));
+        assert_eq!(SeekTarget::new(0., duration), SeekTarget::Position(0.));
+        assert_eq!(SeekTarget::new(30., duration), SeekTarget::Position(30.));
+        assert_eq!(SeekTarget::new(60., duration), SeekTarget::End);
+        assert_eq!(SeekTarget::new(90., duration), SeekTarget::End);
+        assert_eq!(SeekTarget::new(60. - SEEK_END_EPSILON / 2., duration), SeekTarget::End);
+        assert_eq!(SeekTarget::new(f64::NAN, duration), SeekTarget::Position(0.));
+    }
+
+    #[test]
+    fn seek_targets_of_inputs_without_a_duration_are_only_clamped_to_the_start() {
+        for duration in [None, Some(f64::NAN)] {
+            assert_eq!(SeekTarget::new(-5., duration), SeekTarget::Position(0.));
+            assert_eq!(SeekTarget::new(3600., duration), SeekTarget::Position(3600.));
+            assert_eq!(SeekTarget::new(f64::NAN, duration), SeekTarget::Position(0.));
+        }
+    }
+
+    #[test]
+    fn previews_of_the_end_show_the_last_frames() {
+        let duration = Some(60.);
+        assert_eq!(SeekTarget::new(30., duration).preview_position(duration), 30.);
+        assert_eq!(SeekTarget::End.preview_position(duration), 60. - SEEK_END_EPSILON);
+        assert_eq!(SeekTarget::End.preview_position(None), 0.);
+    }
+
+    #[test]
+    fn seeking_to_the_end_wraps_around_when_looping() {
+        assert_eq!(SeekTarget::new(90., Some(60.)).resolve(true), Some(0.));
+        assert_eq!(SeekTarget::new(90., Some(60.)).resolve(false), None);
+        assert_eq!(SeekTarget::new(30., Some(60.)).resolve(true), Some(30.));
+        assert_eq!(SeekTarget::new(30., Some(60.)).resolve(false), Some(30.));
+    }
+
+    /// The number of frames of the input [`write_test_video`] writes.
+    const TEST_VIDEO_FRAMES: u64 = 10;
+
+    /// Writes [`TEST_VIDEO_FRAMES`] small gray frames at 25 fps to `path`, as a video-only input
+    /// that plays without an audio output device.
+    fn write_test_video(path: &std::path::Path) -> Result<(), ffmpeg_next::Error> {
+        let time_base = ffmpeg_next::Rational::new(1, 25);
+        let codec = ffmpeg_next::encoder::find(ffmpeg_next::codec::Id::FFV1)
+            .ok_or(ffmpeg_next::Error::EncoderNotFound)?;
+        let mut output_context = ffmpeg_next::format::output(&path)?;
+        let mut encoder = ffmpeg_next::codec::Context::new_with_codec(codec).encoder().video()?;
+        encoder.set_width(64);
+        encoder.set_height(64);
+        encoder.set_format(ffmpeg_next::format::Pixel::YUV420P);
+        encoder.set_frame_rate(Some(time_base.invert()));
+        encoder.set_time_base(time_base);
+        if output_context.format().flags().contains(ffmpeg_next::format::Flags::GLOBAL_HEADER) {
+            encoder.set_flags(ffmpeg_next::codec::Flags::GLOBAL_HEADER);
+        }
+        let mut encoder = encoder.open_as(codec)?;
+        output_context.add_stream(codec)?.set_parameters(&encoder);
+        output_context.write_header()?;
+        // The muxer picks the time base of the stream.
+        let stream_time_base = output_context.stream(0).unwrap().time_base();
+
+        let write_packets = |encoder: &mut ffmpeg_next::encoder::Video,
+                             output_context: &mut ffmpeg_next::format::context::Output|
+         -> Result<(), ffmpeg_next::Error> {
+            let mut packet = ffmpeg_next::Packet::empty();
+            while encoder.receive_packet(&mut packet).is_ok() {
+                packet.set_stream(0);
+                packet.rescale_ts(time_base, stream_time_base);
+                packet.write_interleaved(output_context)?;
+            }
+            Ok(())
+        };
+        for index in 0..TEST_VIDEO_FRAMES {
+            let mut frame =
+                ffmpeg_next::util::frame::Video::new(ffmpeg_next::format::Pixel::YUV420P, 64, 64);
+            for plane in 0..frame.planes() {
+                frame.data_mut(plane).fill(128);
+            }
+            frame.set_pts(Some(index as i64));
+            encoder.send_frame(&frame)?;
+            write_packets(&mut encoder, &mut output_context)?;
+        }
+        encoder.send_eof()?;
+        write_packets(&mut encoder, &mut output_context)?;
+        output_context.write_trailer()
+    }
+
+    /// Waits up to ten seconds for `condition`, and returns whether it was met.
+    fn wait_for(condition: impl Fn() -> bool) -> bool {
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+        while !condition() {
+            if std::time::Instant::now() > deadline {
+                return false;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
         }
+        true
+    }
+
+    /// Plays a short input with looping on, and turns looping off while it plays for the third
+    /// time. Every frame of each time through reaches the frame callback, or is dropped for being
+    /// late, before the input starts over or playback ends.
+    #[test]
+    fn every_frame_plays_each_time_the_input_loops() {
+        ffmpeg_next::init().unwrap();
+        let path =
+            std::env::temp_dir().join(format!("player-loop-test-{}.mkv", std::process::id()));
+        write_test_video(&path).unwrap();
+
+        let ended = Arc::new(AtomicBool::new(false));
+        let mut player = Player::start(
+            path.clone(),
+            PlayerOptions::default(),
+            |_, _| {},
+            |_| {},
+            |_| {},
+            |_| {},
+            |_| {},
+            {
+                let ended = ended.clone();
+                move || ended.store(true, Ordering::Relaxed)
+            },
+            |_| {},
+        )
+        .unwrap();
+        player.set_loop(true);
+        let played_frames =
+            |player: &Player| player.presented_video_frames() + player.dropped_video_frames();
+
+        assert!(wait_for(|| played_frames(&player) > 2 * TEST_VIDEO_FRAMES));
+        player.set_loop(false);
+        assert!(wait_for(|| ended.load(Ordering::Relaxed)));
+        assert_eq!(played_frames(&player), 3 * TEST_VIDEO_FRAMES);
+
+        drop(player);
+        std::fs::remove_file(&path).ok();
     }
 }
@@ -1,8 +1,12 @@
+use std::cell::Cell;
 use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 use bytemuck::Pod;
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use cpal::SizedSample;
+use cpal::{FromSample, Sample, SizedSample};
 
 use futures::future::OptionFuture;
 use futures::FutureExt;
@@ -11,65 +15,239 @@ use ringbuf::ring_buffer::RbWrite;
 use ringbuf::HeapRb;
 use std::future::Future;
 
-use super::ControlCommand;
+use super::{
+    ControlCommand, DecoderInput, ErrorCallback, PacketMessage, PlaybackClock,
+    DEFAULT_AUDIO_LATENCY, PACKET_QUEUE_SIZE,
+};
+
+/// The levels used to mix the center, surround and LFE channels into the output when the source
+/// has more channels than the output device, for example 5.1 audio on stereo speakers.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DownmixMatrix {
+    pub center_level: f64,
+    pub surround_level: f64,
+    /// The level of the low-frequency effects channel. FFmpeg leaves it out by default, but then
+    /// stereo speakers lose the bass of explosions and the like, which only it carries.
+    pub lfe_level: f64,
+    /// Matrix-encode the surround channels into a Dolby Surround compatible signal.
+    pub dolby_surround: bool,
+}
+
+impl DownmixMatrix {
+    /// Mixes the center and surround channels in at -3 dB, like FFmpeg's default, and the LFE
+    /// channel at -6 dB.
+    pub const DEFAULT: Self = Self {
+        center_level: std::f64::consts::FRAC_1_SQRT_2,
+        surround_level: std::f64::consts::FRAC_1_SQRT_2,
+        lfe_level: 0.5,
+        dolby_surround: false,
+    };
+
+    /// Keeps the center channel, which usually carries the dialog, at full level and lowers the
+    /// surround and LFE channels.
+    pub const DIALOG_BOOST: Self =
+        Self { center_level: 1.0, surround_level: 0.5, lfe_level: 0.25, dolby_surround: false };
+
+    pub const DOLBY_SURROUND: Self = Self { dolby_surround: true, ..Self::DEFAULT };
+}
+
+impl Default for DownmixMatrix {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// The names of the audio output devices, such as speakers, headphones or HDMI outputs, to
+/// pick one for [`super::Player::set_audio_device`].
+pub fn output_device_names() -> Vec<String> {
+    match cpal::default_host().output_devices() {
+        Ok(devices) => devices.filter_map(|device| device.name().ok()).collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// The output device called `name`, or the system's default output device for `None` and when
+/// the named device is gone, such as unplugged headphones.
+pub fn output_device(name: Option<&str>) -> Option<cpal::Device> {
+    let host = cpal::default_host();
+    name.and_then(|name| {
+        host.output_devices()
+            .ok()?
+            .find(|device| device.name().is_ok_and(|device_name| device_name == name))
+    })
+    .or_else(|| host.default_output_device())
+}
 
 pub struct AudioPlaybackThread {
     control_sender: smol::channel::Sender<ControlCommand>,
-    packet_sender: smol::channel::Sender<ffmpeg_next::codec::packet::packet::Packet>,
+    packet_sender: smol::channel::Sender<PacketMessage>,
+    packet_receiver: smol::channel::Receiver<PacketMessage>,
+    ended_receiver: smol::channel::Receiver<()>,
     receiver_thread: Option<std::thread::JoinHandle<()>>,
 }
 
 impl AudioPlaybackThread {
-    pub fn start(stream: &ffmpeg_next::format::stream::Stream) -> Result<Self, anyhow::Error> {
+    pub fn start(
+        stream: &ffmpeg_next::format::stream::Stream,
+        device: cpal::Device,
+        buffer_frames: Option<usize>,
+        fade_in: std::time::Duration,
+        clock: Arc<PlaybackClock>,
+        error_callback: Arc<ErrorCallback>,
+    ) -> Result<Self, anyhow::Error> {
         let (control_sender, control_receiver) = smol::channel::unbounded();
 
-        let (packet_sender, packet_receiver) = smol::channel::bounded(128);
+        let (packet_sender, packet_receiver) = smol::channel::bounded(PACKET_QUEUE_SIZE);
+        let queued_packet_receiver = packet_receiver.clone();
+        let (ended_sender, ended_receiver) = smol::channel::unbounded();
 
         let decoder_context = ffmpeg_next::codec::Context::from_parameters(stream.parameters())?;
         let packet_decoder = decoder_context.decoder().audio()?;
+        let time_base = f64::from(stream.time_base());
 
-        let host = cpal::default_host();
-        let device = host.default_output_device().expect("no output device available");
-
-        let config = device.default_output_config().unwrap();
+        let config = device.default_output_config()?;
+        let buffer_frames = buffer_frames.unwrap_or_else(|| {
+            (config.sample_rate().0 as f64 * DEFAULT_AUDIO_LATENCY.as_secs_f64()) as usize
+        });
 
         let receiver_thread =
             std::thread::Builder::new().name("audio playback thread".into()).spawn(move || {
                 smol::block_on(async move {
+                    let downmix = Rc::new(Cell::new(DownmixMatrix::default()));
+                    let volume = Rc::new(Cell::new(1.0f32));
+                    let delay_ms = Rc::new(Cell::new(0i32));
+                    let balance = Rc::new(Cell::new(0f32));
+                    let speed = Rc::new(Cell::new(1.0f32));
+                    let night_mode = Rc::new(Cell::new(false));
+                    let equalizer = Rc::new(Cell::new([0f32; EQUALIZER_BANDS]));
+                    let loudness_gain = Rc::new(Cell::new(1.0f32));
+                    let feeding = Arc::new(AtomicBool::new(false));
+                    let paused = Arc::new(AtomicBool::new(false));
+
+                    // The resampler mixes to this layout, so its channel count has to match the
+                    // cpal stream's.
                     let output_channel_layout = match config.channels() {
                         1 => ffmpeg_next::util::channel_layout::ChannelLayout::MONO,
                         2 => ffmpeg_next::util::channel_layout::ChannelLayout::STEREO,
-                        _ => todo!(),
+                        6 => ffmpeg_next::util::channel_layout::ChannelLayout::_5POINT1,
+                        8 => ffmpeg_next::util::channel_layout::ChannelLayout::_7POINT1,
+                        channels => ffmpeg_next::util::channel_layout::ChannelLayout::default(
+                            channels.into(),
+                        ),
                     };
 
-                    let mut ffmpeg_to_cpal_forwarder = match config.sample_format() {
+                    let ffmpeg_to_cpal_forwarder = match config.sample_format() {
                         cpal::SampleFormat::U8 => FFmpegToCPalForwarder::new::<u8>(
                             config,
                             &device,
-                            packet_receiver,
+                            buffer_frames,
+                            packet_receiver.clone(),
                             packet_decoder,
+                            time_base,
                             ffmpeg_next::util::format::sample::Sample::U8(
                                 ffmpeg_next::util::format::sample::Type::Packed,
                             ),
                             output_channel_layout,
+                            downmix.clone(),
+                            volume.clone(),
+                            delay_ms.clone(),
+                            balance.clone(),
+                            speed.clone(),
+                            night_mode.clone(),
+                            equalizer.clone(),
+                            loudness_gain.clone(),
+                            fade_in,
+                            feeding.clone(),
+                            paused.clone(),
+                            ended_sender.clone(),
+                            clock.clone(),
+                            error_callback.clone(),
+                        ),
+                        cpal::SampleFormat::I16 => FFmpegToCPalForwarder::new::<i16>(
+                            config,
+                            &device,
+                            buffer_frames,
+                            packet_receiver.clone(),
+                            packet_decoder,
+                            time_base,
+                            ffmpeg_next::util::format::sample::Sample::I16(
+                                ffmpeg_next::util::format::sample::Type::Packed,
+                            ),
+                            output_channel_layout,
+                            downmix.clone(),
+                            volume.clone(),
+                            delay_ms.clone(),
+                            balance.clone(),
+                            speed.clone(),
+                            night_mode.clone(),
+                            equalizer.clone(),
+                            loudness_gain.clone(),
+                            fade_in,
+                            feeding.clone(),
+                            paused.clone(),
+                            ended_sender.clone(),
+                            clock.clone(),
+                            error_callback.clone(),
                         ),
                         cpal::SampleFormat::F32 => FFmpegToCPalForwarder::new::<f32>(
                             config,
                             &device,
-                            packet_receiver,
+                            buffer_frames,
+                            packet_receiver.clone(),
                             packet_decoder,
+                            time_base,
                             ffmpeg_next::util::format::sample::Sample::F32(
                                 ffmpeg_next::util::format::sample::Type::Packed,
                             ),
                             output_channel_layout,
+                            downmix.clone(),
+                            volume.clone(),
+                            delay_ms.clone(),
+                            balance.clone(),
+                            speed.clone(),
+                            night_mode.clone(),
+                            equalizer.clone(),
+                            loudness_gain.clone(),
+                            fade_in,
+                            feeding.clone(),
+                            paused.clone(),
+                            ended_sender.clone(),
+                            clock.clone(),
+                            error_callback.clone(),
                         ),
-                        format @ _ => todo!("unsupported cpal output format {:#?}", format),
+                        format => Err(anyhow::anyhow!("unsupported sample format {}", format)),
+                    };
+
+                    let mut ffmpeg_to_cpal_forwarder = match ffmpeg_to_cpal_forwarder {
+                        Ok(ffmpeg_to_cpal_forwarder) => ffmpeg_to_cpal_forwarder,
+                        Err(error) => {
+                            error_callback(format!("Failed to start audio output: {}", error));
+                            // Nothing will play, so the end is reached right away.
+                            drop(ended_sender);
+                            // Carry on muted: discard the packets, so that the demuxer doesn't
+                            // block on a full queue, until shut down.
+                            loop {
+                                futures::select! {
+                                    _ = packet_receiver.recv().fuse() => {},
+                                    received_command = control_receiver.recv().fuse() => {
+                                        if received_command.is_err() {
+                                            return;
+                                        }
+                                    }
+                                }
+                            }
+                        }
                     };
 
                     let packet_receiver_impl =
                         async { ffmpeg_to_cpal_forwarder.stream().await }.fuse().shared();
 
                     let mut playing = true;
+                    // Muting keeps the volume level, to restore it when unmuting.
+                    let mut volume_level = 1.0f32;
+                    let mut muted = false;
+                    let mut loudness_target = None;
+                    let mut track_loudness = None;
 
                     loop {
                         let packet_receiver: OptionFuture<_> =
@@ -83,9 +261,56 @@ impl AudioPlaybackThread {
                                 match received_command {
                                     Ok(ControlCommand::Pause) => {
                                         playing = false;
+                                        feeding.store(false, Ordering::Relaxed);
+                                        paused.store(true, Ordering::Relaxed);
                                     }
                                     Ok(ControlCommand::Play) => {
                                         playing = true;
+                                        paused.store(false, Ordering::Relaxed);
+                                    }
+                                    Ok(ControlCommand::SetDownmix(matrix)) => {
+                                        downmix.set(matrix);
+                                    }
+                                    Ok(ControlCommand::SetVolume(level)) => {
+                                        volume_level = level.max(0.);
+                                        volume.set(if muted { 0. } else { volume_level });
+                                    }
+                                    Ok(ControlCommand::SetAudioDelay(milliseconds)) => {
+                                        delay_ms.set(milliseconds);
+                                    }
+                                    Ok(ControlCommand::SetBalance(level)) => {
+                                        balance.set(level.clamp(-1., 1.));
+                                    }
+                                    Ok(ControlCommand::SetSpeed(new_speed)) => {
+                                        speed.set(new_speed);
+                                    }
+                                    Ok(ControlCommand::SetNightMode(enabled)) => {
+                                        night_mode.set(enabled);
+                                    }
+                                    Ok(ControlCommand::SetEqualizer(gains)) => {
+                                        let max = MAX_EQUALIZER_GAIN_DB;
+                                        equalizer.set(gains.map(|gain| gain.clamp(-max, max)));
+                                    }
+                                    Ok(ControlCommand::SetLoudnessTarget(target)) => {
+                                        loudness_target = target;
+                                        loudness_gain.set(normalization_gain(
+                                            loudness_target,
+                                            track_loudness,
+                                        ));
+                                    }
+                                    Ok(ControlCommand::SetTrackLoudness(loudness)) => {
+                                        track_loudness = Some(loudness);
+                                        loudness_gain.set(normalization_gain(
+                                            loudness_target,
+                                            track_loudness,
+                                        ));
+                                    }
+                                    Ok(ControlCommand::SetMuted(enabled)) => {
+                                        muted = enabled;
+                                        volume.set(if muted { 0. } else { volume_level });
+                                    }
+                                    Ok(_) => {
+                                        // Handled by the demuxer thread
                                     }
                                     Err(_) => {
                                         // Channel closed -> quit
@@ -98,16 +323,54 @@ impl AudioPlaybackThread {
                 })
             })?;
 
-        Ok(Self { control_sender, packet_sender, receiver_thread: Some(receiver_thread) })
+        Ok(Self {
+            control_sender,
+            packet_sender,
+            packet_receiver: queued_packet_receiver,
+            ended_receiver,
+            receiver_thread: Some(receiver_thread),
+        })
     }
 
     pub async fn receive_packet(&self, packet: ffmpeg_next::codec::packet::packet::Packet) -> bool {
-        match self.packet_sender.send(packet).await {
+        match self.packet_sender.send(PacketMessage::Packet(packet)).await {
+            Ok(_) => return true,
+            Err(smol::channel::SendError(_)) => return false,
+        }
+    }
+
+    /// Asks the playback thread to play the audio the decoder still holds once the queued
+    /// packets are decoded, as the input ended.
+    pub async fn end_of_stream(&self) -> bool {
+        match self.packet_sender.send(PacketMessage::EndOfStream).await {
             Ok(_) => return true,
             Err(smol::channel::SendError(_)) => return false,
         }
     }
 
+    /// The number of packets and other messages waiting to be decoded.
+    pub fn queued_packets(&self) -> usize {
+        self.packet_sender.len()
+    }
+
+    /// Discards the packets that are still queued for decoding and asks the playback thread to
+    /// continue from `position` (in seconds).
+    pub async fn flush(&self, position: f64) -> bool {
+        while self.packet_receiver.try_recv().is_ok() {}
+        // The end reported before the jump is not the end of what plays after it.
+        while self.ended_receiver.try_recv().is_ok() {}
+        match self.packet_sender.send(PacketMessage::Flush(position)).await {
+            Ok(_) => return true,
+            Err(smol::channel::SendError(_)) => return false,
+        }
+    }
+
+    /// Resolves once the output device played the last audio after [`Self::end_of_stream`].
+    pub async fn ended(&self) {
+        // Without the playback thread, nothing is left to play.
+        self.ended_receiver.recv().await.ok();
+    }
+
     pub async fn send_control_message(&self, message: ControlCommand) {
         self.control_sender.send(message).await.unwrap();
     }
@@ -122,116 +385,1093 @@ impl Drop for AudioPlaybackThread {
     }
 }
 
+/// The range of volume levels from 0 to 1 spans, in decibels.
+const VOLUME_RANGE_DB: f32 = 60.;
+
+/// Converts a volume level to the gain it is played at. Loudness is heard logarithmically, so
+/// levels from 0 to 1 map to gains evenly spaced in decibels, from [`VOLUME_RANGE_DB`] below
+/// full level to unity, which makes each step of a volume slider sound like the same change.
+/// 0 is silent, and levels above 1 amplify linearly.
+fn perceptual_gain(level: f32) -> f32 {
+    if level <= 0. {
+        0.
+    } else if level < 1. {
+        10f32.powf((level - 1.) * VOLUME_RANGE_DB / 20.)
+    } else {
+        level
+    }
+}
+
+/// How far quiet audio is turned up at most to reach the loudness target, in decibels. The
+/// peaks that end up above full scale are left to [`soft_limit`].
+const MAX_NORMALIZATION_GAIN_DB: f32 = 12.;
+
+/// The gain that brings audio with the integrated loudness `loudness` to `target`, both in
+/// LUFS, or unity while either is unknown.
+fn normalization_gain(target: Option<f32>, loudness: Option<f32>) -> f32 {
+    match target.zip(loudness) {
+        Some((target, loudness)) => {
+            10f32.powf((target - loudness).min(MAX_NORMALIZATION_GAIN_DB) / 20.)
+        }
+        None => 1.,
+    }
+}
+
+/// Where [`soft_limit`] starts to compress samples, as a fraction of full scale.
+const LIMITER_THRESHOLD: f32 = 0.8;
+
+/// Leaves samples below [`LIMITER_THRESHOLD`] alone and compresses louder ones smoothly into the
+/// rest of the range up to full scale, instead of cutting off their peaks.
+fn soft_limit(sample: f32) -> f32 {
+    let magnitude = sample.abs();
+    if magnitude <= LIMITER_THRESHOLD {
+        return sample;
+    }
+    let headroom = 1. - LIMITER_THRESHOLD;
+    let limited =
+        LIMITER_THRESHOLD + headroom * ((magnitude - LIMITER_THRESHOLD) / headroom).tanh();
+    limited.copysign(sample)
+}
+
+/// How many peaks [`super::Player::audio_peaks`] returns at most, which span the last
+/// few seconds of audio.
+pub const WAVEFORM_PEAKS: usize = 800;
+
+/// How many peaks are taken of each second of played audio.
+const WAVEFORM_PEAKS_PER_SECOND: u32 = 200;
+
+/// Takes the lowest and the highest sample of each stretch of played audio that makes up one
+/// peak of the waveform. This runs in the audio output callback, so it doesn't allocate.
+struct PeakMeter {
+    frames_per_peak: usize,
+    /// Audio frames (one sample for each channel) of the current peak so far.
+    frames: usize,
+    peak: [f32; 2],
+    /// Completed peaks, to be handed on.
+    peaks: Vec<[f32; 2]>,
+}
+
+impl PeakMeter {
+    fn new(sample_rate: u32) -> Self {
+        Self {
+            frames_per_peak: (sample_rate / WAVEFORM_PEAKS_PER_SECOND).max(1) as usize,
+            frames: 0,
+            peak: [0., 0.],
+            peaks: Vec::with_capacity(WAVEFORM_PEAKS),
+        }
+    }
+
+    fn add<T: Sample<Float = f32>>(&mut self, samples: &[T], channels: usize) {
+        for frame in samples.chunks_exact(channels) {
+            for sample in frame {
+                let sample = sample.to_float_sample();
+                self.peak = [self.peak[0].min(sample), self.peak[1].max(sample)];
+            }
+            self.frames += 1;
+            if self.frames == self.frames_per_peak {
+                // Only older peaks are lost while they can't be handed on.
+                if self.peaks.len() == WAVEFORM_PEAKS {
+                    self.peaks.remove(0);
+                }
+                self.peaks.push(self.peak);
+                self.peak = [0., 0.];
+                self.frames = 0;
+            }
+        }
+    }
+}
+
+/// A gain that moves linearly towards its target instead of jumping there, so that volume
+/// changes ease in rather than click.
+struct GainRamp {
+    current: f32,
+    target: f32,
+    /// Change of the gain per audio frame (one sample for each channel).
+    step: f32,
+}
+
+impl GainRamp {
+    fn new(gain: f32) -> Self {
+        Self { current: gain, target: gain, step: 0. }
+    }
+
+    /// Moves the gain to `target` over `duration`, for audio at `rate` frames per second.
+    fn ramp_to(&mut self, target: f32, duration: std::time::Duration, rate: u32) {
+        let frames = duration.as_secs_f32() * rate as f32;
+        self.target = target;
+        self.step = if frames >= 1. { (target - self.current).abs() / frames } else { f32::MAX };
+    }
+
+    fn is_unity(&self) -> bool {
+        self.current == 1. && self.target == 1.
+    }
+
+    fn next(&mut self) -> f32 {
+        if self.current < self.target {
+            self.current = (self.current + self.step).min(self.target);
+        } else if self.current > self.target {
+            self.current = (self.current - self.step).max(self.target);
+        }
+        self.current
+    }
+}
+
 trait FFMpegToCPalSampleForwarder {
+    /// Buffers the samples of `audio_frame` for playback, scaled by the product of `gains`,
+    /// leaving out the first `skip` audio frames. Stereo audio is also scaled by the
+    /// `channel_gains` of the left and right channel.
     fn forward(
         &mut self,
         audio_frame: ffmpeg_next::frame::Audio,
+        skip: usize,
+        gains: [&mut GainRamp; 2],
+        channel_gains: &mut [GainRamp; 2],
     ) -> Pin<Box<dyn Future<Output = ()> + '_>>;
+
+    /// Buffers `samples` samples of silence for playback.
+    fn forward_silence(&mut self, samples: usize) -> Pin<Box<dyn Future<Output = ()> + '_>>;
+
+    /// Whether the output device played all buffered samples.
+    fn is_played_out(&self) -> bool;
 }
 
-impl<T: Pod, R: RbRef> FFMpegToCPalSampleForwarder for ringbuf::Producer<T, R>
+impl<T: Pod + Sample<Float = f32> + FromSample<f32>, R: RbRef> FFMpegToCPalSampleForwarder
+    for ringbuf::Producer<T, R>
 where
     <R as RbRef>::Rb: RbWrite<T>,
 {
     fn forward(
         &mut self,
-        audio_frame: ffmpeg_next::frame::Audio,
+        mut audio_frame: ffmpeg_next::frame::Audio,
+        skip: usize,
+        gains: [&mut GainRamp; 2],
+        channel_gains: &mut [GainRamp; 2],
     ) -> Pin<Box<dyn Future<Output = ()> + '_>> {
+        // Audio::plane() returns the wrong slice size, so correct it by hand. See also
+        // for a fix https://github.com/zmwangx/rust-ffmpeg/pull/104.
+        let channels = audio_frame.channels() as usize;
+        let sample_bytes = channels * core::mem::size_of::<T>();
+        let skipped_bytes = skip.min(audio_frame.samples()) * sample_bytes;
+        let expected_bytes = audio_frame.samples() * sample_bytes;
+
+        let balanced = channels == 2 && !channel_gains.iter().all(|gain| gain.is_unity());
+        if balanced || !gains.iter().all(|gain| gain.is_unity()) {
+            let [first_gain, second_gain] = gains;
+            let samples: &mut [T] = bytemuck::cast_slice_mut(
+                &mut audio_frame.data_mut(0)[skipped_bytes..expected_bytes],
+            );
+            for frame in samples.chunks_exact_mut(channels) {
+                let frame_gain = first_gain.next() * second_gain.next();
+                let frame_channel_gains = if balanced {
+                    [channel_gains[0].next(), channel_gains[1].next()]
+                } else {
+                    [1., 1.]
+                };
+                for (channel, sample) in frame.iter_mut().enumerate() {
+                    let gain = frame_gain * frame_channel_gains.get(channel).unwrap_or(&1.);
+                    let amplified = sample.to_float_sample() * gain;
+                    // Boosted audio is limited softly, as clipping it distorts. Otherwise clamp,
+                    // as integer samples would wrap around.
+                    let limited =
+                        if gain > 1. { soft_limit(amplified) } else { amplified.clamp(-1., 1.) };
+                    *sample = T::from_sample(limited);
+                }
+            }
+        }
+
         Box::pin(async move {
-            // Audio::plane() returns the wrong slice size, so correct it by hand. See also
-            // for a fix https://github.com/zmwangx/rust-ffmpeg/pull/104.
-            let expected_bytes =
-                audio_frame.samples() * audio_frame.channels() as usize * core::mem::size_of::<T>();
             let cpal_sample_data: &[T] =
-                bytemuck::cast_slice(&audio_frame.data(0)[..expected_bytes]);
+                bytemuck::cast_slice(&audio_frame.data(0)[skipped_bytes..expected_bytes]);
 
-            while self.free_len() < cpal_sample_data.len() {
-                smol::Timer::after(std::time::Duration::from_millis(16)).await;
+            // Buffer the samples for playback, in parts, as a small buffer may not fit a
+            // whole frame.
+            let mut remaining = cpal_sample_data;
+            while !remaining.is_empty() {
+                while self.free_len() == 0 {
+                    smol::Timer::after(std::time::Duration::from_millis(16)).await;
+                }
+                remaining = &remaining[self.push_slice(remaining)..];
             }
+        })
+    }
 
-            // Buffer the samples for playback
-            self.push_slice(cpal_sample_data);
+    fn forward_silence(&mut self, samples: usize) -> Pin<Box<dyn Future<Output = ()> + '_>> {
+        Box::pin(async move {
+            // In parts, as there may be more than fits into the buffer.
+            let mut remaining = samples;
+            while remaining > 0 {
+                while self.free_len() == 0 {
+                    smol::Timer::after(std::time::Duration::from_millis(16)).await;
+                }
+                remaining -= self.push_iter(&mut std::iter::repeat(T::EQUILIBRIUM).take(remaining));
+            }
         })
     }
+
+    fn is_played_out(&self) -> bool {
+        self.is_empty()
+    }
 }
 
 struct FFmpegToCPalForwarder {
     _cpal_stream: cpal::Stream,
     ffmpeg_to_cpal_pipe: Box<dyn FFMpegToCPalSampleForwarder>,
-    packet_receiver: smol::channel::Receiver<ffmpeg_next::codec::packet::packet::Packet>,
+    packet_receiver: smol::channel::Receiver<PacketMessage>,
     packet_decoder: ffmpeg_next::decoder::Audio,
+    /// Seconds per unit of the decoded frames' timestamps.
+    time_base: f64,
+    /// The position to continue at after a flush. Audio before it is decoded but not played.
+    skip_until: Option<f64>,
     resampler: ffmpeg_next::software::resampling::Context,
+    output: ffmpeg_next::software::resampling::context::Definition,
+    downmix: Rc<Cell<DownmixMatrix>>,
+    /// The downmix levels the current resampler was created with.
+    resampler_downmix: DownmixMatrix,
+    /// The audio that last failed to convert for the output, to report that only once.
+    unplayable_input: Option<ffmpeg_next::software::resampling::context::Definition>,
+    /// Fades the audio in at the start of playback.
+    fade: GainRamp,
+    /// Follows the volume set by the user.
+    volume: Rc<Cell<f32>>,
+    volume_gain: GainRamp,
+    /// How many milliseconds the audio is to be delayed against the video, set by the user.
+    delay_ms: Rc<Cell<i32>>,
+    /// The delay that was applied since the last flush, in milliseconds.
+    applied_delay_ms: i32,
+    /// Follows the balance set by the user, from -1 (left only) to 1 (right only).
+    balance: Rc<Cell<f32>>,
+    /// The gains of the left and right channel of stereo output for the balance.
+    balance_gains: [GainRamp; 2],
+    /// The balance the gains move towards.
+    balance_target: f32,
+    /// Audio frames (one sample for each channel) still to be left out for a shortened delay.
+    pending_skip: usize,
+    speed: Rc<Cell<f32>>,
+    /// The speed the current audio filter was created for.
+    filter_speed: f32,
+    /// Whether quiet and loud passages are evened out, for listening at a low volume.
+    night_mode: Rc<Cell<bool>>,
+    /// Whether the current audio filter was created to compress the dynamic range.
+    filter_night_mode: bool,
+    /// The gains of the equalizer bands set by the user, in decibels.
+    equalizer: Rc<Cell<[f32; EQUALIZER_BANDS]>>,
+    /// The equalizer gains the current audio filter applies.
+    filter_equalizer: [f32; EQUALIZER_BANDS],
+    /// Brings the audio to the loudness the user picked, on top of the volume.
+    loudness_gain: Rc<Cell<f32>>,
+    /// Changes the tempo without changing the pitch, when not playing at normal speed,
+    /// compresses the dynamic range in night mode, and applies the equalizer.
+    audio_filter: Option<ffmpeg_next::filter::Graph>,
+    /// The audio the current audio filter was created for, which is the only audio it accepts.
+    filter_input: ffmpeg_next::software::resampling::context::Definition,
+    clock: Arc<PlaybackClock>,
+    /// Asks the output callback to drop the samples it has buffered, cleared once it did.
+    discard_buffered: Arc<AtomicBool>,
+    /// Whether audio is being buffered for playback, so that the output running out of samples
+    /// is an underrun, rather than paused playback or the end of the input.
+    feeding: Arc<AtomicBool>,
+    /// Told once the last audio of the input was played.
+    ended_sender: smol::channel::Sender<()>,
+    decoder_input: DecoderInput,
+    error_callback: Arc<ErrorCallback>,
 }
 
 impl FFmpegToCPalForwarder {
-    fn new<T: Send + Pod + SizedSample + 'static>(
+    /// How long changes of the volume take, to avoid clicks.
+    const VOLUME_RAMP: std::time::Duration = std::time::Duration::from_millis(20);
+    /// How long the audio fades out when pausing and back in when resuming, as stopping in the
+    /// middle of a waveform clicks.
+    const PAUSE_FADE: std::time::Duration = std::time::Duration::from_millis(60);
+    /// How long to wait for the output callback to drop the buffered samples. It runs every
+    /// few milliseconds, unless the output device stopped.
+    const DISCARD_TIMEOUT: std::time::Duration = std::time::Duration::from_millis(200);
+    /// How long to wait for the output device to play the buffered samples at the end of the
+    /// input, in case it stopped.
+    const PLAY_OUT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
+
+    #[allow(clippy::too_many_arguments)]
+    fn new<T: Send + Pod + SizedSample + Sample<Float = f32> + FromSample<f32> + 'static>(
         config: cpal::SupportedStreamConfig,
         device: &cpal::Device,
-        packet_receiver: smol::channel::Receiver<ffmpeg_next::codec::packet::packet::Packet>,
+        buffer_frames: usize,
+        packet_receiver: smol::channel::Receiver<PacketMessage>,
         packet_decoder: ffmpeg_next::decoder::Audio,
+        time_base: f64,
         output_format: ffmpeg_next::util::format::sample::Sample,
         output_channel_layout: ffmpeg_next::util::channel_layout::ChannelLayout,
-    ) -> Self {
-        let buffer = HeapRb::new(4096);
+        downmix: Rc<Cell<DownmixMatrix>>,
+        volume: Rc<Cell<f32>>,
+        delay_ms: Rc<Cell<i32>>,
+        balance: Rc<Cell<f32>>,
+        speed: Rc<Cell<f32>>,
+        night_mode: Rc<Cell<bool>>,
+        equalizer: Rc<Cell<[f32; EQUALIZER_BANDS]>>,
+        loudness_gain: Rc<Cell<f32>>,
+        fade_in: std::time::Duration,
+        feeding: Arc<AtomicBool>,
+        paused: Arc<AtomicBool>,
+        ended_sender: smol::channel::Sender<()>,
+        clock: Arc<PlaybackClock>,
+        error_callback: Arc<ErrorCallback>,
+    ) -> Result<Self, anyhow::Error> {
+        let channels = config.channels() as usize;
+        let buffer = HeapRb::new(buffer_frames.max(1) * channels);
         let (sample_producer, mut sample_consumer) = buffer.split();
+        clock.set_audio_buffer_capacity(buffer_frames.max(1) as u64);
 
-        let cpal_stream = device
-            .build_output_stream(
-                &config.config(),
-                move |data, _| {
-                    let filled = sample_consumer.pop_slice(data);
-                    data[filled..].fill(T::EQUILIBRIUM);
-                },
-                move |err| {
-                    eprintln!("error feeding audio stream to cpal: {}", err);
-                },
-                None,
-            )
-            .unwrap();
+        let output_clock = clock.clone();
+        let discard_buffered = Arc::new(AtomicBool::new(false));
+        let output_discard_buffered = discard_buffered.clone();
+        let output_feeding = feeding.clone();
+        let output_error_callback = error_callback.clone();
+        let sample_rate = config.sample_rate().0;
+        // Fades the buffered audio out when pausing, keeping what is left of it for resuming.
+        let mut pause_gain = GainRamp::new(1.);
+        let mut peak_meter = PeakMeter::new(sample_rate);
 
-        cpal_stream.play().unwrap();
+        let cpal_stream = device.build_output_stream(
+            &config.config(),
+            move |data: &mut [T], _| {
+                if output_discard_buffered.load(Ordering::Acquire) {
+                    sample_consumer.clear();
+                    output_discard_buffered.store(false, Ordering::Release);
+                }
+                let pause_target = if paused.load(Ordering::Relaxed) { 0. } else { 1. };
+                if pause_target != pause_gain.target {
+                    pause_gain.ramp_to(pause_target, Self::PAUSE_FADE, sample_rate);
+                }
+                if pause_gain.current == 0. && pause_gain.target == 0. {
+                    data.fill(T::EQUILIBRIUM);
+                    return;
+                }
+                let filled = sample_consumer.pop_slice(data);
+                data[filled..].fill(T::EQUILIBRIUM);
+                if !pause_gain.is_unity() {
+                    for frame in data[..filled].chunks_exact_mut(channels) {
+                        let gain = pause_gain.next();
+                        for sample in frame {
+                            *sample = T::from_sample(sample.to_float_sample() * gain);
+                        }
+                    }
+                }
+                output_clock.add_audio_frames_played((filled / channels) as u64);
+                output_clock.set_audio_buffered_frames((sample_consumer.len() / channels) as u64);
+                peak_meter.add(&data[..filled], channels);
+                output_clock.add_audio_peaks(&mut peak_meter.peaks);
+                if filled < data.len() && output_feeding.load(Ordering::Relaxed) {
+                    output_clock.add_audio_underrun(((data.len() - filled) / channels) as u64);
+                }
+            },
+            move |err| {
+                output_error_callback(format!("Error feeding audio stream to cpal: {}", err));
+            },
+            None,
+        )?;
 
-        let resampler = ffmpeg_next::software::resampling::Context::get(
-            packet_decoder.format(),
-            packet_decoder.channel_layout(),
-            packet_decoder.rate(),
-            output_format,
-            output_channel_layout,
-            config.sample_rate().0,
-        )
-        .unwrap();
+        cpal_stream.play()?;
 
-        Self {
+        let output = ffmpeg_next::software::resampling::context::Definition {
+            format: output_format,
+            channel_layout: output_channel_layout,
+            rate: config.sample_rate().0,
+        };
+        let resampler_downmix = downmix.get();
+        let resampler =
+            create_resampler(&decoder_output(&packet_decoder), &output, resampler_downmix)?;
+        let filter_speed = speed.get();
+        let filter_night_mode = night_mode.get();
+        let filter_input = decoder_output(&packet_decoder);
+        let filter_equalizer = equalizer.get();
+        let audio_filter =
+            create_audio_filter(&filter_input, filter_speed, filter_night_mode, &filter_equalizer)?;
+
+        // Start silent and fade in, unless no fade-in was asked for.
+        let mut fade = GainRamp::new(if fade_in.is_zero() { 1. } else { 0. });
+        fade.ramp_to(1., fade_in, output.rate);
+        // The volume is only known once the queued settings arrive, so ramp up to it rather
+        // than starting at full volume.
+        let volume_gain = GainRamp::new(0.);
+
+        clock.rebase_audio(0., output.rate as f64 / filter_speed as f64);
+
+        Ok(Self {
             _cpal_stream: cpal_stream,
             ffmpeg_to_cpal_pipe: Box::new(sample_producer),
             packet_receiver,
             packet_decoder,
+            time_base,
+            skip_until: None,
             resampler,
-        }
+            output,
+            downmix,
+            resampler_downmix,
+            unplayable_input: None,
+            fade,
+            volume,
+            volume_gain,
+            delay_ms,
+            applied_delay_ms: 0,
+            balance,
+            balance_gains: [GainRamp::new(1.), GainRamp::new(1.)],
+            balance_target: 0.,
+            pending_skip: 0,
+            speed,
+            filter_speed,
+            night_mode,
+            filter_night_mode,
+            equalizer,
+            filter_equalizer,
+            loudness_gain,
+            audio_filter,
+            filter_input,
+            clock,
+            discard_buffered,
+            feeding,
+            ended_sender,
+            decoder_input: DecoderInput::new("audio", error_callback.clone()),
+            error_callback,
+        })
     }
 
     async fn stream(&mut self) {
-    loop {
-        // Receive the next packet from the packet receiver channel.
-        let Ok(packet) = self.packet_receiver.recv().await else { break };
+        loop {
+            // Receive the next packet from the packet receiver channel.
+            let Ok(message) = self.packet_receiver.recv().await else { break };
+
+            let packet = match message {
+                PacketMessage::Packet(packet) => Some(packet),
+                PacketMessage::Flush(position) => {
+                    // Drop the decoder's and the audio filter's buffered state from before the
+                    // jump.
+                    self.packet_decoder.flush();
+                    self.filter_input = decoder_output(&self.packet_decoder);
+                    self.recreate_audio_filter();
+                    self.feeding.store(false, Ordering::Relaxed);
+                    self.discard_buffered_output().await;
+                    self.clock.rebase_audio(position, self.frames_per_input_second());
+                    self.skip_until = Some(position);
+                    // The delay starts over with the audio after the jump.
+                    self.applied_delay_ms = 0;
+                    self.pending_skip = 0;
+                    continue;
+                }
+                PacketMessage::EndOfStream => None,
+            };
+
+            // Send the packet to the decoder.
+            match &packet {
+                // Given up on, but the packets are still taken, so that the demuxer goes on with
+                // the other streams.
+                Some(_) if self.decoder_input.gave_up() => continue,
+                Some(packet) => {
+                    if !self.decoder_input.send(&mut self.packet_decoder, packet) {
+                        continue;
+                    }
+                }
+                // Fails if already draining, which is harmless.
+                None => self.packet_decoder.send_eof().unwrap_or_default(),
+            }
+
+            // Create an empty frame to hold the decoded audio data.
+            let mut decoded_frame = ffmpeg_next::util::frame::Audio::empty();
+
+            // Continue receiving decoded frames until there are no more available.
+            loop {
+                while self.packet_decoder.receive_frame(&mut decoded_frame).is_ok() {
+                    if let Some(position) = self.skip_until {
+                        let end = decoded_frame.pts().map(|pts| {
+                            pts as f64 * self.time_base
+                                + decoded_frame.samples() as f64 / decoded_frame.rate() as f64
+                        });
+                        if end.is_some_and(|end| end <= position) {
+                            continue;
+                        }
+                        self.skip_until = None;
+                    }
+
+                    // Pick up changed audio filter settings, and a changed sample rate, format or
+                    // channel layout of the stream, like at the boundaries of broadcast programs.
+                    let input = frame_definition(&decoded_frame);
+                    if self.speed.get() != self.filter_speed {
+                        // Keep counting the played audio from where it is now.
+                        let position = self.clock.audio_time().unwrap_or_default();
+                        self.filter_speed = self.speed.get();
+                        self.filter_night_mode = self.night_mode.get();
+                        self.filter_equalizer = self.equalizer.get();
+                        self.clock.rebase_audio(position, self.frames_per_input_second());
+                        self.filter_input = input;
+                        self.recreate_audio_filter();
+                    } else if input != self.filter_input
+                        || self.night_mode.get() != self.filter_night_mode
+                        || is_flat(&self.equalizer.get()) != is_flat(&self.filter_equalizer)
+                    {
+                        // Play what the filter holds back of the previous audio first.
+                        self.drain_audio_filter().await;
+                        self.filter_night_mode = self.night_mode.get();
+                        self.filter_equalizer = self.equalizer.get();
+                        self.filter_input = input;
+                        self.recreate_audio_filter();
+                    } else if self.equalizer.get() != self.filter_equalizer {
+                        // The equalizer filters take new gains as they go, without a gap.
+                        self.filter_equalizer = self.equalizer.get();
+                        if let Some(audio_filter) = &mut self.audio_filter {
+                            if let Err(error) =
+                                set_equalizer_gains(audio_filter, &self.filter_equalizer)
+                            {
+                                self.give_up_audio_filter(error);
+                            }
+                        }
+                    }
+
+                    let Some(mut audio_filter) = self.audio_filter.take() else {
+                        self.play_frame(&decoded_frame).await;
+                        continue;
+                    };
+
+                    if let Err(error) = audio_filter.get("in").unwrap().source().add(&decoded_frame)
+                    {
+                        self.give_up_audio_filter(error);
+                        self.play_frame(&decoded_frame).await;
+                        continue;
+                    }
+
+                    let mut filtered_frame = ffmpeg_next::util::frame::Audio::empty();
+                    while audio_filter.get("out").unwrap().sink().frame(&mut filtered_frame).is_ok()
+                    {
+                        self.play_frame(&filtered_frame).await;
+                    }
+
+                    self.audio_filter = Some(audio_filter);
+                }
+                // The decoder has room for the packet it had none for, now that its frames were
+                // taken.
+                if !self.decoder_input.send_pending(&mut self.packet_decoder) {
+                    break;
+                }
+            }
+
+            // The audio filter holds back some audio as well.
+            if packet.is_none() {
+                self.drain_audio_filter().await;
+                // The output plays out what is buffered and then runs out.
+                self.feeding.store(false, Ordering::Relaxed);
+                let deadline = std::time::Instant::now() + Self::PLAY_OUT_TIMEOUT;
+                while !self.ffmpeg_to_cpal_pipe.is_played_out()
+                    && std::time::Instant::now() < deadline
+                {
+                    smol::Timer::after(std::time::Duration::from_millis(16)).await;
+                }
+                self.ended_sender.try_send(()).ok();
+            }
+        }
+    }
 
-        // Send the packet to the decoder.
-        self.packet_decoder.send_packet(&packet).unwrap();
+    /// Replaces the audio filter with one for the current filter settings, dropping the audio
+    /// the previous one held back.
+    fn recreate_audio_filter(&mut self) {
+        match create_audio_filter(
+            &self.filter_input,
+            self.filter_speed,
+            self.filter_night_mode,
+            &self.filter_equalizer,
+        ) {
+            Ok(audio_filter) => self.audio_filter = audio_filter,
+            Err(error) => self.give_up_audio_filter(error),
+        }
+    }
 
-        // Create an empty frame to hold the decoded audio data.
-        let mut decoded_frame = ffmpeg_next::util::frame::Audio::empty();
+    /// Reports that the audio filter failed, like with an FFmpeg build that lacks one of its
+    /// filters, and plays the audio unfiltered until the filter settings or the audio change.
+    fn give_up_audio_filter(&mut self, error: ffmpeg_next::Error) {
+        (self.error_callback)(format!("Failed to filter audio, playing it unfiltered: {error}"));
+        self.audio_filter = None;
+    }
 
-        // Continue receiving decoded frames until there are no more available.
-        while self.packet_decoder.receive_frame(&mut decoded_frame).is_ok() {
-            // Create an empty frame to hold the resampled audio data.
-            let mut resampled_frame = ffmpeg_next::util::frame::Audio::empty();
+    /// Plays the audio the audio filter holds back. It accepts no more audio afterwards.
+    async fn drain_audio_filter(&mut self) {
+        if let Some(mut audio_filter) = self.audio_filter.take() {
+            audio_filter.get("in").unwrap().source().flush().unwrap_or_default();
 
-            // Resample the decoded audio frame to match the output format and channel layout.
-            self.resampler.run(&decoded_frame, &mut resampled_frame).unwrap();
+            let mut filtered_frame = ffmpeg_next::util::frame::Audio::empty();
+            while audio_filter.get("out").unwrap().sink().frame(&mut filtered_frame).is_ok() {
+                self.play_frame(&filtered_frame).await;
+            }
 
-            // Forward the resampled audio frame to the CPAL audio output.
-            self.ffmpeg_to_cpal_pipe.forward(resampled_frame).await;
+            self.audio_filter = Some(audio_filter);
         }
     }
+
+    /// Resamples `frame` to the output format and buffers it for playback.
+    async fn play_frame(&mut self, frame: &ffmpeg_next::util::frame::Audio) {
+        // Pick up changed downmix levels, changes of the stream, and a sample format or rate
+        // changed by conversions the audio filter inserted.
+        let input = frame_definition(frame);
+        if self.downmix.get() != self.resampler_downmix || input != *self.resampler.input() {
+            self.resampler_downmix = self.downmix.get();
+            match create_resampler(&input, &self.output, self.resampler_downmix) {
+                Ok(resampler) => self.resampler = resampler,
+                Err(error) => {
+                    self.skip_unplayable(input, error);
+                    return;
+                }
+            }
+        }
+
+        // Create an empty frame to hold the resampled audio data.
+        let mut resampled_frame = ffmpeg_next::util::frame::Audio::empty();
+
+        // Resample the decoded audio frame to match the output format and channel layout.
+        if let Err(error) = self.resampler.run(frame, &mut resampled_frame) {
+            self.skip_unplayable(input, error);
+            return;
+        }
+        self.unplayable_input = None;
+
+        let volume_gain = perceptual_gain(self.volume.get()) * self.loudness_gain.get();
+        if volume_gain != self.volume_gain.target {
+            self.volume_gain.ramp_to(volume_gain, Self::VOLUME_RAMP, self.output.rate);
+        }
+        if self.balance.get() != self.balance_target {
+            // Turning towards one side lowers the other, keeping the centre at full level.
+            self.balance_target = self.balance.get();
+            let [left, right] = &mut self.balance_gains;
+            left.ramp_to((1. - self.balance_target).min(1.), Self::VOLUME_RAMP, self.output.rate);
+            right.ramp_to((1. + self.balance_target).min(1.), Self::VOLUME_RAMP, self.output.rate);
+        }
+
+        // Apply a changed delay: a longer one by playing silence, a shorter one by leaving out
+        // as much audio. The played silence counts as played audio, so that video synced to
+        // the audio clock is delayed less than the audio.
+        let delay_change = self.delay_ms.get() - self.applied_delay_ms;
+        self.applied_delay_ms = self.delay_ms.get();
+        let delay_frames = delay_change.unsigned_abs() as usize * self.output.rate as usize / 1000;
+        if delay_change > 0 {
+            let channels = resampled_frame.channels() as usize;
+            self.ffmpeg_to_cpal_pipe.forward_silence(delay_frames * channels).await;
+        } else {
+            self.pending_skip += delay_frames;
+        }
+        let skip = self.pending_skip.min(resampled_frame.samples());
+        self.pending_skip -= skip;
+        if skip == resampled_frame.samples() {
+            return;
+        }
+
+        // Forward the resampled audio frame to the CPAL audio output.
+        self.ffmpeg_to_cpal_pipe
+            .forward(
+                resampled_frame,
+                skip,
+                [&mut self.fade, &mut self.volume_gain],
+                &mut self.balance_gains,
+            )
+            .await;
+        self.feeding.store(true, Ordering::Relaxed);
+    }
+
+    /// Leaves out a frame of `input` audio that failed to convert for the output, reporting it
+    /// once for the frames like it that follow.
+    fn skip_unplayable(
+        &mut self,
+        input: ffmpeg_next::software::resampling::context::Definition,
+        error: ffmpeg_next::Error,
+    ) {
+        if self.unplayable_input.as_ref() != Some(&input) {
+            (self.error_callback)(format!("Failed to convert audio for output: {error}"));
+        }
+        self.unplayable_input = Some(input);
+    }
+
+    /// Drops the samples from before a jump that are buffered for the output device, so that
+    /// they don't play after it. New samples are only buffered once that happened.
+    async fn discard_buffered_output(&self) {
+        self.discard_buffered.store(true, Ordering::Release);
+        let deadline = std::time::Instant::now() + Self::DISCARD_TIMEOUT;
+        while self.discard_buffered.load(Ordering::Acquire) && std::time::Instant::now() < deadline
+        {
+            smol::Timer::after(std::time::Duration::from_millis(1)).await;
+        }
+    }
+
+    /// The number of output frames that play one second of the input at the current speed.
+    fn frames_per_input_second(&self) -> f64 {
+        self.output.rate as f64 / self.filter_speed as f64
+    }
+}
+
+/// The compressor of night mode. It cuts how far the audio rises above -20 dBFS to a quarter
+/// and makes up for the lost level, so that dialogue stays audible while loud scenes are tamed.
+const NIGHT_MODE_COMPRESSOR: &str =
+    "acompressor=threshold=0.1:ratio=4:attack=20:release=250:makeup=3";
+
+/// The number of bands of the equalizer.
+pub const EQUALIZER_BANDS: usize = 5;
+
+/// The centre frequencies of the equalizer bands, in Hz. Each band spans two octaves, reaching
+/// the centres of its neighbours.
+const EQUALIZER_FREQUENCIES: [u32; EQUALIZER_BANDS] = [60, 250, 1000, 4000, 12000];
+
+/// How far each equalizer band can be turned up or down, in decibels.
+const MAX_EQUALIZER_GAIN_DB: f32 = 12.;
+
+/// Whether the equalizer `gains` leave the audio unchanged.
+fn is_flat(gains: &[f32; EQUALIZER_BANDS]) -> bool {
+    gains.iter().all(|gain| *gain == 0.)
+}
+
+/// Creates a filter graph that changes the tempo of audio described by `input` to `speed` times
+/// the original while keeping its pitch, in `night_mode` compresses its dynamic range, and
+/// applies the `equalizer` gains, or `None` if all of them leave the audio unchanged. The
+/// `atempo` filter only accepts factors between 0.5 and 2 in older FFmpeg versions, so larger
+/// changes are chained.
+fn create_audio_filter(
+    input: &ffmpeg_next::software::resampling::context::Definition,
+    speed: f32,
+    night_mode: bool,
+    equalizer: &[f32; EQUALIZER_BANDS],
+) -> Result<Option<ffmpeg_next::filter::Graph>, ffmpeg_next::Error> {
+    if speed == 1. && !night_mode && is_flat(equalizer) {
+        return Ok(None);
+    }
+
+    let mut spec = Vec::new();
+    if !is_flat(equalizer) {
+        // Named, so that set_equalizer_gains() can address them.
+        spec.extend(EQUALIZER_FREQUENCIES.iter().zip(equalizer).enumerate().map(
+            |(band, (frequency, gain))| {
+                format!("equalizer@band{}=f={}:t=o:w=2:g={}", band, frequency, gain)
+            },
+        ));
+    }
+    if night_mode {
+        spec.push(NIGHT_MODE_COMPRESSOR.to_string());
+    }
+    if speed != 1. {
+        let mut factors = Vec::new();
+        let mut remaining = speed as f64;
+        while remaining > 2. {
+            factors.push(2.);
+            remaining /= 2.;
+        }
+        while remaining < 0.5 {
+            factors.push(0.5);
+            remaining /= 0.5;
+        }
+        factors.push(remaining);
+        spec.extend(factors.iter().map(|factor| format!("atempo={}", factor)));
+    }
+
+    let mut graph = ffmpeg_next::filter::Graph::new();
+    let args = format!(
+        "time_base=1/{rate}:sample_rate={rate}:sample_fmt={}:channel_layout=0x{:x}",
+        input.format.name(),
+        input.channel_layout.bits(),
+        rate = input.rate,
+    );
+    graph.add(&ffmpeg_next::filter::find("abuffer").unwrap(), "in", &args)?;
+    graph.add(&ffmpeg_next::filter::find("abuffersink").unwrap(), "out", "")?;
+    graph.output("in", 0)?.input("out", 0)?.parse(&spec.join(","))?;
+    graph.validate()?;
+
+    Ok(Some(graph))
 }
+
+/// Changes the gains of the equalizer filters of `graph`, which must have been created with
+/// equalizer gains that weren't flat, to `gains`.
+fn set_equalizer_gains(
+    graph: &mut ffmpeg_next::filter::Graph,
+    gains: &[f32; EQUALIZER_BANDS],
+) -> Result<(), ffmpeg_next::Error> {
+    for (band, gain) in gains.iter().enumerate() {
+        let target = std::ffi::CString::new(format!("equalizer@band{}", band)).unwrap();
+        let gain = std::ffi::CString::new(gain.to_string()).unwrap();
+        let result = unsafe {
+            ffmpeg_next::ffi::avfilter_graph_send_command(
+                graph.as_mut_ptr(),
+                target.as_ptr(),
+                c"g".as_ptr(),
+                gain.as_ptr(),
+                std::ptr::null_mut(),
+                0,
+                0,
+            )
+        };
+        if result < 0 {
+            return Err(ffmpeg_next::Error::from(result));
+        }
+    }
+    Ok(())
+}
+
+/// Describes the audio the decoder produces.
+fn decoder_output(
+    packet_decoder: &ffmpeg_next::decoder::Audio,
+) -> ffmpeg_next::software::resampling::context::Definition {
+    ffmpeg_next::software::resampling::context::Definition {
+        format: packet_decoder.format(),
+        channel_layout: known_channel_layout(
+            packet_decoder.channel_layout(),
+            packet_decoder.channels(),
+        ),
+        rate: packet_decoder.rate(),
+    }
+}
+
+/// Describes the audio of `frame`.
+pub(super) fn frame_definition(
+    frame: &ffmpeg_next::util::frame::Audio,
+) -> ffmpeg_next::software::resampling::context::Definition {
+    ffmpeg_next::software::resampling::context::Definition {
+        format: frame.format(),
+        channel_layout: known_channel_layout(frame.channel_layout(), frame.channels()),
+        rate: frame.rate(),
+    }
+}
+
+/// `layout`, or the usual layout for `channels` channels if it is empty. Decoders that don't
+/// know the channel order only set the number of channels, but downmixing has to know which of
+/// them is the center channel, for example, to keep the dialog.
+fn known_channel_layout(
+    layout: ffmpeg_next::util::channel_layout::ChannelLayout,
+    channels: u16,
+) -> ffmpeg_next::util::channel_layout::ChannelLayout {
+    if layout.is_empty() {
+        ffmpeg_next::util::channel_layout::ChannelLayout::default(channels.into())
+    } else {
+        layout
+    }
+}
+
+/// Creates the resampler that converts decoded audio described by `input` to the format of the
+/// cpal stream described by `output`. This only depends on the two descriptions, so it can be
+/// exercised with synthetic frames without a decoder or an audio device. When the output has
+/// fewer channels than the input, `downmix` sets the levels of the mixing matrix.
+pub(super) fn create_resampler(
+    input: &ffmpeg_next::software::resampling::context::Definition,
+    output: &ffmpeg_next::software::resampling::context::Definition,
+    downmix: DownmixMatrix,
+) -> Result<ffmpeg_next::software::resampling::Context, ffmpeg_next::Error> {
+    let mut resampler = ffmpeg_next::software::resampling::Context::get(
+        input.format,
+        input.channel_layout,
+        input.rate,
+        output.format,
+        output.channel_layout,
+        output.rate,
+    )?;
+
+    if input.channel_layout.channels() > output.channel_layout.channels() {
+        let matrix_encoding = if downmix.dolby_surround {
+            ffmpeg_next::ffi::AVMatrixEncoding::AV_MATRIX_ENCODING_DOLBY
+        } else {
+            ffmpeg_next::ffi::AVMatrixEncoding::AV_MATRIX_ENCODING_NONE
+        };
+
+        // The resampling wrapper doesn't expose the mixing options, so set them on the
+        // underlying SwrContext and initialize it again to rebuild the matrix.
+        unsafe {
+            let swr_context = resampler.as_mut_ptr();
+            let options = swr_context as *mut std::ffi::c_void;
+            ffmpeg_next::ffi::av_opt_set_double(options, c"clev".as_ptr(), downmix.center_level, 0);
+            ffmpeg_next::ffi::av_opt_set_double(
+                options,
+                c"slev".as_ptr(),
+                downmix.surround_level,
+                0,
+            );
+            ffmpeg_next::ffi::av_opt_set_double(
+                options,
+                c"lfe_mix_level".as_ptr(),
+                downmix.lfe_level,
+                0,
+            );
+            ffmpeg_next::ffi::av_opt_set_int(
+                options,
+                c"matrix_encoding".as_ptr(),
+                matrix_encoding as i64,
+                0,
+            );
+            match ffmpeg_next::ffi::swr_init(swr_context) {
+                error if error < 0 => return Err(ffmpeg_next::Error::from(error)),
+                _ => {}
+            }
+        }
+    }
+
+    Ok(resampler)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ffmpeg_next::util::channel_layout::ChannelLayout;
+    use ffmpeg_next::util::format::sample::{Sample as SampleFormat, Type as SampleType};
+
+    const PACKED_F32: SampleFormat = SampleFormat::F32(SampleType::Packed);
+
+    fn definition(
+        channel_layout: ChannelLayout,
+        rate: u32,
+    ) -> ffmpeg_next::software::resampling::context::Definition {
+        ffmpeg_next::software::resampling::context::Definition {
+            format: PACKED_F32,
+            channel_layout,
+            rate,
+        }
+    }
+
+    /// A packed F32 frame of `samples` audio frames, with `sample(frame, channel)` in each
+    /// channel.
+    fn synthetic_frame(
+        input: &ffmpeg_next::software::resampling::context::Definition,
+        samples: usize,
+        sample: impl Fn(usize, usize) -> f32,
+    ) -> ffmpeg_next::util::frame::Audio {
+        let mut frame =
+            ffmpeg_next::util::frame::Audio::new(input.format, samples, input.channel_layout);
+        frame.set_rate(input.rate);
+        let channels = input.channel_layout.channels() as usize;
+        // Planes may be padded past the samples.
+        let data: &mut [f32] =
+            bytemuck::cast_slice_mut(&mut frame.data_mut(0)[..samples * channels * 4]);
+        for (index, value) in data.iter_mut().enumerate() {
+            *value = sample(index / channels, index % channels);
+        }
+        frame
+    }
+
+    /// Resamples `frame` to `output` and flushes the resampler, returning all output samples
+    /// interleaved, along with the number of samples the resampler held back after the run.
+    fn resample(
+        frame: &ffmpeg_next::util::frame::Audio,
+        output: &ffmpeg_next::software::resampling::context::Definition,
+        downmix: DownmixMatrix,
+    ) -> (Vec<f32>, usize) {
+        let mut resampler = create_resampler(&frame_definition(frame), output, downmix).unwrap();
+        let channels = output.channel_layout.channels() as usize;
+        let mut samples = Vec::new();
+        let mut append = |frame: &ffmpeg_next::util::frame::Audio| {
+            if frame.samples() > 0 {
+                let bytes = &frame.data(0)[..frame.samples() * channels * 4];
+                samples.extend_from_slice(bytemuck::cast_slice::<u8, f32>(bytes));
+            }
+        };
+
+        let mut resampled = ffmpeg_next::util::frame::Audio::empty();
+        resampler.run(frame, &mut resampled).unwrap();
+        append(&resampled);
+        let held_back = resampler.delay().map_or(0, |delay| delay.output.max(0) as usize);
+
+        let mut flushed = ffmpeg_next::util::frame::Audio::empty();
+        resampler.flush(&mut flushed).unwrap();
+        append(&flushed);
+        (samples, held_back)
+    }
+
+    /// Mixes audio of `layout` with `value` in `channel` and silence in the others down to
+    /// stereo at the same rate, returning the left and right samples.
+    fn downmix_channel(
+        layout: ChannelLayout,
+        channel: ChannelLayout,
+        value: f32,
+        downmix: DownmixMatrix,
+    ) -> Vec<[f32; 2]> {
+        ffmpeg_next::init().unwrap();
+        // Channels are ordered like the bits of their layout.
+        let index = (layout.bits() & (channel.bits() - 1)).count_ones() as usize;
+        let input = definition(layout, 48000);
+        let frame = synthetic_frame(&input, 480, |_, c| if c == index { value } else { 0. });
+
+        let (samples, _) = resample(&frame, &definition(ChannelLayout::STEREO, 48000), downmix);
+        samples.chunks_exact(2).map(|pair| [pair[0], pair[1]]).collect()
+    }
+
+    #[test]
+    fn center_level_sets_the_share_of_the_center() {
+        for downmix in [
+            DownmixMatrix::DEFAULT,
+            DownmixMatrix::DIALOG_BOOST,
+            DownmixMatrix { center_level: 0.25, ..DownmixMatrix::DEFAULT },
+        ] {
+            let output =
+                downmix_channel(ChannelLayout::_5POINT1, ChannelLayout::FRONT_CENTER, 0.5, downmix);
+
+            assert!(!output.is_empty());
+            let expected = 0.5 * downmix.center_level as f32;
+            for [left, right] in output {
+                assert!((left - expected).abs() < 1e-3, "{left} instead of {expected}");
+                assert!((right - expected).abs() < 1e-3, "{right} instead of {expected}");
+            }
+        }
+    }
+
+    #[test]
+    fn center_channel_reaches_both_stereo_channels() {
+        let output = downmix_channel(
+            ChannelLayout::_5POINT1,
+            ChannelLayout::FRONT_CENTER,
+            0.5,
+            DownmixMatrix::default(),
+        );
+
+        assert!(!output.is_empty());
+        for [left, right] in output {
+            assert!(left > 0.1 && (left - right).abs() < 1e-3, "{left} and {right}");
+        }
+    }
+
+    #[test]
+    fn center_channel_of_an_unknown_layout_reaches_both_stereo_channels() {
+        // As decoders that only know the number of channels describe their frames.
+        let layout = known_channel_layout(ChannelLayout::empty(), 6);
+        assert_eq!(layout.channels(), 6);
+        assert!(layout.contains(ChannelLayout::FRONT_CENTER));
+
+        let output =
+            downmix_channel(layout, ChannelLayout::FRONT_CENTER, 0.5, DownmixMatrix::default());
+
+        assert!(!output.is_empty());
+        for [left, right] in output {
+            assert!(left > 0.1 && (left - right).abs() < 1e-3, "{left} and {right}");
+        }
+    }
+
+    #[test]
+    fn dolby_surround_mixes_the_surround_out_of_phase() {
+        let output = downmix_channel(
+            ChannelLayout::_5POINT1,
+            ChannelLayout::SIDE_LEFT,
+            0.5,
+            DownmixMatrix::DOLBY_SURROUND,
+        );
+
+        assert!(!output.is_empty());
+        for [left, right] in output {
+            assert!(left * right < 0., "{left} and {right} are in phase");
+        }
+    }
+
+    #[test]
+    fn resampling_keeps_the_duration() {
+        ffmpeg_next::init().unwrap();
+        let input = definition(ChannelLayout::STEREO, 44100);
+        let output = definition(ChannelLayout::STEREO, 48000);
+        let samples = 4410;
+        let frame = synthetic_frame(&input, samples, |index, _| {
+            (index as f32 * 440. * std::f32::consts::TAU / 44100.).sin() * 0.5
+        });
+
+        let (resampled, held_back) = resample(&frame, &output, DownmixMatrix::default());
+
+        let expected = samples * 48000 / 44100;
+        let produced = resampled.len() / 2;
+        assert!(
+            produced.abs_diff(expected) <= held_back + 1,
+            "{} samples instead of {}, {} held back",
+            produced,
+            expected,
+            held_back
+        );
+    }
 }
@@ -0,0 +1,764 @@
+// Copyright © SixtyFPS GmbH <info@slint.dev>
+// SPDX-License-Identifier: MIT
+
+use std::io::{Seek, SeekFrom, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::Context;
+use ffmpeg_next::Rescale;
+
+use super::audio::{create_resampler, frame_definition};
+use super::video::create_filter_graph;
+use super::DownmixMatrix;
+
+/// How [`export_clip`] writes the clip.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ClipMode {
+    /// Copies the compressed packets, which is quick and lossless, but the video can only start
+    /// at a keyframe. Players that honour edit lists still start at the chosen position.
+    #[default]
+    StreamCopy,
+    /// Decodes and encodes again with the default encoders of the container, to start and end
+    /// exactly at the chosen positions.
+    Reencode,
+}
+
+/// Decodes an audio stream of `source` from start to finish and writes it to `destination` as a
+/// 16-bit PCM WAV file, keeping its channels and sample rate. `stream_index` selects the stream
+/// by its index in [`super::MediaSummary::streams`], or the default audio stream with `None`.
+///
+/// `progress` is called with the fraction of the input that was exported so far, when the
+/// duration is known. Setting `cancelled` stops the export. Returns `Ok(false)` if it was
+/// cancelled. No file is left behind when the export is cancelled or fails.
+pub fn export_audio(
+    source: &std::path::Path,
+    stream_index: Option<usize>,
+    destination: &std::path::Path,
+    cancelled: &AtomicBool,
+    progress: impl FnMut(f64),
+) -> Result<bool, anyhow::Error> {
+    let result = write_wav(source, stream_index, destination, cancelled, Progress::new(progress));
+    remove_unless_finished(destination, result)
+}
+
+/// Writes the video and audio of `source` between `start` and `end` (in seconds) to
+/// `destination`, in the container its extension names. An `end` past the end of the input
+/// exports up to the end.
+///
+/// `progress` is called with the fraction of the clip that was exported so far. Setting
+/// `cancelled` stops the export. Returns `Ok(false)` if it was cancelled. No file is left
+/// behind when the export is cancelled or fails.
+pub fn export_clip(
+    source: &std::path::Path,
+    (start, end): (f64, f64),
+    destination: &std::path::Path,
+    mode: ClipMode,
+    cancelled: &AtomicBool,
+    progress: impl FnMut(f64),
+) -> Result<bool, anyhow::Error> {
+    let clip = Clip::open(source, start, end, Progress::new(progress))?;
+    let result = match mode {
+        ClipMode::StreamCopy => clip.copy(destination, cancelled),
+        ClipMode::Reencode => clip.reencode(destination, &TranscodeOptions::default(), cancelled),
+    };
+    remove_unless_finished(destination, result)
+}
+
+/// How [`transcode`] encodes, where it doesn't follow the input or the output container.
+#[derive(Clone, Debug, Default)]
+pub struct TranscodeOptions {
+    /// The name of the FFmpeg encoder for the video, like `libx264`, instead of the default
+    /// encoder of the output container.
+    pub video_codec: Option<String>,
+    /// The name of the FFmpeg encoder for the audio, like `aac`.
+    pub audio_codec: Option<String>,
+    /// The width and height to scale the video to, instead of keeping its size.
+    pub resolution: Option<(u32, u32)>,
+}
+
+/// Decodes the video and audio of `source` and encodes them again into `destination`, in the
+/// container its extension names, as set by `options`.
+///
+/// `progress` is called with the fraction of the input that was transcoded so far, when its
+/// duration is known. Setting `cancelled` stops transcoding. Returns `Ok(false)` if it was
+/// cancelled. No file is left behind when transcoding is cancelled or fails.
+pub fn transcode(
+    source: &std::path::Path,
+    destination: &std::path::Path,
+    options: &TranscodeOptions,
+    cancelled: &AtomicBool,
+    progress: impl FnMut(f64),
+) -> Result<bool, anyhow::Error> {
+    let clip = Clip::open(source, 0., f64::INFINITY, Progress::new(progress))?;
+    remove_unless_finished(destination, clip.reencode(destination, options, cancelled))
+}
+
+fn remove_unless_finished(
+    destination: &std::path::Path,
+    result: Result<bool, anyhow::Error>,
+) -> Result<bool, anyhow::Error> {
+    if !matches!(result, Ok(true)) {
+        std::fs::remove_file(destination).ok();
+    }
+    result
+}
+
+/// Passes the progress of an export on, in steps of a percent to not flood the UI.
+struct Progress<F: FnMut(f64)> {
+    report: F,
+    last: f64,
+}
+
+impl<F: FnMut(f64)> Progress<F> {
+    fn new(report: F) -> Self {
+        Self { report, last: -1. }
+    }
+
+    fn update(&mut self, fraction: f64) {
+        let fraction = fraction.clamp(0., 1.);
+        if (fraction - self.last).abs() >= 0.01 {
+            self.last = fraction;
+            (self.report)(fraction);
+        }
+    }
+}
+
+fn write_wav(
+    source: &std::path::Path,
+    stream_index: Option<usize>,
+    destination: &std::path::Path,
+    cancelled: &AtomicBool,
+    mut progress: Progress<impl FnMut(f64)>,
+) -> Result<bool, anyhow::Error> {
+    let mut input_context = ffmpeg_next::format::input(&source)
+        .with_context(|| format!("Failed to open {}", source.display()))?;
+
+    let stream = match stream_index {
+        Some(index) => input_context
+            .stream(index)
+            .filter(|stream| stream.parameters().medium() == ffmpeg_next::media::Type::Audio),
+        None => input_context.streams().best(ffmpeg_next::media::Type::Audio),
+    }
+    .context("The input has no audio stream")?;
+    let stream_index = stream.index();
+    let time_base = f64::from(stream.time_base());
+    let start_time = match stream.start_time() {
+        ffmpeg_next::ffi::AV_NOPTS_VALUE => 0.,
+        start_time => start_time as f64 * time_base,
+    };
+    let duration = (input_context.duration() > 0)
+        .then(|| input_context.duration() as f64 / f64::from(ffmpeg_next::ffi::AV_TIME_BASE));
+
+    let decoder_context = ffmpeg_next::codec::Context::from_parameters(stream.parameters())?;
+    let mut decoder = decoder_context.decoder().audio()?;
+
+    let mut wav = None;
+    let mut resampler: Option<ffmpeg_next::software::resampling::Context> = None;
+    let mut frame = ffmpeg_next::util::frame::Audio::empty();
+
+    // Resamples and writes the frames the decoder has ready.
+    let mut write_decoded = |decoder: &mut ffmpeg_next::decoder::Audio,
+                             wav: &mut Option<WavWriter>,
+                             resampler: &mut Option<ffmpeg_next::software::resampling::Context>|
+     -> Result<(), anyhow::Error> {
+        while decoder.receive_frame(&mut frame).is_ok() {
+            // The output keeps the channels and rate of the first frame, later changes of the
+            // stream are converted to them.
+            let input = frame_definition(&frame);
+            if wav.is_none() {
+                let channels = input.channel_layout.channels() as u16;
+                *wav = Some(WavWriter::create(destination, channels, input.rate)?);
+            }
+            let wav = wav.as_mut().unwrap();
+            if resampler.as_ref().map_or(true, |resampler| *resampler.input() != input) {
+                let output = ffmpeg_next::software::resampling::context::Definition {
+                    format: ffmpeg_next::util::format::sample::Sample::I16(
+                        ffmpeg_next::util::format::sample::Type::Packed,
+                    ),
+                    channel_layout: ffmpeg_next::util::channel_layout::ChannelLayout::default(
+                        wav.channels.into(),
+                    ),
+                    rate: wav.rate,
+                };
+                *resampler = Some(create_resampler(&input, &output, DownmixMatrix::default())?);
+            }
+            let resampler = resampler.as_mut().unwrap();
+
+            let mut resampled_frame = ffmpeg_next::util::frame::Audio::empty();
+            resampler.run(&frame, &mut resampled_frame)?;
+            wav.write_frame(&resampled_frame)?;
+
+            if let (Some(duration), Some(pts)) = (duration, frame.timestamp()) {
+                progress.update((pts as f64 * time_base - start_time) / duration);
+            }
+        }
+        Ok(())
+    };
+
+    for (stream, packet) in input_context.packets() {
+        if cancelled.load(Ordering::Relaxed) {
+            return Ok(false);
+        }
+        if stream.index() != stream_index {
+            continue;
+        }
+        // Skip damaged packets, like playback does.
+        if decoder.send_packet(&packet).is_ok() {
+            write_decoded(&mut decoder, &mut wav, &mut resampler)?;
+        }
+    }
+    decoder.send_eof()?;
+    write_decoded(&mut decoder, &mut wav, &mut resampler)?;
+
+    let mut wav = wav.context("The audio stream has no decodable audio")?;
+    // The resampler may hold back a few samples to resample the next ones.
+    if let Some(resampler) = resampler.as_mut() {
+        let mut resampled_frame = ffmpeg_next::util::frame::Audio::empty();
+        if resampler.flush(&mut resampled_frame).is_ok() && resampled_frame.samples() > 0 {
+            wav.write_frame(&resampled_frame)?;
+        }
+    }
+    wav.finish()?;
+    Ok(true)
+}
+
+/// An opened input to export a clip of.
+struct Clip<F: FnMut(f64)> {
+    input_context: ffmpeg_next::format::context::Input,
+    /// In seconds.
+    start: f64,
+    end: f64,
+    /// The video and audio streams, which are exported.
+    stream_indices: Vec<usize>,
+    progress: Progress<F>,
+}
+
+impl<F: FnMut(f64)> Clip<F> {
+    fn open(
+        source: &std::path::Path,
+        start: f64,
+        end: f64,
+        progress: Progress<F>,
+    ) -> Result<Self, anyhow::Error> {
+        let mut input_context = ffmpeg_next::format::input(&source)
+            .with_context(|| format!("Failed to open {}", source.display()))?;
+        // The clip ends with the input.
+        let duration = input_context.duration() as f64 / f64::from(ffmpeg_next::ffi::AV_TIME_BASE);
+        let end = if input_context.duration() > 0 { end.min(duration) } else { end };
+        if start >= end {
+            anyhow::bail!("The clip starts after the end of the input");
+        }
+
+        let stream_indices = input_context
+            .streams()
+            .filter(|stream| {
+                matches!(
+                    stream.parameters().medium(),
+                    ffmpeg_next::media::Type::Video | ffmpeg_next::media::Type::Audio
+                )
+            })
+            .map(|stream| stream.index())
+            .collect::<Vec<_>>();
+        if stream_indices.is_empty() {
+            anyhow::bail!("The input has no video or audio to export");
+        }
+
+        // To the keyframe at or before the start, which decoding has to begin with. Inputs are
+        // read from the start anyway, and some can't seek.
+        if start > 0. {
+            let timestamp = (start * f64::from(ffmpeg_next::ffi::AV_TIME_BASE)) as i64;
+            input_context.seek(timestamp, ..timestamp)?;
+        }
+
+        Ok(Self { input_context, start, end, stream_indices, progress })
+    }
+
+    /// Remuxes the packets between the keyframe before the start and the end.
+    fn copy(
+        mut self,
+        destination: &std::path::Path,
+        cancelled: &AtomicBool,
+    ) -> Result<bool, anyhow::Error> {
+        let mut output_context = ffmpeg_next::format::output(&destination)
+            .with_context(|| format!("Failed to create {}", destination.display()))?;
+
+        // The output stream and time base of each input stream, by index.
+        let mut stream_mapping = vec![None; self.input_context.nb_streams() as usize];
+        for &index in &self.stream_indices {
+            let input_stream = self.input_context.stream(index).unwrap();
+            let mut output_stream = output_context
+                .add_stream(ffmpeg_next::encoder::find(ffmpeg_next::codec::Id::None))?;
+            output_stream.set_parameters(input_stream.parameters());
+            // The tag of the input container may mean something else in the output container.
+            unsafe {
+                (*output_stream.parameters().as_mut_ptr()).codec_tag = 0;
+            }
+            stream_mapping[index] = Some((output_stream.index(), input_stream.time_base()));
+        }
+        output_context.set_metadata(self.input_context.metadata().to_owned());
+        output_context
+            .write_header()
+            .context("The container can't hold the streams of the input without re-encoding")?;
+
+        let mut ended = vec![false; stream_mapping.len()];
+        let mut written = false;
+        for (stream, mut packet) in self.input_context.packets() {
+            if cancelled.load(Ordering::Relaxed) {
+                return Ok(false);
+            }
+            let Some((output_index, time_base)) = stream_mapping[stream.index()] else { continue };
+            let seconds = |timestamp: i64| timestamp as f64 * f64::from(time_base);
+            let Some(dts) = packet.dts().or(packet.pts()) else { continue };
+            if seconds(dts) >= self.end {
+                ended[stream.index()] = true;
+                if self.stream_indices.iter().all(|&index| ended[index]) {
+                    break;
+                }
+            }
+            let pts = packet.pts().unwrap_or(dts);
+            // The video from the keyframe is needed to decode the start, earlier audio isn't.
+            let video = stream.parameters().medium() == ffmpeg_next::media::Type::Video;
+            if seconds(pts) >= self.end || (!video && seconds(pts) < self.start) {
+                continue;
+            }
+            self.progress.update((seconds(dts) - self.start) / (self.end - self.start));
+
+            // Timestamps start at the start of the clip, so the video before it has negative
+            // ones.
+            let offset = (self.start / f64::from(time_base)).round() as i64;
+            packet.set_pts(packet.pts().map(|pts| pts - offset));
+            packet.set_dts(packet.dts().map(|dts| dts - offset));
+            let output_time_base = output_context.stream(output_index).unwrap().time_base();
+            packet.rescale_ts(time_base, output_time_base);
+            packet.set_stream(output_index);
+            packet.set_position(-1);
+            packet.write_interleaved(&mut output_context)?;
+            written = true;
+        }
+        if !written {
+            anyhow::bail!("The clip starts after the end of the input");
+        }
+        output_context.write_trailer()?;
+        Ok(true)
+    }
+
+    /// Decodes the frames between the start and the end and encodes them again.
+    fn reencode(
+        mut self,
+        destination: &std::path::Path,
+        options: &TranscodeOptions,
+        cancelled: &AtomicBool,
+    ) -> Result<bool, anyhow::Error> {
+        let mut output_context = ffmpeg_next::format::output(&destination)
+            .with_context(|| format!("Failed to create {}", destination.display()))?;
+
+        let mut transcoders = Vec::new();
+        for &index in &self.stream_indices {
+            let input_stream = self.input_context.stream(index).unwrap();
+            // Streams the container has no encoder for are left out.
+            if let Some(transcoder) =
+                Transcoder::new(&input_stream, destination, &mut output_context, options)?
+            {
+                transcoders.push(transcoder);
+            }
+        }
+        if transcoders.is_empty() {
+            anyhow::bail!("The container has no encoders for the streams of the input");
+        }
+        output_context.set_metadata(self.input_context.metadata().to_owned());
+        output_context.write_header()?;
+        for transcoder in &mut transcoders {
+            transcoder.output_time_base =
+                output_context.stream(transcoder.output_index).unwrap().time_base();
+        }
+
+        for (stream, packet) in self.input_context.packets() {
+            if cancelled.load(Ordering::Relaxed) {
+                return Ok(false);
+            }
+            let Some(transcoder) = transcoders
+                .iter_mut()
+                .find(|transcoder| transcoder.input_index == stream.index() && !transcoder.ended)
+            else {
+                continue;
+            };
+            // Skip damaged packets, like playback does.
+            if transcoder.decoder.send_packet(&packet).is_err() {
+                continue;
+            }
+            if let Some(time) =
+                transcoder.receive_frames(self.start, self.end, &mut output_context)?
+            {
+                self.progress.update((time - self.start) / (self.end - self.start));
+            }
+            if transcoders.iter().all(|transcoder| transcoder.ended) {
+                break;
+            }
+        }
+
+        for transcoder in &mut transcoders {
+            transcoder.finish(self.start, self.end, &mut output_context)?;
+        }
+        if !transcoders.iter().any(|transcoder| transcoder.written) {
+            anyhow::bail!("The clip starts after the end of the input");
+        }
+        output_context.write_trailer()?;
+        Ok(true)
+    }
+}
+
+/// Decodes a stream of the input, converts its frames to the format of the encoder with a
+/// filter graph, and encodes them into a stream of the output.
+struct Transcoder {
+    input_index: usize,
+    output_index: usize,
+    input_time_base: ffmpeg_next::Rational,
+    /// The time bases of the frames going into and coming out of the filter graph.
+    source_time_base: ffmpeg_next::Rational,
+    sink_time_base: ffmpeg_next::Rational,
+    encoder_time_base: ffmpeg_next::Rational,
+    /// Only known once the header is written.
+    output_time_base: ffmpeg_next::Rational,
+    decoder: ffmpeg_next::decoder::Opened,
+    graph: ffmpeg_next::filter::Graph,
+    encoder: ffmpeg_next::encoder::Encoder,
+    /// Whether a frame at or past the end of the clip was decoded.
+    ended: bool,
+    /// Whether any packet was written.
+    written: bool,
+}
+
+impl Transcoder {
+    /// Adds a stream for `input_stream` to `output_context`, encoded as `options` ask, or
+    /// returns `None` if they don't name an encoder and the container has none for the medium.
+    fn new(
+        input_stream: &ffmpeg_next::format::stream::Stream,
+        destination: &std::path::Path,
+        output_context: &mut ffmpeg_next::format::context::Output,
+        options: &TranscodeOptions,
+    ) -> Result<Option<Self>, anyhow::Error> {
+        let medium = input_stream.parameters().medium();
+        let (kind, codec_name) = match medium {
+            ffmpeg_next::media::Type::Video => ("video", &options.video_codec),
+            _ => ("audio", &options.audio_codec),
+        };
+        let codec = match codec_name {
+            Some(name) => Some(
+                ffmpeg_next::encoder::find_by_name(name)
+                    .filter(|codec| codec.medium() == medium)
+                    .with_context(|| format!("There is no {} encoder called {}", kind, name))?,
+            ),
+            None => ffmpeg_next::encoder::find(output_context.format().codec(&destination, medium)),
+        };
+        let Some(codec) = codec else {
+            return Ok(None);
+        };
+        let global_header =
+            output_context.format().flags().contains(ffmpeg_next::format::Flags::GLOBAL_HEADER);
+        let decoder_context =
+            ffmpeg_next::codec::Context::from_parameters(input_stream.parameters())?;
+        let input_time_base = input_stream.time_base();
+
+        let encoder_context = ffmpeg_next::codec::Context::new_with_codec(codec);
+        let (decoder, graph, encoder, [source_time_base, sink_time_base, encoder_time_base]) =
+            match medium {
+                ffmpeg_next::media::Type::Video => {
+                    let decoder = decoder_context.decoder().video()?;
+                    let mut encoder = encoder_context.encoder().video()?;
+                    // The decoder's format, if the encoder takes it.
+                    let format = match codec.video()?.formats() {
+                        Some(formats) => {
+                            let formats = formats.collect::<Vec<_>>();
+                            if formats.contains(&decoder.format()) || formats.is_empty() {
+                                decoder.format()
+                            } else {
+                                formats[0]
+                            }
+                        }
+                        None => decoder.format(),
+                    };
+                    // Encoders like MPEG-4 don't take the fine time bases of containers.
+                    let frame_rate = input_stream.avg_frame_rate();
+                    let encoder_time_base = if frame_rate.numerator() > 0 {
+                        frame_rate.invert()
+                    } else {
+                        input_time_base
+                    };
+                    let (width, height) =
+                        options.resolution.unwrap_or((decoder.width(), decoder.height()));
+                    encoder.set_width(width);
+                    encoder.set_height(height);
+                    encoder.set_format(format);
+                    // Scaling to a chosen resolution makes the pixels square.
+                    if options.resolution.is_none() {
+                        encoder.set_aspect_ratio(decoder.aspect_ratio());
+                    }
+                    encoder.set_frame_rate((frame_rate.numerator() > 0).then_some(frame_rate));
+                    encoder.set_time_base(encoder_time_base);
+                    if decoder.bit_rate() > 0 {
+                        encoder.set_bit_rate(decoder.bit_rate());
+                    }
+                    if global_header {
+                        encoder.set_flags(ffmpeg_next::codec::Flags::GLOBAL_HEADER);
+                    }
+                    let scale = options.resolution.map_or(String::new(), |(width, height)| {
+                        format!("scale={}:{},setsar=1,", width, height)
+                    });
+                    let spec = format!(
+                        "{}format=pix_fmts={}",
+                        scale,
+                        format.descriptor().map_or("yuv420p", |descriptor| descriptor.name())
+                    );
+                    let graph = create_filter_graph(
+                        decoder.format(),
+                        (decoder.width(), decoder.height()),
+                        decoder.aspect_ratio(),
+                        input_time_base,
+                        &spec,
+                    )?;
+                    let encoder = encoder.open_as(codec)?;
+                    output_context.add_stream(codec)?.set_parameters(&encoder);
+                    let time_bases = [input_time_base, input_time_base, encoder_time_base];
+                    (decoder.0, graph, encoder.0 .0, time_bases)
+                }
+                _ => {
+                    let decoder = decoder_context.decoder().audio()?;
+                    let mut encoder = encoder_context.encoder().audio()?;
+                    let audio_codec = codec.audio()?;
+                    let format = audio_codec
+                        .formats()
+                        .and_then(|mut formats| formats.next())
+                        .unwrap_or(decoder.format());
+                    // The decoder's rate, if the encoder takes it.
+                    let rate = match audio_codec.rates() {
+                        Some(rates) => {
+                            let rates = rates.collect::<Vec<_>>();
+                            if rates.contains(&(decoder.rate() as i32)) || rates.is_empty() {
+                                decoder.rate()
+                            } else {
+                                rates[0] as u32
+                            }
+                        }
+                        None => decoder.rate(),
+                    };
+                    let channel_layout = audio_codec
+                        .channel_layouts()
+                        .map(|layouts| layouts.best(decoder.channel_layout().channels()))
+                        .unwrap_or(ffmpeg_next::util::channel_layout::ChannelLayout::STEREO);
+                    let encoder_time_base = ffmpeg_next::Rational::new(1, rate as i32);
+                    encoder.set_rate(rate as i32);
+                    encoder.set_channel_layout(channel_layout);
+                    encoder.set_format(format);
+                    encoder.set_time_base(encoder_time_base);
+                    if decoder.bit_rate() > 0 {
+                        encoder.set_bit_rate(decoder.bit_rate());
+                    }
+                    if global_header {
+                        encoder.set_flags(ffmpeg_next::codec::Flags::GLOBAL_HEADER);
+                    }
+                    let encoder = encoder.open_as(codec)?;
+                    output_context.add_stream(codec)?.set_parameters(&encoder);
+
+                    // Decoders that don't know the channel order only set the number of channels.
+                    let input_channel_layout = match decoder.channel_layout() {
+                        layout if layout.is_empty() => {
+                            ffmpeg_next::util::channel_layout::ChannelLayout::default(
+                                decoder.channels().into(),
+                            )
+                        }
+                        layout => layout,
+                    };
+                    let mut graph = ffmpeg_next::filter::Graph::new();
+                    let args = format!(
+                        "time_base=1/{rate}:sample_rate={rate}:sample_fmt={}:channel_layout=0x{:x}",
+                        decoder.format().name(),
+                        input_channel_layout.bits(),
+                        rate = decoder.rate(),
+                    );
+                    graph.add(&ffmpeg_next::filter::find("abuffer").unwrap(), "in", &args)?;
+                    graph.add(&ffmpeg_next::filter::find("abuffersink").unwrap(), "out", "")?;
+                    let spec = format!(
+                        "aformat=sample_fmts={}:sample_rates={}:channel_layouts=0x{:x}",
+                        format.name(),
+                        rate,
+                        channel_layout.bits(),
+                    );
+                    graph.output("in", 0)?.input("out", 0)?.parse(&spec)?;
+                    graph.validate()?;
+                    // Most audio encoders take frames of a fixed size.
+                    if !codec
+                        .capabilities()
+                        .contains(ffmpeg_next::codec::Capabilities::VARIABLE_FRAME_SIZE)
+                    {
+                        graph.get("out").unwrap().sink().set_frame_size(encoder.frame_size());
+                    }
+                    // Converting the rate gives the frames the encoder's time base.
+                    let source_time_base = ffmpeg_next::Rational::new(1, decoder.rate() as i32);
+                    let time_bases = [source_time_base, encoder_time_base, encoder_time_base];
+                    (decoder.0, graph, encoder.0 .0, time_bases)
+                }
+            };
+
+        Ok(Some(Self {
+            input_index: input_stream.index(),
+            output_index: output_context.nb_streams() as usize - 1,
+            input_time_base,
+            source_time_base,
+            sink_time_base,
+            encoder_time_base,
+            output_time_base: encoder_time_base,
+            decoder,
+            graph,
+            encoder,
+            ended: false,
+            written: false,
+        }))
+    }
+
+    /// Filters and encodes the frames the decoder has ready that are within the clip. Returns
+    /// the time of the last one, in seconds.
+    fn receive_frames(
+        &mut self,
+        start: f64,
+        end: f64,
+        output_context: &mut ffmpeg_next::format::context::Output,
+    ) -> Result<Option<f64>, anyhow::Error> {
+        let mut time = None;
+        let mut frame = unsafe { ffmpeg_next::util::frame::Frame::empty() };
+        while !self.ended && self.decoder.receive_frame(&mut frame).is_ok() {
+            let Some(timestamp) = frame.timestamp() else { continue };
+            let seconds = timestamp as f64 * f64::from(self.input_time_base);
+            if seconds >= end {
+                self.ended = true;
+                break;
+            }
+            if seconds < start {
+                continue;
+            }
+            // Timestamps start at the start of the clip.
+            let offset = (start / f64::from(self.input_time_base)).round() as i64;
+            frame.set_pts(Some(
+                (timestamp - offset).rescale(self.input_time_base, self.source_time_base),
+            ));
+            self.graph.get("in").unwrap().source().add(&frame)?;
+            self.encode_filtered(output_context)?;
+            time = Some(seconds);
+        }
+        Ok(time)
+    }
+
+    /// Drains the decoder, the filter graph and the encoder.
+    fn finish(
+        &mut self,
+        start: f64,
+        end: f64,
+        output_context: &mut ffmpeg_next::format::context::Output,
+    ) -> Result<(), anyhow::Error> {
+        if !self.ended {
+            self.decoder.send_eof()?;
+            self.receive_frames(start, end, output_context)?;
+        }
+        self.graph.get("in").unwrap().source().flush()?;
+        self.encode_filtered(output_context)?;
+        self.encoder.send_eof()?;
+        self.write_packets(output_context)
+    }
+
+    fn encode_filtered(
+        &mut self,
+        output_context: &mut ffmpeg_next::format::context::Output,
+    ) -> Result<(), anyhow::Error> {
+        let mut filtered = unsafe { ffmpeg_next::util::frame::Frame::empty() };
+        while self.graph.get("out").unwrap().sink().frame(&mut filtered).is_ok() {
+            filtered.set_pts(
+                filtered.pts().map(|pts| pts.rescale(self.sink_time_base, self.encoder_time_base)),
+            );
+            self.encoder.send_frame(&filtered)?;
+            self.write_packets(output_context)?;
+        }
+        Ok(())
+    }
+
+    fn write_packets(
+        &mut self,
+        output_context: &mut ffmpeg_next::format::context::Output,
+    ) -> Result<(), anyhow::Error> {
+        let mut packet = ffmpeg_next::Packet::empty();
+        while self.encoder.receive_packet(&mut packet).is_ok() {
+            packet.set_stream(self.output_index);
+            packet.rescale_ts(self.encoder_time_base, self.output_time_base);
+            packet.write_interleaved(output_context)?;
+            self.written = true;
+        }
+        Ok(())
+    }
+}
+
+/// Writes 16-bit PCM samples to a WAV file, filling in the sizes in the header when done.
+struct WavWriter {
+    file: std::io::BufWriter<std::fs::File>,
+    channels: u16,
+    rate: u32,
+    /// The number of bytes of samples written so far.
+    data_size: u64,
+}
+
+impl WavWriter {
+    const HEADER_SIZE: u64 = 44;
+
+    fn create(path: &std::path::Path, channels: u16, rate: u32) -> Result<Self, anyhow::Error> {
+        let file = std::fs::File::create(path)
+            .with_context(|| format!("Failed to create {}", path.display()))?;
+        let mut writer = Self { file: std::io::BufWriter::new(file), channels, rate, data_size: 0 };
+        // With zero sizes, until they are known.
+        writer.write_header()?;
+        Ok(writer)
+    }
+
+    fn write_header(&mut self) -> std::io::Result<()> {
+        let block_align = self.channels * 2;
+        let data_size = self.data_size as u32;
+        let file = &mut self.file;
+        file.write_all(b"RIFF")?;
+        file.write_all(&(Self::HEADER_SIZE as u32 - 8 + data_size).to_le_bytes())?;
+        file.write_all(b"WAVE")?;
+        file.write_all(b"fmt ")?;
+        file.write_all(&16u32.to_le_bytes())?;
+        // PCM
+        file.write_all(&1u16.to_le_bytes())?;
+        file.write_all(&self.channels.to_le_bytes())?;
+        file.write_all(&self.rate.to_le_bytes())?;
+        file.write_all(&(self.rate * u32::from(block_align)).to_le_bytes())?;
+        file.write_all(&block_align.to_le_bytes())?;
+        file.write_all(&16u16.to_le_bytes())?;
+        file.write_all(b"data")?;
+        file.write_all(&data_size.to_le_bytes())
+    }
+
+    /// Appends the samples of `frame`, which has to be packed 16-bit audio with the channels
+    /// of the file.
+    fn write_frame(
+        &mut self,
+        frame: &ffmpeg_next::util::frame::Audio,
+    ) -> Result<(), anyhow::Error> {
+        let size = frame.samples() * usize::from(self.channels) * 2;
+        // The sizes in the header can't describe more.
+        if self.data_size + size as u64 + Self::HEADER_SIZE > u64::from(u32::MAX) {
+            anyhow::bail!("The audio is too long for a WAV file");
+        }
+        // The plane may be padded beyond the samples.
+        for sample in frame.data(0)[..size].chunks_exact(2) {
+            let sample = i16::from_ne_bytes([sample[0], sample[1]]);
+            self.file.write_all(&sample.to_le_bytes())?;
+        }
+        self.data_size += size as u64;
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<(), anyhow::Error> {
+        self.file.seek(SeekFrom::Start(0))?;
+        self.write_header()?;
+        self.file.flush()?;
+        Ok(())
+    }
+}
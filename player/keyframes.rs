@@ -0,0 +1,73 @@
+// Copyright © SixtyFPS GmbH <info@slint.dev>
+// SPDX-License-Identifier: MIT
+
+use std::sync::{Arc, Mutex, Weak};
+
+/// Filled in by [`spawn_indexer`] once the whole input was scanned.
+pub type SharedKeyframeIndex = Arc<Mutex<Option<KeyframeIndex>>>;
+
+/// The timestamps of the keyframes of a video stream, which playback can start from without
+/// decoding any earlier frames.
+pub struct KeyframeIndex {
+    pub stream_index: usize,
+    /// Seconds per unit of the timestamps.
+    time_base: f64,
+    /// Sorted presentation timestamps, in the stream's time base.
+    timestamps: Vec<i64>,
+}
+
+impl KeyframeIndex {
+    /// Reads all packets of `path` to collect the keyframes of the stream with index
+    /// `stream_index`. Gives up once `index` is dropped, as nobody is waiting for the result
+    /// anymore.
+    fn build(
+        path: &std::path::Path,
+        stream_index: usize,
+        index: &Weak<Mutex<Option<KeyframeIndex>>>,
+    ) -> Option<Self> {
+        let mut input_context = ffmpeg_next::format::input(&path).ok()?;
+        let time_base = f64::from(input_context.stream(stream_index)?.time_base());
+
+        let mut timestamps = Vec::new();
+        for (stream, packet) in input_context.packets() {
+            if index.strong_count() == 0 {
+                return None;
+            }
+            if stream.index() == stream_index && packet.is_key() {
+                timestamps.extend(packet.pts().or(packet.dts()));
+            }
+        }
+        timestamps.sort_unstable();
+        timestamps.dedup();
+
+        Some(Self { stream_index, time_base, timestamps })
+    }
+
+    /// The last keyframe at or before `position` (in seconds), as its timestamp and its position.
+    pub fn keyframe_before(&self, position: f64) -> Option<(i64, f64)> {
+        let count = self.timestamps.partition_point(|&pts| pts as f64 * self.time_base <= position);
+        let pts = *self.timestamps[..count].last()?;
+        Some((pts, pts as f64 * self.time_base))
+    }
+
+    /// The positions of all keyframes, in seconds.
+    pub fn positions(&self) -> Vec<f64> {
+        self.timestamps.iter().map(|&pts| pts as f64 * self.time_base).collect()
+    }
+}
+
+/// Builds the keyframe index of the stream with index `stream_index` of `path` on a background
+/// thread, so that the first frame isn't delayed by reading the whole input.
+pub fn spawn_indexer(path: std::path::PathBuf, stream_index: usize, index: &SharedKeyframeIndex) {
+    let index = Arc::downgrade(index);
+    std::thread::Builder::new()
+        .name("keyframe index thread".into())
+        .spawn(move || {
+            let keyframe_index = KeyframeIndex::build(&path, stream_index, &index);
+            if let (Some(keyframe_index), Some(index)) = (keyframe_index, index.upgrade()) {
+                *index.lock().unwrap() = Some(keyframe_index);
+            }
+        })
+        // Without the thread, seeking works as it does without an index.
+        .ok();
+}
@@ -0,0 +1,133 @@
+// Copyright © SixtyFPS GmbH <info@slint.dev>
+// SPDX-License-Identifier: MIT
+
+use super::audio::frame_definition;
+
+/// The loudness ReplayGain 2 tags bring tracks to, in LUFS.
+const REPLAYGAIN_REFERENCE_LUFS: f32 = -18.;
+
+/// Integrated loudness at or below this (in LUFS) is what EBU R128 reports for silence, which
+/// has no loudness to match.
+const SILENCE_LUFS: f32 = -70.;
+
+/// Finds out the integrated loudness (in LUFS) of the audio stream with index `stream_index` of
+/// `input_context`, which was opened from `path`. ReplayGain tags are taken as they are.
+/// Without them, local files are measured on a background thread, which gives up once the
+/// returned receiver is dropped. The receiver gets nothing if the loudness can't be found out,
+/// like for network streams, which would have to be downloaded twice.
+pub fn find_out(
+    path: &std::path::Path,
+    input_context: &ffmpeg_next::format::context::Input,
+    stream_index: usize,
+    is_url: bool,
+) -> smol::channel::Receiver<f32> {
+    let (sender, receiver) = smol::channel::bounded(1);
+    if let Some(loudness) = tagged_loudness(input_context, stream_index) {
+        sender.try_send(loudness).ok();
+    } else if !is_url {
+        let path = path.to_owned();
+        std::thread::Builder::new()
+            .name("loudness thread".into())
+            .spawn(move || {
+                if let Some(loudness) = measure(&path, stream_index, &sender) {
+                    sender.try_send(loudness).ok();
+                }
+            })
+            // Without the thread, the audio plays at its own loudness.
+            .ok();
+    }
+    receiver
+}
+
+/// The loudness the ReplayGain track gain of the stream or of the container corrects.
+fn tagged_loudness(
+    input_context: &ffmpeg_next::format::context::Input,
+    stream_index: usize,
+) -> Option<f32> {
+    // Tags are looked up ignoring case, as containers spell them differently.
+    let stream_tag = input_context
+        .stream(stream_index)
+        .and_then(|stream| stream.metadata().get("REPLAYGAIN_TRACK_GAIN").map(str::to_owned));
+    let tag = stream_tag
+        .or_else(|| input_context.metadata().get("REPLAYGAIN_TRACK_GAIN").map(str::to_owned))?;
+    // Written like "-6.48 dB".
+    let gain = tag.trim().trim_end_matches("dB").trim().parse::<f32>().ok()?;
+    gain.is_finite().then(|| REPLAYGAIN_REFERENCE_LUFS - gain)
+}
+
+/// Decodes all of the stream with index `stream_index` of `path` through the `ebur128` filter,
+/// which attaches the integrated loudness of the audio so far to the frames it passes on.
+fn measure(
+    path: &std::path::Path,
+    stream_index: usize,
+    sender: &smol::channel::Sender<f32>,
+) -> Option<f32> {
+    let mut input_context = ffmpeg_next::format::input(&path).ok()?;
+    let stream = input_context.stream(stream_index)?;
+    let decoder_context = ffmpeg_next::codec::Context::from_parameters(stream.parameters()).ok()?;
+    let mut decoder = decoder_context.decoder().audio().ok()?;
+
+    let mut graph: Option<ffmpeg_next::filter::Graph> = None;
+    let mut loudness = None;
+    let mut frame = ffmpeg_next::util::frame::Audio::empty();
+    let mut measured_frame = ffmpeg_next::util::frame::Audio::empty();
+    let mut packets = input_context.packets();
+    loop {
+        let end = match packets.next() {
+            Some((stream, packet)) => {
+                if sender.is_closed() {
+                    return None;
+                }
+                // Damaged packets are skipped, like playback does.
+                if stream.index() != stream_index || decoder.send_packet(&packet).is_err() {
+                    continue;
+                }
+                false
+            }
+            None => {
+                decoder.send_eof().ok();
+                true
+            }
+        };
+
+        while decoder.receive_frame(&mut frame).is_ok() {
+            if graph.is_none() {
+                graph = Some(create_meter(&frame_definition(&frame)).ok()?);
+            }
+            graph.as_mut()?.get("in")?.source().add(&frame).ok()?;
+        }
+        if let Some(graph) = graph.as_mut() {
+            if end {
+                graph.get("in")?.source().flush().ok();
+            }
+            while graph.get("out")?.sink().frame(&mut measured_frame).is_ok() {
+                loudness = measured_frame
+                    .metadata()
+                    .get("lavfi.r128.I")
+                    .and_then(|value| value.parse::<f32>().ok())
+                    .or(loudness);
+            }
+        }
+        if end {
+            return loudness.filter(|loudness| *loudness > SILENCE_LUFS);
+        }
+    }
+}
+
+/// Creates a filter graph that measures the loudness of audio described by `input`.
+fn create_meter(
+    input: &ffmpeg_next::software::resampling::context::Definition,
+) -> Result<ffmpeg_next::filter::Graph, ffmpeg_next::Error> {
+    let mut graph = ffmpeg_next::filter::Graph::new();
+    let args = format!(
+        "time_base=1/{rate}:sample_rate={rate}:sample_fmt={}:channel_layout=0x{:x}",
+        input.format.name(),
+        input.channel_layout.bits(),
+        rate = input.rate,
+    );
+    graph.add(&ffmpeg_next::filter::find("abuffer").unwrap(), "in", &args)?;
+    graph.add(&ffmpeg_next::filter::find("abuffersink").unwrap(), "out", "")?;
+    graph.output("in", 0)?.input("out", 0)?.parse("ebur128=metadata=1")?;
+    graph.validate()?;
+    Ok(graph)
+}
@@ -0,0 +1,131 @@
+// Copyright © SixtyFPS GmbH <info@slint.dev>
+// SPDX-License-Identifier: MIT
+
+use anyhow::Context;
+
+/// A short description of an input, as returned by [`can_play`].
+#[derive(Clone, Debug)]
+pub struct MediaSummary {
+    /// The duration in seconds, if the container knows it.
+    pub duration: Option<f64>,
+    /// The overall bit rate in bits per second, if known.
+    pub bit_rate: Option<i64>,
+    pub streams: Vec<StreamSummary>,
+    /// Sorted by start, and empty for inputs without chapters.
+    pub chapters: Vec<Chapter>,
+}
+
+/// A chapter of an input, like a scene of a movie or a section of a lecture.
+#[derive(Clone, Debug)]
+pub struct Chapter {
+    /// In seconds.
+    pub start: f64,
+    /// In seconds.
+    pub end: f64,
+    pub title: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct StreamSummary {
+    pub index: usize,
+    pub medium: ffmpeg_next::media::Type,
+    pub codec: &'static str,
+    /// Whether this build of FFmpeg has a decoder for the stream's codec.
+    pub decodable: bool,
+    /// The language tag from the stream's metadata, such as "eng".
+    pub language: Option<String>,
+    /// The number of channels of an audio stream.
+    pub channels: Option<u16>,
+    /// The sample rate of an audio stream, in Hz.
+    pub sample_rate: Option<u32>,
+    /// The width and height of a video stream, in pixels.
+    pub resolution: Option<(u32, u32)>,
+    pub pixel_format: Option<ffmpeg_next::format::Pixel>,
+    /// The average frame rate of a video stream, in frames per second.
+    pub frame_rate: Option<f64>,
+}
+
+/// Checks whether `source` (a file path or URL) can be played, without starting any playback
+/// threads. Only the beginning of the input is probed to keep this quick, but opening it may
+/// still block on I/O, so call this off the UI thread. URLs are requested with `network`.
+pub fn can_play(
+    source: &std::path::Path,
+    network: &super::NetworkOptions,
+) -> Result<MediaSummary, anyhow::Error> {
+    let mut probe_options = network.to_dictionary();
+    probe_options.set("probesize", "1000000");
+    probe_options.set("analyzeduration", "1000000");
+
+    let input_context = ffmpeg_next::format::input_with_dictionary(&source, probe_options)
+        .with_context(|| format!("Failed to open {}", source.display()))?;
+
+    let streams = input_context
+        .streams()
+        .map(|stream| {
+            let parameters = stream.parameters();
+            let audio = parameters.medium() == ffmpeg_next::media::Type::Audio;
+            let channels =
+                audio.then(|| unsafe { (*parameters.as_ptr()).ch_layout.nb_channels } as u16);
+            let sample_rate = audio.then(|| unsafe { (*parameters.as_ptr()).sample_rate } as u32);
+            // The decoder context reads the video properties from the parameters, without
+            // opening the decoder.
+            let video = (parameters.medium() == ffmpeg_next::media::Type::Video)
+                .then(|| {
+                    ffmpeg_next::codec::Context::from_parameters(parameters.clone())
+                        .ok()?
+                        .decoder()
+                        .video()
+                        .ok()
+                })
+                .flatten();
+            let frame_rate = video
+                .is_some()
+                .then(|| stream.avg_frame_rate())
+                .filter(|rate| rate.numerator() > 0 && rate.denominator() > 0)
+                .map(f64::from);
+            StreamSummary {
+                index: stream.index(),
+                medium: parameters.medium(),
+                codec: parameters.id().name(),
+                decodable: ffmpeg_next::decoder::find(parameters.id()).is_some(),
+                language: stream.metadata().get("language").map(str::to_owned),
+                channels,
+                sample_rate,
+                resolution: video.as_ref().map(|video| (video.width(), video.height())),
+                pixel_format: video.as_ref().map(|video| video.format()),
+                frame_rate,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let has_playable_stream = streams.iter().any(|stream| {
+        stream.decodable
+            && matches!(
+                stream.medium,
+                ffmpeg_next::media::Type::Video | ffmpeg_next::media::Type::Audio
+            )
+    });
+    if !has_playable_stream {
+        anyhow::bail!("{} has no decodable video or audio stream", source.display());
+    }
+
+    let duration = (input_context.duration() > 0)
+        .then(|| input_context.duration() as f64 / f64::from(ffmpeg_next::ffi::AV_TIME_BASE));
+
+    let bit_rate = (input_context.bit_rate() > 0).then(|| input_context.bit_rate());
+
+    let mut chapters = input_context
+        .chapters()
+        .map(|chapter| {
+            let time_base = f64::from(chapter.time_base());
+            Chapter {
+                start: chapter.start() as f64 * time_base,
+                end: chapter.end() as f64 * time_base,
+                title: chapter.metadata().get("title").map(str::to_owned),
+            }
+        })
+        .collect::<Vec<_>>();
+    chapters.sort_by(|a, b| a.start.total_cmp(&b.start));
+
+    Ok(MediaSummary { duration, bit_rate, streams, chapters })
+}
@@ -0,0 +1,109 @@
+// Copyright © SixtyFPS GmbH <info@slint.dev>
+// SPDX-License-Identifier: MIT
+
+use anyhow::Context;
+
+/// Writes the packets read from the input to a file as they are, without decoding them, so that
+/// a live stream can be recorded while it is watched. The file is finalized when this is
+/// dropped.
+pub struct Recorder {
+    output_context: ffmpeg_next::format::context::Output,
+    /// The index of the output stream and the time base of each recorded input stream, by the
+    /// index of the input stream.
+    stream_mapping: Vec<Option<(usize, ffmpeg_next::Rational)>>,
+    /// Recording starting mid-stream waits for a keyframe of this stream, as the video couldn't
+    /// be decoded before it.
+    video_stream_index: Option<usize>,
+    /// The position of the first recorded packet, in seconds, which the recording starts at.
+    start: Option<f64>,
+    /// The last decoding timestamp written to each output stream. Packets that aren't after
+    /// it, like after a seek, are left out, as the file has to go forward.
+    last_dts: Vec<Option<i64>>,
+}
+
+impl Recorder {
+    /// Creates `path` with a copy of the video and audio streams of `input_context`, in the
+    /// container its extension names.
+    pub fn new(
+        input_context: &ffmpeg_next::format::context::Input,
+        path: &std::path::Path,
+    ) -> Result<Self, anyhow::Error> {
+        let mut output_context = ffmpeg_next::format::output(&path)
+            .with_context(|| format!("Failed to create {}", path.display()))?;
+
+        let mut stream_mapping = vec![None; input_context.nb_streams() as usize];
+        for input_stream in input_context.streams() {
+            if !matches!(
+                input_stream.parameters().medium(),
+                ffmpeg_next::media::Type::Video | ffmpeg_next::media::Type::Audio
+            ) {
+                continue;
+            }
+            let mut output_stream = output_context
+                .add_stream(ffmpeg_next::encoder::find(ffmpeg_next::codec::Id::None))?;
+            output_stream.set_parameters(input_stream.parameters());
+            // The tag of the input container may mean something else in the output container.
+            unsafe {
+                (*output_stream.parameters().as_mut_ptr()).codec_tag = 0;
+            }
+            stream_mapping[input_stream.index()] =
+                Some((output_stream.index(), input_stream.time_base()));
+        }
+        if stream_mapping.iter().all(Option::is_none) {
+            anyhow::bail!("The input has no video or audio to record");
+        }
+        output_context.set_metadata(input_context.metadata().to_owned());
+        output_context
+            .write_header()
+            .context("The container can't hold the streams of the input")?;
+
+        let video_stream_index = input_context
+            .streams()
+            .best(ffmpeg_next::media::Type::Video)
+            .map(|stream| stream.index());
+        let last_dts = vec![None; output_context.nb_streams() as usize];
+        Ok(Self { output_context, stream_mapping, video_stream_index, start: None, last_dts })
+    }
+
+    /// Appends a copy of `packet`, if it belongs to a recorded stream.
+    pub fn write(&mut self, packet: &ffmpeg_next::Packet) -> Result<(), anyhow::Error> {
+        let Some(&Some((output_index, time_base))) = self.stream_mapping.get(packet.stream())
+        else {
+            return Ok(());
+        };
+        let Some(dts) = packet.dts().or(packet.pts()) else { return Ok(()) };
+        let position = dts as f64 * f64::from(time_base);
+        let start = match self.start {
+            Some(start) => start,
+            None if self.video_stream_index.is_none()
+                || (Some(packet.stream()) == self.video_stream_index && packet.is_key()) =>
+            {
+                *self.start.insert(position)
+            }
+            None => return Ok(()),
+        };
+        // Audio from before the first keyframe would have negative timestamps.
+        if position < start || self.last_dts[output_index].is_some_and(|last| dts <= last) {
+            return Ok(());
+        }
+        self.last_dts[output_index] = Some(dts);
+
+        let mut packet = packet.clone();
+        let offset = (start / f64::from(time_base)).round() as i64;
+        packet.set_pts(packet.pts().map(|pts| pts - offset));
+        packet.set_dts(packet.dts().map(|dts| dts - offset));
+        let output_time_base = self.output_context.stream(output_index).unwrap().time_base();
+        packet.rescale_ts(time_base, output_time_base);
+        packet.set_stream(output_index);
+        packet.set_position(-1);
+        packet.write_interleaved(&mut self.output_context)?;
+        Ok(())
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        // Without the trailer, containers like MP4 can't be played.
+        self.output_context.write_trailer().ok();
+    }
+}
@@ -0,0 +1,197 @@
+// Copyright © SixtyFPS GmbH <info@slint.dev>
+// SPDX-License-Identifier: MIT
+
+use std::sync::{Arc, Mutex, Weak};
+
+use anyhow::Context;
+
+/// Filled in by [`spawn_generator`] one thumbnail at a time.
+pub type SharedThumbnails = Arc<Mutex<Thumbnails>>;
+
+/// How many thumbnails are made of an input, spread evenly over its duration.
+const THUMBNAIL_COUNT: usize = 100;
+
+/// The height of the thumbnails, in pixels. The width follows from the aspect ratio.
+const THUMBNAIL_HEIGHT: u32 = 90;
+
+/// Small RGB images of the video at evenly spaced positions, to preview where a seek lands.
+#[derive(Default)]
+pub struct Thumbnails {
+    /// By position in seconds, in ascending order.
+    thumbnails: Vec<(f64, ffmpeg_next::util::frame::Video)>,
+}
+
+impl Thumbnails {
+    /// The thumbnail closest to `position` (in seconds) of those generated so far.
+    pub fn nearest(&self, position: f64) -> Option<&ffmpeg_next::util::frame::Video> {
+        let count = self.thumbnails.partition_point(|(time, _)| *time <= position);
+        let before = count.checked_sub(1).map(|index| &self.thumbnails[index]);
+        let after = self.thumbnails.get(count);
+        match (before, after) {
+            (Some(before), Some(after)) if after.0 - position < position - before.0 => Some(after),
+            (Some(before), _) => Some(before),
+            (None, after) => after,
+        }
+        .map(|(_, frame)| frame)
+    }
+}
+
+/// Generates the thumbnails of the stream with index `stream_index` of `path`, which is
+/// `duration` seconds long, on a background thread. Gives up once `thumbnails` is dropped, as
+/// nobody is waiting for them anymore.
+pub fn spawn_generator(
+    path: std::path::PathBuf,
+    stream_index: usize,
+    duration: f64,
+    thumbnails: &SharedThumbnails,
+) {
+    let thumbnails = Arc::downgrade(thumbnails);
+    std::thread::Builder::new()
+        .name("thumbnail thread".into())
+        .spawn(move || {
+            generate(&path, stream_index, duration, &thumbnails);
+        })
+        // Without the thread, there are no seek previews.
+        .ok();
+}
+
+fn generate(
+    path: &std::path::Path,
+    stream_index: usize,
+    duration: f64,
+    thumbnails: &Weak<Mutex<Thumbnails>>,
+) -> Option<()> {
+    let mut extractor = FrameExtractor::open(path, Some(stream_index)).ok()?;
+
+    for number in 0..THUMBNAIL_COUNT {
+        let position = (number as f64 + 0.5) * duration / THUMBNAIL_COUNT as f64;
+        // The first frame after the seek, from the keyframe before the position, is close
+        // enough for a preview and quick to decode.
+        let Ok((position, frame)) = extractor.frame_at(position, false) else { continue };
+        let Ok(thumbnail) = extractor.to_rgb(&frame, Some(THUMBNAIL_HEIGHT)) else { continue };
+
+        let thumbnails = thumbnails.upgrade()?;
+        let mut thumbnails = thumbnails.lock().unwrap();
+        let index = thumbnails.thumbnails.partition_point(|(time, _)| *time <= position);
+        thumbnails.thumbnails.insert(index, (position, thumbnail));
+    }
+    Some(())
+}
+
+/// Decodes single frames of a video stream at arbitrary positions, and converts them to RGB.
+pub struct FrameExtractor {
+    input_context: ffmpeg_next::format::context::Input,
+    stream_index: usize,
+    /// Seconds per unit of the timestamps.
+    time_base: f64,
+    decoder: ffmpeg_next::decoder::Video,
+    /// Reused while the frames and the requested size stay the same.
+    scaler: Option<ffmpeg_next::software::scaling::Context>,
+}
+
+impl FrameExtractor {
+    /// Opens the video stream with index `stream_index` of `path`, or its default video stream
+    /// with `None`.
+    pub fn open(
+        path: &std::path::Path,
+        stream_index: Option<usize>,
+    ) -> Result<Self, anyhow::Error> {
+        let input_context = ffmpeg_next::format::input(&path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        let stream = match stream_index {
+            Some(index) => input_context.stream(index),
+            None => input_context.streams().best(ffmpeg_next::media::Type::Video),
+        }
+        .context("The input has no video stream")?;
+        let stream_index = stream.index();
+        let time_base = f64::from(stream.time_base());
+        let decoder_context = ffmpeg_next::codec::Context::from_parameters(stream.parameters())?;
+        let decoder = decoder_context.decoder().video()?;
+        Ok(Self { input_context, stream_index, time_base, decoder, scaler: None })
+    }
+
+    /// The duration of the input in seconds, if the container knows it.
+    pub fn duration(&self) -> Option<f64> {
+        (self.input_context.duration() > 0).then(|| {
+            self.input_context.duration() as f64 / f64::from(ffmpeg_next::ffi::AV_TIME_BASE)
+        })
+    }
+
+    /// Decodes the frame shown at `position` (in seconds) when `accurate`, or else the keyframe
+    /// at or before it, which is quicker. Returns the frame's own position along with it.
+    pub fn frame_at(
+        &mut self,
+        position: f64,
+        accurate: bool,
+    ) -> Result<(f64, ffmpeg_next::util::frame::Video), anyhow::Error> {
+        let timestamp = (position * f64::from(ffmpeg_next::ffi::AV_TIME_BASE)) as i64;
+        self.input_context.seek(timestamp, ..timestamp)?;
+        self.decoder.flush();
+
+        let mut frame = ffmpeg_next::util::frame::Video::empty();
+        let mut previous: Option<(f64, ffmpeg_next::util::frame::Video)> = None;
+        let mut packets = self.input_context.packets();
+        loop {
+            let received = match packets.next() {
+                Some((stream, packet)) => {
+                    // Skip damaged packets, like playback does.
+                    if stream.index() != self.stream_index
+                        || self.decoder.send_packet(&packet).is_err()
+                    {
+                        continue;
+                    }
+                    self.decoder.receive_frame(&mut frame).is_ok()
+                }
+                // The last frames are still in the decoder at the end of the input.
+                None => {
+                    self.decoder.send_eof().ok();
+                    if self.decoder.receive_frame(&mut frame).is_err() {
+                        // Past the last frame, which is the closest then.
+                        return previous.context("No frame could be decoded there");
+                    }
+                    true
+                }
+            };
+            if !received {
+                continue;
+            }
+            let frame_position =
+                frame.timestamp().map_or(position, |pts| pts as f64 * self.time_base);
+            if !accurate || frame_position >= position {
+                return Ok((frame_position, frame));
+            }
+            previous = Some((frame_position, frame.clone()));
+        }
+    }
+
+    /// Converts `frame` to RGB, scaled to `height` pixels high keeping its aspect ratio, or at
+    /// its own size with `None`.
+    pub fn to_rgb(
+        &mut self,
+        frame: &ffmpeg_next::util::frame::Video,
+        height: Option<u32>,
+    ) -> Result<ffmpeg_next::util::frame::Video, anyhow::Error> {
+        let height = height.unwrap_or(frame.height()).max(1);
+        let width = (frame.width() * height / frame.height().max(1)).max(1);
+        // Inputs may change their size midway.
+        let changed = self.scaler.as_ref().map_or(true, |scaler| {
+            let (input, output) = (scaler.input(), scaler.output());
+            (input.format, input.width, input.height, output.width, output.height)
+                != (frame.format(), frame.width(), frame.height(), width, height)
+        });
+        if changed {
+            self.scaler = Some(ffmpeg_next::software::scaling::Context::get(
+                frame.format(),
+                frame.width(),
+                frame.height(),
+                ffmpeg_next::format::Pixel::RGB24,
+                width,
+                height,
+                ffmpeg_next::software::scaling::Flags::BILINEAR,
+            )?);
+        }
+        let mut rgb_frame = ffmpeg_next::util::frame::Video::empty();
+        self.scaler.as_mut().unwrap().run(frame, &mut rgb_frame)?;
+        Ok(rgb_frame)
+    }
+}
@@ -1,62 +1,496 @@
 // Copyright © SixtyFPS GmbH <info@slint.dev>
 // SPDX-License-Identifier: MIT
 
+use std::cell::{Cell, RefCell};
+use std::sync::{Arc, Mutex};
+
+use ffmpeg_next::Rescale;
 use futures::{future::OptionFuture, FutureExt};
 
-use super::ControlCommand;
+use super::{
+    ControlCommand, DecoderInput, ErrorCallback, PacketMessage, PlaybackClock, PACKET_QUEUE_SIZE,
+};
+
+/// How decoded frames are paced relative to their presentation timestamps.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SmoothingMode {
+    /// Present every frame exactly at its timestamp.
+    #[default]
+    Off,
+    /// Snap slightly jittery timestamps onto the stream's nominal frame interval, while still
+    /// following long-term drift.
+    FrameGrid,
+}
+
+/// Which clock video frames are presented against.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SyncMode {
+    /// Follow the position of the audio handed to the output device, so that video stays in
+    /// sync with what is heard even when the audio device's clock runs slightly fast or slow, or
+    /// the machine falls behind. Without audio, the wall clock is used.
+    #[default]
+    AudioMaster,
+    /// Follow the wall clock, independently of the audio.
+    Wallclock,
+}
+
+/// When interlaced video is deinterlaced, with FFmpeg's `bwdif` filter.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DeinterlaceMode {
+    /// Deinterlace the frames that are flagged as interlaced, once the stream turns out to
+    /// contain any, and leave progressive video alone.
+    #[default]
+    Auto,
+    /// Deinterlace every frame, for interlaced streams that aren't flagged as such.
+    On,
+    Off,
+}
+
+/// Brightness, contrast and saturation changes applied to decoded frames with FFmpeg's `eq`
+/// filter. The default leaves frames unchanged.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PictureAdjustment {
+    /// Added to the brightness, from -1 to 1.
+    pub brightness: f32,
+    /// Multiplies the contrast, from 0 to 2.
+    pub contrast: f32,
+    /// Multiplies the saturation, from 0 (grayscale) to 3.
+    pub saturation: f32,
+}
+
+impl PictureAdjustment {
+    pub fn is_neutral(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl Default for PictureAdjustment {
+    fn default() -> Self {
+        Self { brightness: 0., contrast: 1., saturation: 1. }
+    }
+}
+
+/// The rendering quality the frame callback is asked to produce. Lower levels trade image
+/// quality for a cheaper conversion when frames can't be processed within their frame interval.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum VideoQuality {
+    /// Scale with the cheapest filter at half the resolution.
+    HalfResolution,
+    /// Scale with a faster, lower quality filter.
+    Fast,
+    #[default]
+    Full,
+}
+
+impl VideoQuality {
+    fn lower(self) -> Self {
+        match self {
+            Self::Full => Self::Fast,
+            Self::Fast | Self::HalfResolution => Self::HalfResolution,
+        }
+    }
+
+    fn higher(self) -> Self {
+        match self {
+            Self::HalfResolution => Self::Fast,
+            Self::Fast | Self::Full => Self::Full,
+        }
+    }
+}
+
+/// Thresholds for adapting the [`VideoQuality`] to the time it takes to decode and present a
+/// frame, expressed as a fraction of the stream's frame interval.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AdaptiveQuality {
+    /// Lower the quality when frames consistently take longer than this.
+    pub degrade_above: f64,
+    /// Raise the quality again when frames consistently take less than this.
+    pub restore_below: f64,
+}
+
+impl Default for AdaptiveQuality {
+    fn default() -> Self {
+        Self { degrade_above: 0.9, restore_below: 0.5 }
+    }
+}
 
 pub struct VideoPlaybackThread {
     control_sender: smol::channel::Sender<ControlCommand>,
-    packet_sender: smol::channel::Sender<ffmpeg_next::codec::packet::packet::Packet>,
+    packet_sender: smol::channel::Sender<PacketMessage>,
+    packet_receiver: smol::channel::Receiver<PacketMessage>,
+    preview_receiver: smol::channel::Receiver<()>,
+    ended_receiver: smol::channel::Receiver<()>,
     receiver_thread: Option<std::thread::JoinHandle<()>>,
 }
 
 impl VideoPlaybackThread {
     pub fn start(
         stream: &ffmpeg_next::format::stream::Stream,
-        mut video_frame_callback: Box<dyn FnMut(&ffmpeg_next::util::frame::Video) + Send>,
+        video_frame_callback: Box<dyn FnMut(&ffmpeg_next::util::frame::Video, VideoQuality) + Send>,
+        playback_clock: Arc<PlaybackClock>,
+        hardware_decoding: bool,
+        frame_queue_depth: usize,
+        video_filter: Arc<Mutex<Option<String>>>,
+        error_callback: Arc<ErrorCallback>,
     ) -> Result<Self, anyhow::Error> {
         let (control_sender, control_receiver) = smol::channel::unbounded();
 
-        let (packet_sender, packet_receiver) = smol::channel::bounded(128);
+        let (packet_sender, packet_receiver) = smol::channel::bounded(PACKET_QUEUE_SIZE);
+        let queued_packet_receiver = packet_receiver.clone();
+
+        let (preview_sender, preview_receiver) = smol::channel::unbounded();
+        let (ended_sender, ended_receiver) = smol::channel::unbounded();
 
-        let decoder_context = ffmpeg_next::codec::Context::from_parameters(stream.parameters())?;
-        let mut packet_decoder = decoder_context.decoder().video()?;
+        let mut packet_decoder = open_decoder(stream, hardware_decoding)?;
 
-        let clock = StreamClock::new(stream);
+        let mut clock = StreamClock::new(stream);
+        let time_base_seconds = clock.time_base_seconds;
+        let time_base = stream.time_base();
+        let frame_queue_depth = frame_queue_depth.max(1);
+        let mut frame_grid = FrameGrid::new(stream);
+        let smoothing_mode = Cell::new(SmoothingMode::default());
+        let sync_mode = Cell::new(SyncMode::default());
+        let mut quality_controller = QualityController::new(stream);
+        let adaptive_quality = Cell::new(None);
+        let refresh_interval = Cell::new(None);
+        let speed = Cell::new(1.0f64);
+        let picture_adjustment = Cell::new(PictureAdjustment::default());
+        let deinterlace_mode = Cell::new(DeinterlaceMode::default());
+        // Set when the video filter, the picture adjustment or the deinterlacing changed, so
+        // that the filter is rebuilt.
+        let filter_changed = Cell::new(true);
+        let scrubbing = Cell::new(false);
+        // When playback was paused, and for how long it was paused before being resumed, which
+        // delays the frames that follow.
+        let paused_since = Cell::new(None);
+        let paused_duration = Cell::new(std::time::Duration::ZERO);
+        let nominal_interval = frame_interval(stream).map(|interval| interval.round() as i64);
+        let nominal_duration = nominal_frame_rate(stream).map(|frame_rate| {
+            std::time::Duration::from_secs_f64(
+                frame_rate.denominator() as f64 / frame_rate.numerator() as f64,
+            )
+        });
 
         let receiver_thread =
             std::thread::Builder::new().name("video playback thread".into()).spawn(move || {
                 smol::block_on(async move {
-                    let packet_receiver_impl = async {
+                    // Used while decoding scrub previews, and while presenting frames.
+                    let video_frame_callback = RefCell::new(video_frame_callback);
+                    // Counts the flushes. Frames decoded before the latest one are dropped rather
+                    // than presented.
+                    let flush_generation = Cell::new(0u64);
+                    let (frame_sender, frame_receiver) = smol::channel::bounded(frame_queue_depth);
+
+                    // Decodes ahead of the presentation into the frame queue, so that a frame
+                    // that takes longer to decode, like a keyframe, doesn't delay the
+                    // presentation as long as frames are queued.
+                    let decoding = async {
+                        let frame_sender = frame_sender;
+                        // The position to preview after a flush while scrubbing.
+                        let mut preview_position = None;
+                        // The filter graph description to apply, `None` when the frames are
+                        // shown as decoded.
+                        let mut filter_spec = None;
+                        let mut filter: Option<VideoFilter> = None;
+                        // Whether an interlaced frame was decoded, which makes the automatic
+                        // mode deinterlace.
+                        let mut seen_interlaced = false;
+                        let mut decoder_input = DecoderInput::new("video", error_callback);
+
                         loop {
-                            let Ok(packet) = packet_receiver.recv().await else { break };
+                            let Ok(message) = packet_receiver.recv().await else { break };
+
+                            let packet = match message {
+                                PacketMessage::Packet(packet) => Some(packet),
+                                PacketMessage::Flush(position) => {
+                                    packet_decoder.flush();
+                                    // Drops the frames the filter holds back.
+                                    filter = None;
+                                    flush_generation.set(flush_generation.get() + 1);
+                                    preview_position = scrubbing.get().then_some(position);
+                                    if frame_sender
+                                        .send(QueuedFrame::Flush(position))
+                                        .await
+                                        .is_err()
+                                    {
+                                        break;
+                                    }
+                                    continue;
+                                }
+                                PacketMessage::EndOfStream => None,
+                            };
 
                             smol::future::yield_now().await;
 
-                            packet_decoder.send_packet(&packet).unwrap();
+                            let mut decode_start = std::time::Instant::now();
+
+                            match &packet {
+                                // Given up on, but the packets are still taken, so that the
+                                // demuxer goes on with the other streams.
+                                Some(_) if decoder_input.gave_up() => continue,
+                                Some(packet) => {
+                                    if !decoder_input.send(&mut packet_decoder, packet) {
+                                        continue;
+                                    }
+                                }
+                                // Fails if already draining, which is harmless.
+                                None => packet_decoder.send_eof().unwrap_or_default(),
+                            }
 
                             let mut decoded_frame = ffmpeg_next::util::frame::Video::empty();
 
-                            while packet_decoder.receive_frame(&mut decoded_frame).is_ok() {
-                                if let Some(delay) =
-                                    clock.convert_pts_to_instant(decoded_frame.pts())
-                                {
-                                    smol::Timer::after(delay).await;
+                            loop {
+                                while packet_decoder.receive_frame(&mut decoded_frame).is_ok() {
+                                    match download_hardware_frame(&decoded_frame) {
+                                        Ok(Some(software_frame)) => decoded_frame = software_frame,
+                                        Ok(None) => {}
+                                        // Can't be converted for display
+                                        Err(_) => continue,
+                                    }
+                                    playback_clock.add_decoded_video_frame();
+
+                                    if !seen_interlaced && decoded_frame.is_interlaced() {
+                                        seen_interlaced = true;
+                                        if deinterlace_mode.get() == DeinterlaceMode::Auto {
+                                            filter_changed.set(true);
+                                        }
+                                    }
+                                    if filter_changed.take() {
+                                        let deinterlace = match deinterlace_mode.get() {
+                                            DeinterlaceMode::Auto if seen_interlaced => {
+                                                Some("bwdif=mode=send_frame:deint=interlaced")
+                                            }
+                                            DeinterlaceMode::On => {
+                                                Some("bwdif=mode=send_frame:deint=all")
+                                            }
+                                            DeinterlaceMode::Auto | DeinterlaceMode::Off => None,
+                                        };
+                                        filter_spec = video_filter_spec(
+                                            deinterlace,
+                                            video_filter.lock().unwrap().as_deref(),
+                                            picture_adjustment.get(),
+                                        );
+                                    }
+                                    if let Some(spec) = &filter_spec {
+                                        let outdated = !filter.as_ref().is_some_and(|filter| {
+                                            filter.accepts(&decoded_frame, spec)
+                                        });
+                                        if outdated {
+                                            filter =
+                                                VideoFilter::new(&decoded_frame, spec, time_base)
+                                                    .ok();
+                                            // A filter that can't take these frames is skipped
+                                            // until it changes, rather than rebuilt for every one.
+                                            if filter.is_none() {
+                                                filter_spec = None;
+                                            }
+                                        }
+                                    } else {
+                                        filter = None;
+                                    }
+                                    let frames = match filter
+                                        .as_mut()
+                                        .map(|filter| filter.apply(&decoded_frame))
+                                    {
+                                        Some(Ok(filtered_frames)) => filtered_frames,
+                                        // Frames the filter fails on are shown unfiltered.
+                                        Some(Err(_)) | None => vec![std::mem::replace(
+                                            &mut decoded_frame,
+                                            ffmpeg_next::util::frame::Video::empty(),
+                                        )],
+                                    };
+
+                                    for frame in frames {
+                                        let decode_time = decode_start.elapsed();
+
+                                        if scrubbing.get() {
+                                            // Present the first frame at the scrub position right
+                                            // away and skip the rest.
+                                            let reached_preview =
+                                                preview_position.is_some_and(|position| {
+                                                    frame.pts().map_or(true, |pts| {
+                                                        pts as f64 * time_base_seconds >= position
+                                                    })
+                                                });
+                                            if reached_preview {
+                                                preview_position = None;
+                                                (video_frame_callback.borrow_mut())(
+                                                    &frame,
+                                                    VideoQuality::Full,
+                                                );
+                                                if let Some(pts) = frame.pts() {
+                                                    playback_clock.set_video_time(
+                                                        pts as f64 * time_base_seconds,
+                                                    );
+                                                }
+                                                preview_sender.try_send(()).ok();
+                                            }
+                                            decode_start = std::time::Instant::now();
+                                            continue;
+                                        }
+
+                                        let queued_frame = QueuedFrame::Frame {
+                                            frame,
+                                            decode_time,
+                                            generation: flush_generation.get(),
+                                        };
+                                        if frame_sender.send(queued_frame).await.is_err() {
+                                            return;
+                                        }
+
+                                        decode_start = std::time::Instant::now();
+                                    }
                                 }
+                                // The decoder has room for the packet it had none for, now
+                                // that its frames were taken.
+                                if !decoder_input.send_pending(&mut packet_decoder) {
+                                    break;
+                                }
+                            }
 
-                                video_frame_callback(&decoded_frame);
+                            if packet.is_none() {
+                                let end = QueuedFrame::EndOfStream(flush_generation.get());
+                                if frame_sender.send(end).await.is_err() {
+                                    return;
+                                }
                             }
                         }
-                    }
-                    .fuse()
-                    .shared();
+                    };
+
+                    // Presents the queued frames when they are due.
+                    let presenting = async {
+                        // The timestamp of the previous frame, to pace frames without one.
+                        let mut last_pts: Option<i64> = None;
+                        // The position to continue at after a flush. Frames before it are
+                        // only decoded as the base of the frames that follow.
+                        let mut skip_until = None;
+
+                        while let Ok(queued_frame) = frame_receiver.recv().await {
+                            let (decoded_frame, decode_time, generation) = match queued_frame {
+                                QueuedFrame::Frame { frame, decode_time, generation } => {
+                                    (frame, decode_time, generation)
+                                }
+                                QueuedFrame::Flush(position) => {
+                                    clock.rebase(position);
+                                    // Only pauses after the rebase delay the frames.
+                                    paused_duration.set(std::time::Duration::ZERO);
+                                    if paused_since.get().is_some() {
+                                        paused_since.set(Some(std::time::Instant::now()));
+                                    }
+                                    frame_grid.reset();
+                                    last_pts = None;
+                                    skip_until = (!scrubbing.get()).then_some(position);
+                                    continue;
+                                }
+                                QueuedFrame::EndOfStream(generation) => {
+                                    if generation == flush_generation.get() {
+                                        ended_sender.try_send(()).ok();
+                                    }
+                                    continue;
+                                }
+                            };
+
+                            // Only previews are shown while scrubbing.
+                            if generation != flush_generation.get() || scrubbing.get() {
+                                continue;
+                            }
+
+                            // Variable frame rate streams are paced by their timestamps as
+                            // well. Frames without one are shown a nominal frame interval
+                            // after the previous frame.
+                            let frame_pts =
+                                decoded_frame.pts().or(decoded_frame.timestamp()).or_else(|| {
+                                    last_pts
+                                        .zip(nominal_interval)
+                                        .map(|(pts, interval)| pts + interval)
+                                });
+                            last_pts = frame_pts;
+
+                            if let Some(position) = skip_until {
+                                // Skip frames that are replaced before the position, keeping
+                                // the one shown at it.
+                                let before = frame_pts.is_some_and(|pts| {
+                                    let pts = clock.pts_to_seconds(pts);
+                                    match nominal_duration {
+                                        Some(interval) => pts + interval.as_secs_f64() <= position,
+                                        None => pts < position,
+                                    }
+                                });
+                                if before {
+                                    continue;
+                                }
+                                skip_until = None;
+                            }
+
+                            let pts = match smoothing_mode.get() {
+                                SmoothingMode::Off => frame_pts,
+                                SmoothingMode::FrameGrid => {
+                                    frame_pts.map(|pts| frame_grid.snap(pts))
+                                }
+                            };
+
+                            if clock.speed() != speed.get() {
+                                clock.set_speed(speed.get());
+                            }
+                            clock.delay(paused_duration.take());
+                            if let Some(audio_time) = playback_clock
+                                .audio_time()
+                                .filter(|_| sync_mode.get() == SyncMode::AudioMaster)
+                            {
+                                clock.follow(audio_time);
+                            }
+
+                            // Frames overdue by more than a frame interval are dropped
+                            // rather than presented late, so that the video catches up
+                            // instead of lagging further behind the audio. They were still
+                            // decoded, as the frames that follow depend on them.
+                            let due_time = clock.due_time(pts, refresh_interval.get());
+                            let late =
+                                due_time.zip(nominal_duration).is_some_and(|(due, interval)| {
+                                    due.elapsed() > interval.div_f64(clock.speed())
+                                });
+                            if late {
+                                playback_clock.add_dropped_video_frame();
+                                continue;
+                            }
+
+                            if let Some(due_time) = due_time {
+                                smol::Timer::at(due_time).await;
+                            }
+                            // Flushed while waiting
+                            if generation != flush_generation.get() {
+                                continue;
+                            }
+
+                            let present_start = std::time::Instant::now();
+                            (video_frame_callback.borrow_mut())(
+                                &decoded_frame,
+                                quality_controller.quality(),
+                            );
+                            playback_clock.add_presented_video_frame();
+                            if let Some(pts) = frame_pts {
+                                playback_clock.set_video_time(clock.pts_to_seconds(pts));
+                            }
+                            quality_controller.record(
+                                adaptive_quality.get(),
+                                decode_time + present_start.elapsed(),
+                            );
+                        }
+                    };
+
+                    let packet_receiver_impl =
+                        futures::future::join(decoding, presenting).fuse().shared();
 
                     let mut playing = true;
 
                     loop {
-                        let packet_receiver: OptionFuture<_> =
-                            if playing { Some(packet_receiver_impl.clone()) } else { None }.into();
+                        // Scrub previews are decoded even while paused.
+                        let packet_receiver: OptionFuture<_> = if playing || scrubbing.get() {
+                            Some(packet_receiver_impl.clone())
+                        } else {
+                            None
+                        }
+                        .into();
 
                         smol::pin!(packet_receiver);
 
@@ -66,9 +500,55 @@ impl VideoPlaybackThread {
                                 match received_command {
                                     Ok(ControlCommand::Pause) => {
                                         playing = false;
+                                        if paused_since.get().is_none() {
+                                            paused_since.set(Some(std::time::Instant::now()));
+                                        }
                                     }
                                     Ok(ControlCommand::Play) => {
                                         playing = true;
+                                        if let Some(since) = paused_since.take() {
+                                            paused_duration
+                                                .set(paused_duration.get() + since.elapsed());
+                                        }
+                                    }
+                                    Ok(ControlCommand::SetSmoothingMode(mode)) => {
+                                        smoothing_mode.set(mode);
+                                    }
+                                    Ok(ControlCommand::SetSyncMode(mode)) => {
+                                        sync_mode.set(mode);
+                                    }
+                                    Ok(ControlCommand::SetAdaptiveQuality(settings)) => {
+                                        adaptive_quality.set(settings);
+                                    }
+                                    Ok(ControlCommand::SetDisplayRefreshRate(rate)) => {
+                                        refresh_interval.set(
+                                            rate.filter(|rate| *rate > 0.).map(|rate| {
+                                                std::time::Duration::from_secs_f64(1. / rate)
+                                            }),
+                                        );
+                                    }
+                                    Ok(ControlCommand::SetSpeed(new_speed)) => {
+                                        speed.set(new_speed.into());
+                                    }
+                                    Ok(ControlCommand::SetPictureAdjustment(adjustment)) => {
+                                        picture_adjustment.set(adjustment);
+                                        filter_changed.set(true);
+                                    }
+                                    Ok(ControlCommand::SetDeinterlaceMode(mode)) => {
+                                        deinterlace_mode.set(mode);
+                                        filter_changed.set(true);
+                                    }
+                                    Ok(ControlCommand::ChangeVideoFilter) => {
+                                        filter_changed.set(true);
+                                    }
+                                    Ok(ControlCommand::BeginScrub) => {
+                                        scrubbing.set(true);
+                                    }
+                                    Ok(ControlCommand::EndScrub) => {
+                                        scrubbing.set(false);
+                                    }
+                                    Ok(_) => {
+                                        // Handled by the demuxer thread
                                     }
                                     Err(_) => {
                                         // Channel closed -> quit
@@ -81,16 +561,63 @@ impl VideoPlaybackThread {
                 })
             })?;
 
-        Ok(Self { control_sender, packet_sender, receiver_thread: Some(receiver_thread) })
+        Ok(Self {
+            control_sender,
+            packet_sender,
+            packet_receiver: queued_packet_receiver,
+            preview_receiver,
+            ended_receiver,
+            receiver_thread: Some(receiver_thread),
+        })
     }
 
     pub async fn receive_packet(&self, packet: ffmpeg_next::codec::packet::packet::Packet) -> bool {
-        match self.packet_sender.send(packet).await {
+        match self.packet_sender.send(PacketMessage::Packet(packet)).await {
             Ok(_) => return true,
             Err(smol::channel::SendError(_)) => return false,
         }
     }
 
+    /// Asks the playback thread to present the frames the decoder still holds once the queued
+    /// packets are decoded, as the input ended.
+    pub async fn end_of_stream(&self) -> bool {
+        match self.packet_sender.send(PacketMessage::EndOfStream).await {
+            Ok(_) => return true,
+            Err(smol::channel::SendError(_)) => return false,
+        }
+    }
+
+    /// The number of packets and other messages waiting to be decoded.
+    pub fn queued_packets(&self) -> usize {
+        self.packet_sender.len()
+    }
+
+    /// Discards the packets that are still queued for decoding and asks the playback thread to
+    /// continue from `position` (in seconds).
+    pub async fn flush(&self, position: f64) -> bool {
+        while self.packet_receiver.try_recv().is_ok() {}
+        // The end reported before the jump is not the end of what plays after it.
+        while self.ended_receiver.try_recv().is_ok() {}
+        match self.packet_sender.send(PacketMessage::Flush(position)).await {
+            Ok(_) => return true,
+            Err(smol::channel::SendError(_)) => return false,
+        }
+    }
+
+    /// Resolves once a frame was presented for the position requested while scrubbing.
+    pub async fn preview_presented(&self) {
+        if self.preview_receiver.recv().await.is_err() {
+            // The playback thread is gone, so no preview will ever arrive.
+            futures::future::pending::<()>().await;
+        }
+    }
+
+    /// Resolves once the last frame was presented after [`Self::end_of_stream`].
+    pub async fn ended(&self) {
+        // Without the playback thread, nothing is left to present.
+        self.ended_receiver.recv().await.ok();
+    }
+
     pub async fn send_control_message(&self, message: ControlCommand) {
         self.control_sender.send(message).await.unwrap();
     }
@@ -105,12 +632,35 @@ impl Drop for VideoPlaybackThread {
     }
 }
 
+/// Passed from decoding to presentation, in stream order.
+enum QueuedFrame {
+    Frame {
+        frame: ffmpeg_next::util::frame::Video,
+        decode_time: std::time::Duration,
+        /// The number of flushes before the frame was decoded.
+        generation: u64,
+    },
+    /// The decoder was flushed to continue at a new position (in seconds), see
+    /// [`PacketMessage::Flush`].
+    Flush(f64),
+    /// The decoder output its last frame, which is the last one to present. Carries the
+    /// number of flushes before it.
+    EndOfStream(u64),
+}
+
 struct StreamClock {
     time_base_seconds: f64,
+    /// The instant at which the stream's timestamp 0 is (or would have been) due.
     start_time: std::time::Instant,
+    /// Seconds of the stream that are played per second of wall-clock time.
+    speed: f64,
 }
 
 impl StreamClock {
+    /// See [`Self::follow`], in seconds.
+    const SYNC_THRESHOLD: f64 = 0.02;
+    const MAX_SYNC_CORRECTION: f64 = 2.;
+
     fn new(stream: &ffmpeg_next::format::stream::Stream) -> Self {
         let time_base_seconds = stream.time_base();
         let time_base_seconds =
@@ -118,15 +668,496 @@ impl StreamClock {
 
         let start_time = std::time::Instant::now();
 
-        Self { time_base_seconds, start_time }
+        Self { time_base_seconds, start_time, speed: 1. }
+    }
+
+    fn rebase(&mut self, position: f64) {
+        let now = std::time::Instant::now();
+        let elapsed = std::time::Duration::from_secs_f64(position.max(0.) / self.speed);
+        self.start_time = now.checked_sub(elapsed).unwrap_or(now);
+    }
+
+    fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    /// Changes the speed, continuing from the current position.
+    fn set_speed(&mut self, speed: f64) {
+        let position = self.start_time.elapsed().as_secs_f64() * self.speed;
+        self.speed = speed;
+        self.rebase(position);
+    }
+
+    fn pts_to_seconds(&self, pts: i64) -> f64 {
+        pts as f64 * self.time_base_seconds
+    }
+
+    /// The position (in seconds) that is due now.
+    fn position(&self) -> f64 {
+        self.start_time.elapsed().as_secs_f64() * self.speed
     }
 
-    fn convert_pts_to_instant(&self, pts: Option<i64>) -> Option<std::time::Duration> {
+    /// Moves to `position` (in seconds) of a master clock, when the two drifted apart by more
+    /// than [`Self::SYNC_THRESHOLD`]. Smaller differences are left alone, as the master clock
+    /// advances in steps. So are differences of more than [`Self::MAX_SYNC_CORRECTION`]: the
+    /// master clock then stopped, such as audio that ended before the video.
+    fn follow(&mut self, position: f64) {
+        let drift = (position - self.position()).abs();
+        if drift > Self::SYNC_THRESHOLD && drift < Self::MAX_SYNC_CORRECTION {
+            self.rebase(position);
+        }
+    }
+
+    /// Moves the due time of all frames later by `duration`, such as the time spent paused.
+    fn delay(&mut self, duration: std::time::Duration) {
+        self.start_time += duration;
+    }
+
+    /// Returns when the frame with `pts` is due. With a `refresh_interval`, the due time is
+    /// rounded to the nearest multiple of it, so that frames line up with the display's
+    /// refreshes.
+    fn due_time(
+        &self,
+        pts: Option<i64>,
+        refresh_interval: Option<std::time::Duration>,
+    ) -> Option<std::time::Instant> {
         pts.and_then(|pts| {
-            let pts_since_start =
-                std::time::Duration::from_secs_f64(pts as f64 * self.time_base_seconds);
+            let mut seconds_since_start = self.pts_to_seconds(pts).max(0.) / self.speed;
+            if let Some(refresh_interval) = refresh_interval {
+                let interval = refresh_interval.as_secs_f64();
+                seconds_since_start = (seconds_since_start / interval).round() * interval;
+            }
+            let pts_since_start = std::time::Duration::from_secs_f64(seconds_since_start);
             self.start_time.checked_add(pts_since_start)
         })
-        .map(|absolute_pts| absolute_pts.duration_since(std::time::Instant::now()))
+    }
+}
+
+/// The stream's average frame rate, falling back to its base frame rate (`r_frame_rate`) when
+/// the container doesn't know the average.
+fn nominal_frame_rate(
+    stream: &ffmpeg_next::format::stream::Stream,
+) -> Option<ffmpeg_next::Rational> {
+    [stream.avg_frame_rate(), stream.rate()]
+        .into_iter()
+        .find(|frame_rate| frame_rate.numerator() > 0 && frame_rate.denominator() > 0)
+}
+
+/// The nominal frame interval in stream time base units, if the frame rate is known.
+fn frame_interval(stream: &ffmpeg_next::format::stream::Stream) -> Option<f64> {
+    let time_base = stream.time_base();
+    nominal_frame_rate(stream).filter(|_| time_base.numerator() > 0).map(|frame_rate| {
+        (frame_rate.denominator() as f64 * time_base.denominator() as f64)
+            / (frame_rate.numerator() as f64 * time_base.numerator() as f64)
+    })
+}
+
+/// Tracks how long frames take to decode and present relative to the frame interval, and picks
+/// the [`VideoQuality`] to keep up.
+struct QualityController {
+    frame_interval: Option<std::time::Duration>,
+    quality: VideoQuality,
+    /// Exponential moving average of the frame time divided by the frame interval.
+    load: f64,
+    samples: u32,
+}
+
+impl QualityController {
+    /// Number of frames to measure after a change before deciding on the quality again.
+    const SAMPLES_PER_DECISION: u32 = 30;
+
+    fn new(stream: &ffmpeg_next::format::stream::Stream) -> Self {
+        let frame_interval = nominal_frame_rate(stream).map(|frame_rate| {
+            std::time::Duration::from_secs_f64(
+                frame_rate.denominator() as f64 / frame_rate.numerator() as f64,
+            )
+        });
+
+        Self { frame_interval, quality: VideoQuality::Full, load: 0., samples: 0 }
+    }
+
+    fn quality(&self) -> VideoQuality {
+        self.quality
+    }
+
+    fn record(&mut self, settings: Option<AdaptiveQuality>, frame_time: std::time::Duration) {
+        let (Some(settings), Some(frame_interval)) = (settings, self.frame_interval) else {
+            self.quality = VideoQuality::Full;
+            self.samples = 0;
+            return;
+        };
+
+        let load = frame_time.as_secs_f64() / frame_interval.as_secs_f64();
+        self.load = if self.samples == 0 { load } else { self.load + (load - self.load) * 0.1 };
+        self.samples += 1;
+
+        if self.samples < Self::SAMPLES_PER_DECISION {
+            return;
+        }
+
+        let quality = if self.load > settings.degrade_above {
+            self.quality.lower()
+        } else if self.load < settings.restore_below {
+            self.quality.higher()
+        } else {
+            self.quality
+        };
+
+        if quality != self.quality {
+            self.quality = quality;
+            self.samples = 0;
+        }
+    }
+}
+
+/// Fits presentation timestamps onto a grid spaced by the stream's nominal frame interval.
+struct FrameGrid {
+    /// The nominal frame interval in stream time base units, if the frame rate is known.
+    interval: Option<f64>,
+    last_pts: Option<f64>,
+}
+
+impl FrameGrid {
+    /// Fraction of the deviation from the grid that is applied per frame, so that the grid
+    /// slowly follows a frame rate that differs from the nominal one.
+    const DRIFT_CORRECTION: f64 = 0.05;
+
+    fn new(stream: &ffmpeg_next::format::stream::Stream) -> Self {
+        Self { interval: frame_interval(stream), last_pts: None }
+    }
+
+    fn reset(&mut self) {
+        self.last_pts = None;
+    }
+
+    fn snap(&mut self, pts: i64) -> i64 {
+        let pts = pts as f64;
+
+        let snapped = match (self.interval, self.last_pts) {
+            (Some(interval), Some(last_pts)) => {
+                let expected = last_pts + interval;
+                let deviation = pts - expected;
+                if deviation.abs() < interval / 2. {
+                    expected + deviation * Self::DRIFT_CORRECTION
+                } else {
+                    // Not a jittery neighbour of the previous frame (a gap or a jump), so
+                    // start a new grid here.
+                    pts
+                }
+            }
+            _ => pts,
+        };
+
+        self.last_pts = Some(snapped);
+        snapped.round() as i64
+    }
+}
+
+/// The hardware decoding API used on this platform, if any.
+const HARDWARE_DEVICE_TYPE: Option<ffmpeg_next::ffi::AVHWDeviceType> = if cfg!(target_os = "linux")
+{
+    Some(ffmpeg_next::ffi::AVHWDeviceType::AV_HWDEVICE_TYPE_VAAPI)
+} else if cfg!(target_os = "windows") {
+    Some(ffmpeg_next::ffi::AVHWDeviceType::AV_HWDEVICE_TYPE_D3D11VA)
+} else if cfg!(target_os = "macos") {
+    Some(ffmpeg_next::ffi::AVHWDeviceType::AV_HWDEVICE_TYPE_VIDEOTOOLBOX)
+} else {
+    None
+};
+
+/// The filter graph description for the deinterlacing filter, the user's video filter and the
+/// `eq` filter of the picture adjustment, in this order, or `None` when none of them changes the
+/// frames.
+fn video_filter_spec(
+    deinterlace: Option<&str>,
+    user_filter: Option<&str>,
+    adjustment: PictureAdjustment,
+) -> Option<String> {
+    let mut filters = Vec::new();
+    if let Some(deinterlace) = deinterlace {
+        filters.push(deinterlace.to_string());
+    }
+    if let Some(user_filter) = user_filter.map(str::trim).filter(|filter| !filter.is_empty()) {
+        filters.push(user_filter.to_string());
+    }
+    if !adjustment.is_neutral() {
+        filters.push(format!(
+            "eq=brightness={}:contrast={}:saturation={}",
+            adjustment.brightness, adjustment.contrast, adjustment.saturation
+        ));
+    }
+    (!filters.is_empty()).then(|| filters.join(","))
+}
+
+/// Checks that `spec` is a valid filter graph description for video, like `hqdn3d,unsharp`,
+/// without any frames to filter.
+pub fn check_video_filter(spec: &str) -> Result<(), ffmpeg_next::Error> {
+    create_filter_graph(
+        ffmpeg_next::format::Pixel::YUV420P,
+        (64, 64),
+        ffmpeg_next::Rational::new(1, 1),
+        ffmpeg_next::Rational::new(1, 25),
+        spec,
+    )?;
+    Ok(())
+}
+
+/// Creates a filter graph that applies `spec` to frames of the given format, size and sample
+/// aspect ratio, with timestamps in `time_base`.
+pub(super) fn create_filter_graph(
+    format: ffmpeg_next::format::Pixel,
+    (width, height): (u32, u32),
+    aspect_ratio: ffmpeg_next::Rational,
+    time_base: ffmpeg_next::Rational,
+    spec: &str,
+) -> Result<ffmpeg_next::filter::Graph, ffmpeg_next::Error> {
+    let mut graph = ffmpeg_next::filter::Graph::new();
+    let args = format!(
+        "video_size={}x{}:pix_fmt={}:time_base={}/{}:pixel_aspect={}/{}",
+        width,
+        height,
+        ffmpeg_next::ffi::AVPixelFormat::from(format) as i32,
+        time_base.numerator(),
+        time_base.denominator(),
+        aspect_ratio.numerator(),
+        aspect_ratio.denominator().max(1),
+    );
+    graph.add(&ffmpeg_next::filter::find("buffer").unwrap(), "in", &args)?;
+    graph.add(&ffmpeg_next::filter::find("buffersink").unwrap(), "out", "")?;
+    graph.output("in", 0)?.input("out", 0)?.parse(spec)?;
+    graph.validate()?;
+    Ok(graph)
+}
+
+/// A filter graph that applies the user's video filter and the picture adjustment to frames of
+/// the format and size it was created for.
+struct VideoFilter {
+    graph: ffmpeg_next::filter::Graph,
+    spec: String,
+    format: ffmpeg_next::format::Pixel,
+    width: u32,
+    height: u32,
+    time_base: ffmpeg_next::Rational,
+    /// The time base of the filtered frames, which filters like `fps` change.
+    output_time_base: ffmpeg_next::Rational,
+}
+
+impl VideoFilter {
+    fn new(
+        frame: &ffmpeg_next::util::frame::Video,
+        spec: &str,
+        time_base: ffmpeg_next::Rational,
+    ) -> Result<Self, ffmpeg_next::Error> {
+        let graph = create_filter_graph(
+            frame.format(),
+            (frame.width(), frame.height()),
+            frame.aspect_ratio(),
+            time_base,
+            spec,
+        )?;
+        let output_time_base = ffmpeg_next::Rational::from(unsafe {
+            ffmpeg_next::ffi::av_buffersink_get_time_base(graph.get("out").unwrap().as_ptr())
+        });
+
+        Ok(Self {
+            graph,
+            spec: spec.to_string(),
+            format: frame.format(),
+            width: frame.width(),
+            height: frame.height(),
+            time_base,
+            output_time_base,
+        })
+    }
+
+    /// Whether the filter applies `spec` and takes frames like `frame`. Adaptive streams may
+    /// change the frame size in the middle of a stream.
+    fn accepts(&self, frame: &ffmpeg_next::util::frame::Video, spec: &str) -> bool {
+        self.spec == spec
+            && self.format == frame.format()
+            && self.width == frame.width()
+            && self.height == frame.height()
+    }
+
+    /// Feeds `frame` to the filter and returns the frames that came out, with timestamps in
+    /// the stream's time base. Filters like `fps` or `yadif` hold frames back or add frames, so
+    /// there may be none or several.
+    fn apply(
+        &mut self,
+        frame: &ffmpeg_next::util::frame::Video,
+    ) -> Result<Vec<ffmpeg_next::util::frame::Video>, ffmpeg_next::Error> {
+        self.graph.get("in").unwrap().source().add(frame)?;
+        let mut filtered_frames = Vec::new();
+        let mut filtered_frame = ffmpeg_next::util::frame::Video::empty();
+        while self.graph.get("out").unwrap().sink().frame(&mut filtered_frame).is_ok() {
+            if self.output_time_base != self.time_base {
+                let pts = filtered_frame.pts();
+                filtered_frame
+                    .set_pts(pts.map(|pts| pts.rescale(self.output_time_base, self.time_base)));
+            }
+            filtered_frames.push(std::mem::replace(
+                &mut filtered_frame,
+                ffmpeg_next::util::frame::Video::empty(),
+            ));
+        }
+        Ok(filtered_frames)
+    }
+}
+
+/// Opens a decoder for `stream`, decoding on the GPU if `hardware_decoding` is set and the
+/// platform's hardware decoding API is available, and in software otherwise.
+fn open_decoder(
+    stream: &ffmpeg_next::format::stream::Stream,
+    hardware_decoding: bool,
+) -> Result<ffmpeg_next::decoder::Video, anyhow::Error> {
+    if let Some(device_type) = HARDWARE_DEVICE_TYPE.filter(|_| hardware_decoding) {
+        let mut decoder_context =
+            ffmpeg_next::codec::Context::from_parameters(stream.parameters())?;
+        let mut device = std::ptr::null_mut();
+        let result = unsafe {
+            ffmpeg_next::ffi::av_hwdevice_ctx_create(
+                &mut device,
+                device_type,
+                std::ptr::null(),
+                std::ptr::null_mut(),
+                0,
+            )
+        };
+        if result >= 0 {
+            // The decoder takes over the reference to the device, and picks a hardware pixel
+            // format when opened if the codec supports one on this device.
+            unsafe { (*decoder_context.as_mut_ptr()).hw_device_ctx = device };
+            if let Ok(packet_decoder) = decoder_context.decoder().video() {
+                return Ok(packet_decoder);
+            }
+        }
+        // Fall back to software decoding
+    }
+
+    let decoder_context = ffmpeg_next::codec::Context::from_parameters(stream.parameters())?;
+    Ok(decoder_context.decoder().video()?)
+}
+
+/// Copies a frame decoded on the GPU to system memory, where the frame callback can convert
+/// it. Returns `None` for frames that already are in system memory.
+fn download_hardware_frame(
+    frame: &ffmpeg_next::util::frame::Video,
+) -> Result<Option<ffmpeg_next::util::frame::Video>, ffmpeg_next::Error> {
+    if unsafe { (*frame.as_ptr()).hw_frames_ctx.is_null() } {
+        return Ok(None);
+    }
+
+    let mut software_frame = ffmpeg_next::util::frame::Video::empty();
+    unsafe {
+        let result = ffmpeg_next::ffi::av_hwframe_transfer_data(
+            software_frame.as_mut_ptr(),
+            frame.as_ptr(),
+            0,
+        );
+        if result < 0 {
+            return Err(ffmpeg_next::Error::from(result));
+        }
+        // Keep the timestamps, and side data like the display matrix.
+        ffmpeg_next::ffi::av_frame_copy_props(software_frame.as_mut_ptr(), frame.as_ptr());
+    }
+    Ok(Some(software_frame))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 25 fps in a 1/90000 time base, as in MPEG-TS.
+    const INTERVAL: i64 = 3600;
+    /// A millisecond in the same time base.
+    const MILLISECOND: i64 = 90;
+
+    #[test]
+    fn frame_grid_evens_out_jitter() {
+        let mut grid = FrameGrid { interval: Some(INTERVAL as f64), last_pts: None };
+        // Off by up to a millisecond either way, so that neighbours are up to 2 ms apart.
+        let jitter = [MILLISECOND, -MILLISECOND, MILLISECOND / 2, 0, -MILLISECOND, MILLISECOND / 3];
+        let snapped: Vec<i64> = (0..250)
+            .map(|frame| grid.snap(frame * INTERVAL + jitter[frame as usize % jitter.len()]))
+            .collect();
+
+        for pair in snapped.windows(2) {
+            let spacing = pair[1] - pair[0];
+            assert!(
+                (spacing - INTERVAL).abs() <= MILLISECOND / 8,
+                "frames {} and {} are {spacing} apart",
+                pair[0],
+                pair[1]
+            );
+        }
+    }
+
+    /// A controller for 25 fps video that is at `quality`.
+    fn quality_controller(quality: VideoQuality) -> QualityController {
+        QualityController {
+            frame_interval: Some(std::time::Duration::from_millis(40)),
+            quality,
+            load: 0.,
+            samples: 0,
+        }
+    }
+
+    /// Records `frames` frames that each took `milliseconds`, with the default thresholds.
+    fn record(controller: &mut QualityController, frames: u32, milliseconds: u64) {
+        for _ in 0..frames {
+            controller.record(
+                Some(AdaptiveQuality::default()),
+                std::time::Duration::from_millis(milliseconds),
+            );
+        }
+    }
+
+    #[test]
+    fn quality_steps_down_above_the_threshold() {
+        let mut controller = quality_controller(VideoQuality::Full);
+        // 95% of the frame interval, above the 90% to step down at.
+        record(&mut controller, QualityController::SAMPLES_PER_DECISION - 1, 38);
+        assert_eq!(controller.quality(), VideoQuality::Full);
+        record(&mut controller, 1, 38);
+        assert_eq!(controller.quality(), VideoQuality::Fast);
+        record(&mut controller, QualityController::SAMPLES_PER_DECISION, 38);
+        assert_eq!(controller.quality(), VideoQuality::HalfResolution);
+        record(&mut controller, QualityController::SAMPLES_PER_DECISION, 38);
+        assert_eq!(controller.quality(), VideoQuality::HalfResolution);
+    }
+
+    #[test]
+    fn quality_steps_up_below_the_threshold() {
+        let mut controller = quality_controller(VideoQuality::HalfResolution);
+        // 45% of the frame interval, below the 50% to step up at.
+        record(&mut controller, QualityController::SAMPLES_PER_DECISION - 1, 18);
+        assert_eq!(controller.quality(), VideoQuality::HalfResolution);
+        record(&mut controller, 1, 18);
+        assert_eq!(controller.quality(), VideoQuality::Fast);
+        record(&mut controller, QualityController::SAMPLES_PER_DECISION, 18);
+        assert_eq!(controller.quality(), VideoQuality::Full);
+    }
+
+    #[test]
+    fn quality_holds_between_the_thresholds() {
+        let mut controller = quality_controller(VideoQuality::Fast);
+        // 70% of the frame interval.
+        record(&mut controller, 3 * QualityController::SAMPLES_PER_DECISION, 28);
+        assert_eq!(controller.quality(), VideoQuality::Fast);
+    }
+
+    #[test]
+    fn quality_is_full_without_adaptive_quality() {
+        let mut controller = quality_controller(VideoQuality::HalfResolution);
+        controller.record(None, std::time::Duration::from_millis(80));
+        assert_eq!(controller.quality(), VideoQuality::Full);
+    }
+
+    #[test]
+    fn frame_grid_starts_over_after_a_jump() {
+        let mut grid = FrameGrid { interval: Some(INTERVAL as f64), last_pts: None };
+        assert_eq!(grid.snap(0), 0);
+        assert_eq!(grid.snap(INTERVAL), INTERVAL);
+        assert_eq!(grid.snap(100 * INTERVAL + MILLISECOND), 100 * INTERVAL + MILLISECOND);
     }
 }
@@ -0,0 +1,65 @@
+// Copyright © SixtyFPS GmbH <info@slint.dev>
+// SPDX-License-Identifier: MIT
+
+use std::path::{Path, PathBuf};
+
+/// The inputs queued for playback, in order, and which of them is playing.
+#[derive(Clone, Debug)]
+pub struct Playlist {
+    /// Never empty.
+    items: Vec<PathBuf>,
+    current: usize,
+}
+
+impl Playlist {
+    /// A playlist of just `path`.
+    pub fn new(path: PathBuf) -> Self {
+        Self { items: vec![path], current: 0 }
+    }
+
+    /// Queues `path` after the last item.
+    pub fn append(&mut self, path: PathBuf) {
+        self.items.push(path);
+    }
+
+    pub fn current(&self) -> &Path {
+        &self.items[self.current]
+    }
+
+    /// Moves on to the next item, or returns `None` at the end of the playlist.
+    pub fn next(&mut self) -> Option<&Path> {
+        if !self.has_next() {
+            return None;
+        }
+        self.current += 1;
+        Some(self.current())
+    }
+
+    /// Moves back to the previous item, or returns `None` at the start of the playlist.
+    pub fn previous(&mut self) -> Option<&Path> {
+        if !self.has_previous() {
+            return None;
+        }
+        self.current -= 1;
+        Some(self.current())
+    }
+
+    pub fn has_next(&self) -> bool {
+        self.current + 1 < self.items.len()
+    }
+
+    pub fn has_previous(&self) -> bool {
+        self.current > 0
+    }
+
+    /// A description of the current item for display, like `movie.mkv (2/5)`.
+    pub fn describe_current(&self) -> String {
+        let path = self.current();
+        let name = path.file_name().unwrap_or(path.as_os_str()).to_string_lossy();
+        if self.items.len() > 1 {
+            format!("{} ({}/{})", name, self.current + 1, self.items.len())
+        } else {
+            name.into_owned()
+        }
+    }
+}
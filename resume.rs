@@ -0,0 +1,78 @@
+// Copyright © SixtyFPS GmbH <info@slint.dev>
+// SPDX-License-Identifier: MIT
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Where playback of each input stopped, kept in a file so that it can continue there the next
+/// time the input is opened.
+#[derive(Debug, Default)]
+pub struct ResumePositions {
+    /// `None` when there is no config directory to keep the positions in.
+    file: Option<PathBuf>,
+    /// In seconds, by path or URL of the input.
+    positions: HashMap<String, f64>,
+    /// Whether the positions changed since they were last saved.
+    changed: bool,
+}
+
+impl ResumePositions {
+    /// Reads the positions saved in the user's config directory. Starts over without any if
+    /// they can't be read, as they are merely a convenience.
+    pub fn load() -> Self {
+        let file = config_dir().map(|dir| dir.join("resume-positions"));
+        let positions = file
+            .as_ref()
+            .and_then(|file| std::fs::read_to_string(file).ok())
+            .map(|text| parse(&text))
+            .unwrap_or_default();
+        Self { file, positions, changed: false }
+    }
+
+    pub fn get(&self, input: &Path) -> Option<f64> {
+        self.positions.get(&*input.to_string_lossy()).copied()
+    }
+
+    pub fn set(&mut self, input: &Path, position: f64) {
+        self.positions.insert(input.to_string_lossy().into_owned(), position);
+        self.changed = true;
+    }
+
+    /// Forgets the position of `input`, for example because it was played to the end.
+    pub fn remove(&mut self, input: &Path) {
+        self.changed |= self.positions.remove(&*input.to_string_lossy()).is_some();
+    }
+
+    /// Writes the positions to the config directory, if they changed.
+    pub fn save(&mut self) -> Result<(), anyhow::Error> {
+        let Some(file) = self.file.as_ref().filter(|_| self.changed) else { return Ok(()) };
+        if let Some(dir) = file.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        // One input per line, after its position and a tab, which paths rarely contain.
+        let text = self
+            .positions
+            .iter()
+            .map(|(input, position)| format!("{:.3}\t{}\n", position, input))
+            .collect::<String>();
+        std::fs::write(file, text)?;
+        self.changed = false;
+        Ok(())
+    }
+}
+
+fn parse(text: &str) -> HashMap<String, f64> {
+    text.lines()
+        .filter_map(|line| {
+            let (position, input) = line.split_once('\t')?;
+            Some((input.to_owned(), position.parse().ok()?))
+        })
+        .collect()
+}
+
+/// The player's directory in the platform's config directory, or `None` if the platform has
+/// none, like without a home directory.
+pub fn config_dir() -> Option<PathBuf> {
+    directories::ProjectDirs::from("", "", "ffmpeg-video-player")
+        .map(|dirs| dirs.config_dir().to_owned())
+}
@@ -0,0 +1,217 @@
+// Copyright © SixtyFPS GmbH <info@slint.dev>
+// SPDX-License-Identifier: MIT
+
+use std::path::PathBuf;
+
+use crate::resume::config_dir;
+
+/// The choices made in the UI that are kept from one launch to the next, in a file in the user's
+/// config directory.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Settings {
+    /// 1 is the input's own volume.
+    pub volume: f32,
+    /// The name of the audio output device, or `None` for the system's default output.
+    pub audio_device: Option<String>,
+    pub subtitle_delay_ms: i32,
+    /// The playback speed, 1 being normal speed.
+    pub speed: f32,
+    /// Whether jittery frame timestamps are evened out, see [`crate::player::SmoothingMode`].
+    pub smooth_motion: bool,
+    /// Whether the video quality adapts to what the machine keeps up with, see
+    /// [`crate::player::AdaptiveQuality`].
+    pub adaptive_quality: bool,
+    /// Where the window was last, unless it was never saved.
+    pub window: Option<WindowGeometry>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            volume: 1.,
+            audio_device: None,
+            subtitle_delay_ms: 0,
+            speed: 1.,
+            smooth_motion: false,
+            adaptive_quality: false,
+            window: None,
+        }
+    }
+}
+
+/// The position and size of the window, in physical pixels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WindowGeometry {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Settings {
+    /// Reads the settings saved in the user's config directory. Settings that are missing or
+    /// can't be read keep their defaults, as do all of them without a file.
+    pub fn load() -> Self {
+        let text = file().and_then(|file| std::fs::read_to_string(file).ok()).unwrap_or_default();
+        Self::parse(&text)
+    }
+
+    /// Writes the settings to the config directory.
+    pub fn save(&self) -> Result<(), anyhow::Error> {
+        let Some(file) = file() else { return Ok(()) };
+        if let Some(dir) = file.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(file, self.to_toml())?;
+        Ok(())
+    }
+
+    /// Reads settings from TOML text. Values that are missing, of the wrong type or out of range
+    /// keep their defaults, as do all of them if the text isn't TOML.
+    fn parse(text: &str) -> Self {
+        let table = text.parse::<toml::Table>().unwrap_or_default();
+        let mut settings = Self::default();
+        if let Some(volume) = table.get("volume").and_then(|value| parse_number(value, 0.)) {
+            settings.volume = volume;
+        }
+        if let Some(name) = table.get("audio_device").and_then(toml::Value::as_str) {
+            settings.audio_device = Some(name.to_owned()).filter(|name| !name.is_empty());
+        }
+        if let Some(delay) = table
+            .get("subtitle_delay_ms")
+            .and_then(toml::Value::as_integer)
+            .and_then(|delay| i32::try_from(delay).ok())
+        {
+            settings.subtitle_delay_ms = delay;
+        }
+        if let Some(speed) =
+            table.get("speed").and_then(|value| parse_number(value, f32::MIN_POSITIVE))
+        {
+            settings.speed = speed;
+        }
+        if let Some(enabled) = table.get("smooth_motion").and_then(toml::Value::as_bool) {
+            settings.smooth_motion = enabled;
+        }
+        if let Some(enabled) = table.get("adaptive_quality").and_then(toml::Value::as_bool) {
+            settings.adaptive_quality = enabled;
+        }
+        if let Some(window) =
+            table.get("window").and_then(toml::Value::as_str).and_then(parse_window_geometry)
+        {
+            settings.window = Some(window);
+        }
+        settings
+    }
+
+    /// The settings as TOML text, which [`Self::parse`] reads back.
+    fn to_toml(&self) -> String {
+        let mut table = toml::Table::new();
+        table.insert("volume".into(), float_value(self.volume));
+        // TOML has no null, so the default device is left out.
+        if let Some(name) = &self.audio_device {
+            table.insert("audio_device".into(), name.as_str().into());
+        }
+        table.insert("subtitle_delay_ms".into(), self.subtitle_delay_ms.into());
+        table.insert("speed".into(), float_value(self.speed));
+        table.insert("smooth_motion".into(), self.smooth_motion.into());
+        table.insert("adaptive_quality".into(), self.adaptive_quality.into());
+        if let Some(WindowGeometry { x, y, width, height }) = self.window {
+            table.insert("window".into(), format!("{},{},{},{}", x, y, width, height).into());
+        }
+        table.to_string()
+    }
+}
+
+/// `None` when there is no config directory to keep the settings in.
+fn file() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("settings.toml"))
+}
+
+/// Parses a window geometry written as `x,y,width,height`.
+fn parse_window_geometry(text: &str) -> Option<WindowGeometry> {
+    let mut numbers = text.split(',').map(str::trim);
+    let geometry = WindowGeometry {
+        x: numbers.next()?.parse().ok()?,
+        y: numbers.next()?.parse().ok()?,
+        width: numbers.next()?.parse().ok()?,
+        height: numbers.next()?.parse().ok()?,
+    };
+    (numbers.next().is_none() && geometry.width > 0 && geometry.height > 0).then_some(geometry)
+}
+
+/// Reads a finite number that is at least `min`, written with or without a fractional part.
+fn parse_number(value: &toml::Value, min: f32) -> Option<f32> {
+    let number = value.as_float().or_else(|| value.as_integer().map(|number| number as f64))?;
+    Some(number as f32).filter(|number| number.is_finite() && *number >= min)
+}
+
+/// `number` as a TOML float, written as briefly as the `f32` allows, like `0.8` rather than
+/// `0.800000011920929`.
+fn float_value(number: f32) -> toml::Value {
+    toml::Value::Float(number.to_string().parse().unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_are_read_back_as_saved() {
+        let settings = Settings {
+            volume: 0.8,
+            audio_device: Some("Speakers = \"Front\"".into()),
+            subtitle_delay_ms: -250,
+            speed: 1.5,
+            smooth_motion: true,
+            adaptive_quality: true,
+            window: Some(WindowGeometry { x: -10, y: 20, width: 1280, height: 720 }),
+        };
+        let text = settings.to_toml();
+        assert!(text.contains("volume = 0.8\n"));
+        assert_eq!(Settings::parse(&text), settings);
+        assert_eq!(Settings::parse(&Settings::default().to_toml()), Settings::default());
+    }
+
+    #[test]
+    fn a_malformed_file_falls_back_to_the_defaults() {
+        for text in ["", "volume = ", "not toml at all", "volume = 0.5\nvolume = 0.7", "\u{0}"] {
+            assert_eq!(Settings::parse(text), Settings::default(), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn a_partial_file_keeps_the_defaults_of_the_rest() {
+        let settings = Settings::parse("speed = 2\nsmooth_motion = true\nfrom_a_newer_version = 1");
+        assert_eq!(settings, Settings { speed: 2., smooth_motion: true, ..Settings::default() });
+    }
+
+    #[test]
+    fn malformed_values_keep_their_defaults() {
+        let settings = Settings::parse(
+            "volume = -1\n\
+             audio_device = 3\n\
+             subtitle_delay_ms = 99999999999\n\
+             speed = 0\n\
+             smooth_motion = \"yes\"\n\
+             adaptive_quality = 1\n\
+             window = \"10,20,0,600\"",
+        );
+        assert_eq!(settings, Settings::default());
+        assert_eq!(Settings::parse("speed = inf").speed, 1.);
+        assert_eq!(Settings::parse("audio_device = \"\"").audio_device, None);
+    }
+
+    #[test]
+    fn window_geometry_is_four_numbers() {
+        assert_eq!(
+            parse_window_geometry("-10, 20,1280 ,720"),
+            Some(WindowGeometry { x: -10, y: 20, width: 1280, height: 720 })
+        );
+        assert_eq!(parse_window_geometry("10,20,1280"), None);
+        assert_eq!(parse_window_geometry("10,20,1280,720,1"), None);
+        assert_eq!(parse_window_geometry("10,20,-1280,720"), None);
+        assert_eq!(parse_window_geometry("10,20,1280,0"), None);
+        assert_eq!(parse_window_geometry("a,b,c,d"), None);
+        assert_eq!(parse_window_geometry(""), None);
+    }
+}
@@ -0,0 +1,205 @@
+// Copyright © SixtyFPS GmbH <info@slint.dev>
+// SPDX-License-Identifier: MIT
+
+use std::path::Path;
+
+/// Text shown over the video between two positions of the input.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cue {
+    /// In seconds.
+    pub start: f64,
+    /// In seconds, exclusive.
+    pub end: f64,
+    pub text: String,
+}
+
+/// The cues of an external subtitle file.
+#[derive(Clone, Debug, Default)]
+pub struct Subtitles {
+    /// Sorted by start.
+    cues: Vec<Cue>,
+    /// How much later than their timestamps the cues are shown, in seconds.
+    delay: f64,
+}
+
+impl Subtitles {
+    /// Reads a SubRip (`.srt`) file. Files that aren't UTF-8, as older ones often are, have their
+    /// unreadable characters replaced rather than being rejected.
+    pub fn load(path: &Path) -> Result<Self, anyhow::Error> {
+        let data = std::fs::read(path)?;
+        let subtitles = Self::parse_srt(&String::from_utf8_lossy(&data));
+        if subtitles.cues.is_empty() {
+            return Err(anyhow::anyhow!("{} contains no subtitles", path.display()));
+        }
+        Ok(subtitles)
+    }
+
+    /// Parses the cues of SubRip text. Cues that can't be parsed are skipped.
+    pub fn parse_srt(text: &str) -> Self {
+        let text = text.trim_start_matches('\u{feff}').replace("\r\n", "\n");
+
+        let mut cues = Vec::new();
+        for block in text.split("\n\n") {
+            let mut lines = block.lines().skip_while(|line| line.trim().is_empty());
+            let Some(mut line) = lines.next() else { continue };
+            // The counter before the timing is optional in practice.
+            if !line.contains("-->") {
+                let Some(next_line) = lines.next() else { continue };
+                line = next_line;
+            }
+            let Some((start, end)) = parse_timing(line) else { continue };
+            let text = lines.map(strip_tags).collect::<Vec<_>>().join("\n");
+            cues.push(Cue { start, end, text });
+        }
+        cues.sort_by(|a, b| a.start.total_cmp(&b.start));
+
+        Self { cues, delay: 0. }
+    }
+
+    /// Shows the cues `delay` seconds later than their timestamps, or earlier for negative
+    /// values, for files that are out of sync with the video.
+    pub fn set_delay(&mut self, delay: f64) {
+        self.delay = delay;
+    }
+
+    /// The text of the cues shown at `position` (in seconds), one below the other when they
+    /// overlap, or an empty string when there is none.
+    pub fn text_at(&self, position: f64) -> String {
+        let position = position - self.delay;
+        let started = self.cues.partition_point(|cue| cue.start <= position);
+        self.cues[..started]
+            .iter()
+            .filter(|cue| cue.end > position)
+            .map(|cue| cue.text.as_str())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// Parses a timing line like `00:01:02,500 --> 00:01:04,000`, which may be followed by a
+/// position on screen, into the start and end in seconds.
+fn parse_timing(line: &str) -> Option<(f64, f64)> {
+    let (start, end) = line.split_once("-->")?;
+    let end = end.split_whitespace().next()?;
+    Some((parse_timestamp(start.trim())?, parse_timestamp(end)?))
+}
+
+/// Parses `hours:minutes:seconds,milliseconds`. Some files use a period before the
+/// milliseconds.
+fn parse_timestamp(timestamp: &str) -> Option<f64> {
+    let (time, milliseconds) =
+        timestamp.split_once(',').or_else(|| timestamp.split_once('.')).unwrap_or((timestamp, "0"));
+    let mut parts = time.split(':').map(|part| part.trim().parse::<u32>().ok());
+    let (hours, minutes, seconds) = (parts.next()??, parts.next()??, parts.next()??);
+    if parts.next().is_some() {
+        return None;
+    }
+    let milliseconds = milliseconds.trim().parse::<u32>().ok()?;
+    Some(f64::from(hours * 3600 + minutes * 60 + seconds) + f64::from(milliseconds) / 1000.)
+}
+
+/// Removes the formatting tags like `<i>` and `{\an8}` that SubRip files use, as the text is
+/// shown without formatting.
+fn strip_tags(line: &str) -> String {
+    let mut text = String::with_capacity(line.len());
+    let mut closing = None;
+    for c in line.chars() {
+        match (closing, c) {
+            (None, '<') => closing = Some('>'),
+            (None, '{') => closing = Some('}'),
+            (None, c) => text.push(c),
+            (Some(end), c) if c == end => closing = None,
+            (Some(_), _) => {}
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SRT: &str = "\u{feff}1\r\n\
+        00:00:01,000 --> 00:00:02,500\r\n\
+        <i>First</i> cue\r\n\
+        \r\n\
+        2\r\n\
+        00:00:04,000 --> 00:00:06,000\r\n\
+        Second cue\r\n\
+        over two lines\r\n";
+
+    #[test]
+    fn a_bom_and_crlf_line_endings_are_read_like_plain_srt() {
+        let subtitles = Subtitles::parse_srt(SRT);
+        assert_eq!(
+            subtitles.cues,
+            [
+                Cue { start: 1., end: 2.5, text: "First cue".into() },
+                Cue { start: 4., end: 6., text: "Second cue\nover two lines".into() },
+            ]
+        );
+    }
+
+    #[test]
+    fn cues_without_a_counter_are_read() {
+        let subtitles = Subtitles::parse_srt("00:00:01,000 --> 00:00:02,000\nNo counter\n");
+        assert_eq!(subtitles.cues, [Cue { start: 1., end: 2., text: "No counter".into() }]);
+    }
+
+    #[test]
+    fn timestamps_may_have_a_period_before_the_milliseconds() {
+        assert_eq!(parse_timestamp("01:02:03.250"), Some(3723.25));
+        assert_eq!(parse_timestamp("01:02:03,250"), Some(3723.25));
+        assert_eq!(parse_timing("00:00:01.500 --> 00:00:02.000 X1:10 X2:20"), Some((1.5, 2.)));
+        assert_eq!(parse_timestamp("01:02"), None);
+        assert_eq!(parse_timestamp("01:02:03:04,000"), None);
+    }
+
+    #[test]
+    fn formatting_tags_are_removed() {
+        assert_eq!(
+            strip_tags("{\\an8}<b>Bold</b> and <font color=\"red\">red</font>"),
+            "Bold and red"
+        );
+    }
+
+    #[test]
+    fn overlapping_cues_are_shown_one_below_the_other() {
+        let subtitles = Subtitles::parse_srt(
+            "1\n00:00:01,000 --> 00:00:04,000\nSpeaker one\n\n\
+             2\n00:00:02,000 --> 00:00:03,000\nSpeaker two\n",
+        );
+        assert_eq!(subtitles.text_at(1.5), "Speaker one");
+        assert_eq!(subtitles.text_at(2.5), "Speaker one\nSpeaker two");
+        assert_eq!(subtitles.text_at(3.5), "Speaker one");
+    }
+
+    #[test]
+    fn no_text_is_shown_between_cues_and_from_their_end_on() {
+        let subtitles = Subtitles::parse_srt(SRT);
+        assert_eq!(subtitles.text_at(0.5), "");
+        assert_eq!(subtitles.text_at(1.), "First cue");
+        // The end is exclusive.
+        assert_eq!(subtitles.text_at(2.5), "");
+        assert_eq!(subtitles.text_at(3.), "");
+        assert_eq!(subtitles.text_at(5.), "Second cue\nover two lines");
+        assert_eq!(subtitles.text_at(6.), "");
+        assert_eq!(subtitles.text_at(60.), "");
+    }
+
+    #[test]
+    fn a_delay_shows_the_cues_later_or_earlier() {
+        let mut subtitles = Subtitles::parse_srt(SRT);
+        subtitles.set_delay(2.);
+        assert_eq!(subtitles.text_at(1.5), "");
+        assert_eq!(subtitles.text_at(3.), "First cue");
+        assert_eq!(subtitles.text_at(4.5), "");
+        assert_eq!(subtitles.text_at(7.), "Second cue\nover two lines");
+
+        subtitles.set_delay(-1.);
+        assert_eq!(subtitles.text_at(0.), "First cue");
+        assert_eq!(subtitles.text_at(1.5), "");
+        assert_eq!(subtitles.text_at(3.), "Second cue\nover two lines");
+        assert_eq!(subtitles.text_at(5.), "");
+    }
+}
@@ -0,0 +1,82 @@
+// Copyright © SixtyFPS GmbH <info@slint.dev>
+// SPDX-License-Identifier: MIT
+
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+
+use crate::player;
+
+/// How to invoke [`Transcode`].
+pub const USAGE: &str = "Usage: ffmpeg --transcode <input> <output> [--video-codec <encoder>] \
+                         [--audio-codec <encoder>] [--resolution <width>x<height>]";
+
+/// Decodes an input and encodes it again into another file without opening a window, for
+/// scripts that convert media. The output container follows from the extension of the output.
+/// See [`USAGE`] for the arguments.
+#[derive(Debug)]
+pub struct Transcode {
+    input: PathBuf,
+    output: PathBuf,
+    options: player::TranscodeOptions,
+}
+
+impl Transcode {
+    /// Parses the command line arguments after the program name, or returns `Ok(None)` if they
+    /// don't ask for transcoding.
+    pub fn from_args(args: &[String]) -> Result<Option<Self>, anyhow::Error> {
+        if !args.iter().any(|arg| arg == "--transcode") {
+            return Ok(None);
+        }
+
+        let mut paths = Vec::new();
+        let mut options = player::TranscodeOptions::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| anyhow::anyhow!("{} needs a value", arg));
+            match arg.as_str() {
+                "--transcode" => {}
+                "--video-codec" => options.video_codec = Some(value()?.clone()),
+                "--audio-codec" => options.audio_codec = Some(value()?.clone()),
+                "--resolution" => options.resolution = Some(parse_resolution(value()?)?),
+                option if option.starts_with("--") => anyhow::bail!("Unknown option {}", option),
+                path => paths.push(PathBuf::from(path)),
+            }
+        }
+
+        let [input, output] = <[PathBuf; 2]>::try_from(paths)
+            .map_err(|_| anyhow::anyhow!("Give one input and one output"))?;
+        Ok(Some(Self { input, output, options }))
+    }
+
+    /// Transcodes the input, printing how far it got to stdout when the input's duration is
+    /// known. Returns whether the output was written.
+    pub fn run(&self) -> Result<bool, anyhow::Error> {
+        let mut stdout = std::io::stdout();
+        let mut printed = false;
+        let finished = player::transcode(
+            &self.input,
+            &self.output,
+            &self.options,
+            &AtomicBool::new(false),
+            |fraction| {
+                print!("\r{:3.0}%", fraction * 100.);
+                stdout.flush().ok();
+                printed = true;
+            },
+        )?;
+        if printed {
+            println!();
+        }
+        println!("{}", self.output.display());
+        Ok(finished)
+    }
+}
+
+/// Parses a resolution like `1280x720`.
+fn parse_resolution(text: &str) -> Result<(u32, u32), anyhow::Error> {
+    text.split_once('x')
+        .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)))
+        .filter(|&(width, height)| width > 0 && height > 0)
+        .ok_or_else(|| anyhow::anyhow!("{:?} is not a resolution like 1280x720", text))
+}